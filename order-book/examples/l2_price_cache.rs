@@ -0,0 +1,72 @@
+//! Compare L2 generation with and without the formatted price cache on a deep book.
+//!
+//! cargo run --release -p order-book --example l2_price_cache
+use order_book::account::AccountManager;
+use order_book::json::{JsonAccount, JsonOrder, Side};
+use order_book::primitive::Address;
+use order_book::OrderBook;
+use std::time::{Duration, Instant};
+
+const LEVELS: u64 = 200;
+const ORDERS_PER_LEVEL: u64 = 5;
+const ROUNDS: u32 = 20_000;
+
+fn build_book() -> OrderBook {
+    let trader = "0xb794f5ea0ba39494ce839613fffba74279579268"
+        .parse::<Address>()
+        .unwrap();
+    let mut manager = AccountManager::new();
+    manager
+        .add_json_account(
+            "maker",
+            JsonAccount {
+                ddxBalance: "1000000.0".to_string(),
+                usdBalance: "1000000.0".to_string(),
+                traderAddress: trader,
+                balances: Default::default(),
+                funds: Default::default(),
+            },
+        )
+        .unwrap();
+    let mut order_book = OrderBook::new("DDX".to_string());
+    let mut nonce = 0u64;
+    for level in 0..LEVELS {
+        for _ in 0..ORDERS_PER_LEVEL {
+            for (side, price) in [(Side::Bid, 100 + level), (Side::Ask, 400 + level)] {
+                nonce += 1;
+                let order = JsonOrder {
+                    amount: "1.0".to_string(),
+                    nonce: format!("0x{:064x}", nonce),
+                    price: format!("{}.01", price),
+                    side,
+                    traderAddress: trader,
+                    postOnly: false,
+                    reduceOnly: false,
+                    signature: None,
+                    expiresAt: None,
+                    displayAmount: None,
+                };
+                order_book.add_order(&mut manager, order).unwrap();
+            }
+        }
+    }
+    order_book
+}
+
+fn time_l2(order_book: &OrderBook) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        std::hint::black_box(order_book.generate_l2_order_book());
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut order_book = build_book();
+    let uncached = time_l2(&order_book);
+    order_book.config_mut().l2_price_cache = Some(256);
+    let cached = time_l2(&order_book);
+    println!("{} L2 snapshots of a {} level book", ROUNDS, LEVELS);
+    println!("uncached: {:?} ({:?} each)", uncached, uncached / ROUNDS);
+    println!("cached:   {:?} ({:?} each)", cached, cached / ROUNDS);
+}
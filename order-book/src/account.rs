@@ -1,18 +1,43 @@
 //! In-memory account management.
+use crate::config::{AssetSymbols, FeeSchedule};
+use crate::error::{BalanceError, ConversionError, OrderBookError};
 use crate::fill::Fill;
 use crate::json::JsonOrder;
-use crate::json::{JsonAccount, Side};
+use crate::json::{Asset, JsonAccount, JsonFunds, Side};
 use crate::order::Order;
-use crate::primitive::{Address, decimal_to_u256, u256_to_decimal};
+use crate::primitive::{Address, decimal_to_u256, notional, Rounding, u256_to_decimal};
 use crate::FillResult;
 use ethers::types::U256;
-use std::collections::HashMap;
-use std::ops::Div;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
 // max account balance error.
 pub const ERROR: u16 = 10000;
 
-#[derive(Debug, Clone)]
+/// USD to hold for a bid, rounded up so that the hold always covers the order.
+/// Returns `None` if the notional overflows.
+pub(crate) fn reserved_notional(amount: &U256, price: &U256) -> Option<U256> {
+    notional(amount, price, Rounding::Up)
+}
+
+/// Asset and amount to hold for an order: the rounded up notional in USD for a bid, with the
+/// highest fee it can be charged on top, the amount in DDX for an ask.
+pub(crate) fn required_hold(order: &Order, fees: &FeeSchedule) -> Option<(Asset, U256)> {
+    match order.get_side() {
+        Side::Bid => Some((Asset::Usd, bid_hold(&order.amount, &order.price, fees)?)),
+        Side::Ask => Some((Asset::Ddx, order.amount)),
+    }
+}
+
+/// USD held for a bid of `amount` at `price`, see `required_hold`.
+fn bid_hold(amount: &U256, price: &U256, fees: &FeeSchedule) -> Option<U256> {
+    let notional = reserved_notional(amount, price)?;
+    notional.checked_add(fees.max_fee(notional)?)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     _username: String,
     ddx_balance: U256,
@@ -23,65 +48,294 @@ pub struct Account {
 }
 
 impl Account {
-    pub fn from_json(user: String, json: JsonAccount) -> Self {
-        Self {
+    pub fn from_json(user: String, json: JsonAccount) -> Result<Self, ConversionError> {
+        Ok(Self {
             _username: user,
-            ddx_balance: decimal_to_u256(&json.ddxBalance),
+            ddx_balance: decimal_to_u256(&json.ddxBalance)?,
             ddx_hold: U256::zero(),
-            usd_balance: decimal_to_u256(&json.usdBalance),
+            usd_balance: decimal_to_u256(&json.usdBalance)?,
             usd_hold: U256::zero(),
             trader_address: json.traderAddress.clone(),
-        }
+        })
     }
 
-    pub fn to_json(&self) -> JsonAccount {
-        JsonAccount {
-            ddxBalance: u256_to_decimal(&self.total_ddx()),
-            usdBalance: u256_to_decimal(&self.total_usd()),
+    pub fn to_json(&self, symbols: &AssetSymbols) -> Result<JsonAccount, BalanceError> {
+        let ddx_balance = u256_to_decimal(&self.total_ddx()?);
+        let usd_balance = u256_to_decimal(&self.total_usd()?);
+        let balances = BTreeMap::from([
+            (symbols.base.clone(), ddx_balance.clone()),
+            (symbols.quote.clone(), usd_balance.clone()),
+        ]);
+        Ok(JsonAccount {
+            ddxBalance: ddx_balance,
+            usdBalance: usd_balance,
             traderAddress: self.trader_address.clone(),
-        }
+            balances,
+            funds: JsonFunds {
+                ddxAvailable: u256_to_decimal(&self.ddx_balance),
+                ddxHold: u256_to_decimal(&self.ddx_hold),
+                usdAvailable: u256_to_decimal(&self.usd_balance),
+                usdHold: u256_to_decimal(&self.usd_hold),
+            },
+        })
     }
 
-    pub fn update(&mut self, side: Side, fill: &Fill) {
-        let unit_scale = U256::from(1e18 as u64);
+    /// Settle one leg of a fill, leaving the account as it was if the fill does not fit its
+    /// balances.
+    pub fn update(&mut self, side: Side, fill: &Fill) -> Result<(), BalanceError> {
         match side {
             Side::Bid => {
                 assert_eq!(
                     self.trader_address, fill.to,
                     "Filled bid order contains mismatched data!"
                 );
-                self.ddx_balance += fill.fill_amount;
-                self.usd_hold -= fill.fill_amount.saturating_mul(fill.price).div(unit_scale);
             }
             Side::Ask => {
                 assert_eq!(
                     self.trader_address, fill.from,
                     "Filled ask order contains mismatched data!"
                 );
-                self.ddx_hold -= fill.fill_amount;
-                self.usd_balance += fill.fill_amount.saturating_mul(fill.price).div(unit_scale);
             }
         }
+        self.settle(side, fill)
+    }
+
+    /// Charge the fee of one leg of a fill. A buyer pays it from the fee held with the bid,
+    /// a seller from the proceeds.
+    fn charge_fee(&mut self, side: Side, fee: U256) -> Result<(), BalanceError> {
+        let funds = match side {
+            Side::Bid => &mut self.usd_hold,
+            Side::Ask => &mut self.usd_balance,
+        };
+        *funds = funds
+            .checked_sub(fee)
+            .ok_or(BalanceError::Underflow(Asset::Usd))?;
+        Ok(())
+    }
+
+    /// Whether an order can only reduce this trader's position, the DDX balance. Balances cannot
+    /// go short, so that is an ask for no more than the available DDX, the rest being held for
+    /// other asks already.
+    fn reduces_position(&self, order: &Order) -> bool {
+        match order.get_side() {
+            Side::Ask => order.amount <= U256::from(ERROR) + self.ddx_balance,
+            Side::Bid => false,
+        }
+    }
+
+    /// Move the funds backing an order from the available to the held balance.
+    fn hold(&mut self, order: &Order, fees: &FeeSchedule) -> Option<()> {
+        let (balance, hold, diff) = match required_hold(order, fees)? {
+            (Asset::Usd, diff) => (&mut self.usd_balance, &mut self.usd_hold, diff),
+            (Asset::Ddx, diff) => (&mut self.ddx_balance, &mut self.ddx_hold, diff),
+        };
+        if diff <= U256::from(ERROR) + *balance {
+            *balance -= diff;
+            *hold += diff;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Move the funds held for what is left of a cancelled order back to the balance.
+    fn release(&mut self, cancelled_order: &Order, fees: &FeeSchedule) {
+        match cancelled_order.get_side() {
+            Side::Bid => {
+                // the hold of what is left of the order, as rounded up when it was placed.
+                let diff = required_hold(cancelled_order, fees).map_or(U256::MAX, |(_, diff)| diff);
+                assert!(
+                    diff <= U256::from(ERROR) + self.usd_hold,
+                    "User account pending USD balance mismatch!"
+                );
+                self.usd_balance += diff;
+                self.usd_hold -= diff;
+            }
+            Side::Ask => {
+                assert!(
+                    cancelled_order.amount <= U256::from(ERROR) + self.ddx_hold,
+                    "User account pending DDX balance mismatch!"
+                );
+                self.ddx_balance += cancelled_order.amount;
+                self.ddx_hold -= cancelled_order.amount;
+            }
+        }
+    }
+
+    /// Move `amount` of held USD back to the balance.
+    fn release_usd(&mut self, amount: U256) -> Result<(), BalanceError> {
+        let usd_hold = self
+            .usd_hold
+            .checked_sub(amount)
+            .ok_or(BalanceError::Underflow(Asset::Usd))?;
+        let usd_balance = self
+            .usd_balance
+            .checked_add(amount)
+            .ok_or(BalanceError::Overflow(Asset::Usd))?;
+        self.usd_hold = usd_hold;
+        self.usd_balance = usd_balance;
+        Ok(())
+    }
+
+    /// Settle one leg of a fill against this account's pending funds.
+    fn settle(&mut self, side: Side, fill: &Fill) -> Result<(), BalanceError> {
+        let notional = notional(&fill.fill_amount, &fill.price, Rounding::Down)
+            .ok_or(BalanceError::Overflow(Asset::Usd))?;
+        match side {
+            Side::Bid => {
+                let ddx_balance = self
+                    .ddx_balance
+                    .checked_add(fill.fill_amount)
+                    .ok_or(BalanceError::Overflow(Asset::Ddx))?;
+                let usd_hold = self
+                    .usd_hold
+                    .checked_sub(notional)
+                    .ok_or(BalanceError::Underflow(Asset::Usd))?;
+                self.ddx_balance = ddx_balance;
+                self.usd_hold = usd_hold;
+            }
+            Side::Ask => {
+                let ddx_hold = self
+                    .ddx_hold
+                    .checked_sub(fill.fill_amount)
+                    .ok_or(BalanceError::Underflow(Asset::Ddx))?;
+                let usd_balance = self
+                    .usd_balance
+                    .checked_add(notional)
+                    .ok_or(BalanceError::Overflow(Asset::Usd))?;
+                self.ddx_hold = ddx_hold;
+                self.usd_balance = usd_balance;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn total_ddx(&self) -> Result<U256, BalanceError> {
+        self.ddx_balance
+            .checked_add(self.ddx_hold)
+            .ok_or(BalanceError::Overflow(Asset::Ddx))
+    }
+
+    pub fn total_usd(&self) -> Result<U256, BalanceError> {
+        self.usd_balance
+            .checked_add(self.usd_hold)
+            .ok_or(BalanceError::Overflow(Asset::Usd))
+    }
+
+    pub(crate) fn ddx_hold(&self) -> U256 {
+        self.ddx_hold
     }
 
-    pub fn total_ddx(&self) -> U256 {
-        self.ddx_balance + self.ddx_hold
+    pub(crate) fn usd_hold(&self) -> U256 {
+        self.usd_hold
     }
+}
+
+/// How to settle fills and cancels of orders whose trader account has been deleted.
+#[derive(Debug, Clone, Default)]
+pub enum OrphanPolicy {
+    /// Drop the orphaned leg.
+    #[default]
+    Skip,
+    /// Route the orphaned leg to a clearing account, which takes over the pending funds of
+    /// deleted accounts so that total funds are conserved.
+    Clearing(Address),
+}
+
+/// Operator check of a new order against its trader's account, returning why it is rejected.
+pub type OrderCheck = Arc<dyn Fn(&JsonOrder, &Account) -> Result<(), String> + Send + Sync>;
 
-    pub fn total_usd(&self) -> U256 {
-        self.usd_balance + self.usd_hold
+#[derive(Clone, Default)]
+struct OrderChecks(Vec<OrderCheck>);
+
+impl fmt::Debug for OrderChecks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} order checks", self.0.len())
     }
 }
 
-#[derive(Debug)]
+/// Accounts and the nonces they used. Operator settings are left out of snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountManager {
-    accounts: HashMap<Address, Account>,
+    pub(crate) accounts: HashMap<Address, Account>,
+    #[serde(skip)]
+    orphan_policy: OrphanPolicy,
+    #[serde(skip)]
+    symbols: AssetSymbols,
+    #[serde(skip)]
+    order_checks: OrderChecks,
+    // Nonces of each trader's accepted orders, which cannot be submitted again.
+    pub(crate) used_nonces: HashMap<Address, HashSet<U256>>,
+    #[serde(skip)]
+    fees: FeeSchedule,
+    // USD fees collected from settled fills.
+    pub(crate) fee_pool: U256,
 }
 
 impl AccountManager {
     pub fn new() -> Self {
+        Self::with_fees(FeeSchedule::default())
+    }
+
+    /// Manager charging `fees` on settled fills.
+    pub fn with_fees(fees: FeeSchedule) -> Self {
         AccountManager {
             accounts: HashMap::new(),
+            orphan_policy: OrphanPolicy::Skip,
+            symbols: AssetSymbols::default(),
+            order_checks: OrderChecks::default(),
+            used_nonces: HashMap::new(),
+            fees,
+            fee_pool: U256::zero(),
+        }
+    }
+
+    /// Number of accounts.
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        self.fees
+    }
+
+    /// USD fees collected so far.
+    pub fn fee_pool(&self) -> U256 {
+        self.fee_pool
+    }
+
+    /// Set the asset symbols labelling account balances.
+    pub fn set_asset_symbols(&mut self, symbols: AssetSymbols) {
+        self.symbols = symbols;
+    }
+
+    /// Set the orphaned fill policy, a missing clearing account is created with zero balances.
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        if let OrphanPolicy::Clearing(address) = policy {
+            if !self.accounts.contains_key(&address) {
+                self.new_account("clearing", address);
+            }
+        }
+        self.orphan_policy = policy;
+    }
+
+    /// The account settling on behalf of a trader, which is the clearing account if the
+    /// trader's account has been deleted under the clearing policy.
+    fn settling_account(&mut self, address: &Address) -> Option<&mut Account> {
+        let address = self.settling_address(address)?;
+        self.accounts.get_mut(&address)
+    }
+
+    /// Address of the account settling on behalf of a trader, see `settling_account`.
+    fn settling_address(&self, address: &Address) -> Option<Address> {
+        if self.accounts.contains_key(address) {
+            return Some(*address);
+        }
+        match self.orphan_policy {
+            OrphanPolicy::Skip => None,
+            OrphanPolicy::Clearing(clearing) => {
+                Some(clearing).filter(|clearing| self.accounts.contains_key(clearing))
+            }
         }
     }
     pub fn new_account(&mut self, user: &str, address: Address) {
@@ -96,103 +350,380 @@ impl AccountManager {
         self.accounts.insert(address, account);
     }
 
-    pub fn add_json_account(&mut self, user: &str, json: JsonAccount) {
+    pub fn add_json_account(
+        &mut self,
+        user: &str,
+        json: JsonAccount,
+    ) -> Result<(), ConversionError> {
         let address = json.traderAddress.clone();
-        let account = Account::from_json(user.to_string(), json);
+        let account = Account::from_json(user.to_string(), json)?;
         self.accounts.insert(address, account);
+        Ok(())
     }
 
-    pub fn delete_account(&mut self, address: &Address) -> Option<JsonAccount> {
-        if let Some(account) = self.accounts.remove(address) {
-            Some(account.to_json())
-        } else {
-            None
+    /// Remove an account. Nothing is removed if the clearing account cannot take over its
+    /// holds.
+    pub fn delete_account(&mut self, address: &Address) -> Result<JsonAccount, OrderBookError> {
+        let account = self
+            .accounts
+            .get(address)
+            .ok_or(OrderBookError::NoSuchAccount)?;
+        let json = account.to_json(&self.symbols)?;
+        // pending funds still back resting orders, so the clearing account takes them over.
+        let mut clearing_holds = None;
+        if let OrphanPolicy::Clearing(clearing) = self.orphan_policy {
+            if let Some(clearing_account) = self.accounts.get(&clearing) {
+                if clearing != *address {
+                    let ddx_hold = clearing_account
+                        .ddx_hold
+                        .checked_add(account.ddx_hold)
+                        .ok_or(BalanceError::Overflow(Asset::Ddx))?;
+                    let usd_hold = clearing_account
+                        .usd_hold
+                        .checked_add(account.usd_hold)
+                        .ok_or(BalanceError::Overflow(Asset::Usd))?;
+                    clearing_holds = Some((clearing, ddx_hold, usd_hold));
+                }
+            }
+        }
+        self.accounts.remove(address);
+        if let Some((clearing, ddx_hold, usd_hold)) = clearing_holds {
+            let clearing_account = self.accounts.get_mut(&clearing).unwrap();
+            clearing_account.ddx_hold = ddx_hold;
+            clearing_account.usd_hold = usd_hold;
         }
+        Ok(json)
     }
 
-    pub fn get_json_account(&self, address: &Address) -> Option<JsonAccount> {
-        if let Some(account) = self.accounts.get(address) {
-            Some(account.to_json())
+    /// Move available funds between two accounts. Held funds backing resting orders cannot be
+    /// transferred. Nothing moves if either account is missing or the available balance is
+    /// not enough.
+    pub fn transfer(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        asset: Asset,
+        amount: U256,
+    ) -> Result<(), OrderBookError> {
+        let balance = |account: &Account| match asset {
+            Asset::Ddx => account.ddx_balance,
+            Asset::Usd => account.usd_balance,
+        };
+        let receiver = self
+            .accounts
+            .get(to)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        let sender = self
+            .accounts
+            .get(from)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        let sender_balance = balance(sender)
+            .checked_sub(amount)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        let receiver_balance = if from == to {
+            sender_balance
         } else {
-            None
+            balance(receiver)
+        }
+        .checked_add(amount)
+        .ok_or(BalanceError::Overflow(asset))?;
+        for (address, new_balance) in [(from, sender_balance), (to, receiver_balance)] {
+            let account = self.accounts.get_mut(address).unwrap();
+            match asset {
+                Asset::Ddx => account.ddx_balance = new_balance,
+                Asset::Usd => account.usd_balance = new_balance,
+            }
         }
+        Ok(())
     }
 
-    /// Generate a validate order from available account balance.
-    pub fn validate_order(&mut self, order: JsonOrder) -> Option<Order> {
-        if let Some(account) = self.accounts.get_mut(&order.traderAddress) {
-            let unit_scale = U256::from(1e18 as u64);
-            let encoded_order = order.encode_order();
-            match order.side {
-                Side::Bid => {
-                    let diff = encoded_order
-                        .amount
-                        .saturating_mul(encoded_order.price)
-                        .div(unit_scale);
-                    if diff <= U256::from(ERROR) + account.usd_balance {
-                        account.usd_balance -= diff;
-                        account.usd_hold += diff;
-                    } else {
-                        return None;
-                    }
-                }
-                Side::Ask => {
-                    if encoded_order.amount <= U256::from(ERROR) + account.ddx_balance {
-                        account.ddx_balance -= encoded_order.amount;
-                        account.ddx_hold += encoded_order.amount;
-                    } else {
-                        return None;
-                    }
-                }
+    /// Credit funds to an existing account.
+    pub fn deposit(
+        &mut self,
+        address: &Address,
+        asset: Asset,
+        amount: U256,
+    ) -> Result<(), OrderBookError> {
+        let account = self
+            .accounts
+            .get_mut(address)
+            .ok_or(OrderBookError::NoSuchAccount)?;
+        let balance = match asset {
+            Asset::Ddx => &mut account.ddx_balance,
+            Asset::Usd => &mut account.usd_balance,
+        };
+        *balance = balance
+            .checked_add(amount)
+            .ok_or(BalanceError::Overflow(asset))?;
+        Ok(())
+    }
+
+    /// Debit available funds from an account. Held funds backing resting orders cannot be
+    /// withdrawn, and nothing is debited if the available balance is not enough.
+    pub fn withdraw(
+        &mut self,
+        address: &Address,
+        asset: Asset,
+        amount: U256,
+    ) -> Result<(), OrderBookError> {
+        let account = self
+            .accounts
+            .get_mut(address)
+            .ok_or(OrderBookError::NoSuchAccount)?;
+        let balance = match asset {
+            Asset::Ddx => &mut account.ddx_balance,
+            Asset::Usd => &mut account.usd_balance,
+        };
+        *balance = balance
+            .checked_sub(amount)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        Ok(())
+    }
+
+    /// Accounts whose total balance of `asset`, held funds included, exceeds `threshold`,
+    /// largest first.
+    pub fn accounts_above(
+        &self,
+        asset: Asset,
+        threshold: U256,
+    ) -> Result<Vec<JsonAccount>, BalanceError> {
+        let total = |account: &Account| match asset {
+            Asset::Ddx => account.total_ddx(),
+            Asset::Usd => account.total_usd(),
+        };
+        let mut accounts = Vec::new();
+        for account in self.accounts.values() {
+            let total = total(account)?;
+            if total > threshold {
+                accounts.push((total, account));
             }
-            Some(encoded_order)
-        } else {
-            None
         }
+        accounts.sort_by(|(a_total, a), (b_total, b)| {
+            b_total
+                .cmp(a_total)
+                .then(a.trader_address.cmp(&b.trader_address))
+        });
+        accounts
+            .into_iter()
+            .map(|(_, account)| account.to_json(&self.symbols))
+            .collect()
+    }
+
+    pub fn get_json_account(&self, address: &Address) -> Result<JsonAccount, OrderBookError> {
+        let account = self
+            .accounts
+            .get(address)
+            .ok_or(OrderBookError::NoSuchAccount)?;
+        Ok(account.to_json(&self.symbols)?)
+    }
+
+    /// Register a check run on every new order before its funds are held, e.g. to restrict
+    /// traders. Checks run in registration order and the first error rejects the order.
+    pub fn add_order_check<F>(&mut self, check: F)
+    where
+        F: Fn(&JsonOrder, &Account) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.order_checks.0.push(Arc::new(check));
+    }
+
+    /// Whether a trader's order with the given nonce has already been accepted.
+    pub fn nonce_used(&self, trader: &Address, nonce: &U256) -> bool {
+        self.used_nonces
+            .get(trader)
+            .map_or(false, |nonces| nonces.contains(nonce))
+    }
+
+    /// Nonces of a trader's accepted orders in ascending order.
+    pub fn used_nonces(&self, trader: &Address) -> Vec<U256> {
+        let mut nonces: Vec<U256> = self
+            .used_nonces
+            .get(trader)
+            .map(|nonces| nonces.iter().cloned().collect())
+            .unwrap_or_default();
+        nonces.sort();
+        nonces
+    }
+
+    /// Run the registered checks of a new order against its trader's account.
+    fn check_order(&self, order: &JsonOrder, account: &Account) -> Result<(), OrderBookError> {
+        for check in self.order_checks.0.iter() {
+            check(order, account).map_err(OrderBookError::Rejected)?;
+        }
+        Ok(())
+    }
+
+    /// Accept a new order whose funds are held as `encoded_order`, which may be priced apart
+    /// from the submitted order. Its nonce is used up, so a replay of the order is rejected.
+    pub(crate) fn accept_order(
+        &mut self,
+        order: &JsonOrder,
+        encoded_order: &Order,
+    ) -> Result<(), OrderBookError> {
+        if self.nonce_used(&encoded_order.traderAddress, &encoded_order.nonce) {
+            return Err(OrderBookError::NonceUsed);
+        }
+        let account = self
+            .accounts
+            .get(&order.traderAddress)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        self.check_order(order, account)?;
+        if order.reduceOnly && !account.reduces_position(encoded_order) {
+            return Err(OrderBookError::ReduceOnly);
+        }
+        self.hold_funds(encoded_order)
+            .ok_or(OrderBookError::InsufficientFunds)?;
+        self.used_nonces
+            .entry(encoded_order.traderAddress)
+            .or_default()
+            .insert(encoded_order.nonce);
+        Ok(())
+    }
+
+    /// Check that the trader of `replaced` can place `order` once `replaced` is cancelled and
+    /// its funds are released, without changing any balance.
+    pub(crate) fn check_replacement(
+        &self,
+        replaced: &Order,
+        order: &JsonOrder,
+        encoded_order: &Order,
+    ) -> Result<(), OrderBookError> {
+        if self.nonce_used(&encoded_order.traderAddress, &encoded_order.nonce) {
+            return Err(OrderBookError::NonceUsed);
+        }
+        let mut account = self
+            .accounts
+            .get(&order.traderAddress)
+            .ok_or(OrderBookError::InsufficientFunds)?
+            .clone();
+        account.release(replaced, &self.fees);
+        self.check_order(order, &account)?;
+        if order.reduceOnly && !account.reduces_position(encoded_order) {
+            return Err(OrderBookError::ReduceOnly);
+        }
+        account
+            .hold(encoded_order, &self.fees)
+            .ok_or(OrderBookError::InsufficientFunds)
+    }
+
+    /// Generate a validate order from available account balance.
+    pub fn validate_order(&mut self, order: JsonOrder) -> Result<Order, OrderBookError> {
+        let encoded_order = order.encode_order()?;
+        self.accept_order(&order, &encoded_order)?;
+        Ok(encoded_order)
+    }
+
+    /// Move the funds backing an order from the available to the held balance.
+    pub(crate) fn hold_funds(&mut self, order: &Order) -> Option<()> {
+        let fees = self.fees;
+        self.accounts
+            .get_mut(&order.traderAddress)?
+            .hold(order, &fees)
     }
 
     /// Revert pending balance from canceled order and make it available to new orders.
     pub fn release_pending_fund(&mut self, cancelled_order: &Order) -> Option<Account> {
-        if let Some(account) = self.accounts.get_mut(&cancelled_order.traderAddress) {
-            let unit_scale = U256::from(1e18 as u64);
-            match cancelled_order.get_side() {
-                Side::Bid => {
-                    let diff = cancelled_order
-                        .amount
-                        .saturating_mul(cancelled_order.price)
-                        .div(unit_scale);
-                    assert!(
-                        diff <= U256::from(ERROR) + account.usd_hold,
-                        "User account pending USD balance mismatch!"
-                    );
-                    account.usd_balance += diff;
-                    account.usd_hold -= diff;
-                }
-                Side::Ask => {
-                    assert!(
-                        cancelled_order.amount <= U256::from(ERROR) + account.ddx_hold,
-                        "User account pending DDX balance mismatch!"
-                    );
-                    account.ddx_balance += cancelled_order.amount;
-                    account.ddx_hold -= cancelled_order.amount;
-                }
-            }
-            Some(account.clone())
+        let fees = self.fees;
+        let account = self.settling_account(&cancelled_order.traderAddress)?;
+        account.release(cancelled_order, &fees);
+        Some(account.clone())
+    }
+
+    /// Release the funds held for `order` beyond what it holds once reduced to `new_amount`, i.e.
+    /// its hold less the hold of the reduced order, each rounded up as when it was placed.
+    pub(crate) fn release_reduced(&mut self, order: &Order, new_amount: U256) -> Option<Account> {
+        let fees = self.fees;
+        let account = self.settling_account(&order.traderAddress)?;
+        account.release(order, &fees);
+        let reduced = Order {
+            amount: new_amount,
+            ..*order
+        };
+        account
+            .hold(&reduced, &fees)
+            .expect("a reduced order holds no more than the order did");
+        Some(account.clone())
+    }
+
+    /// Settle the fills and release the funds of the cancelled orders. A fill that does not fit
+    /// the balances of one of its accounts is skipped whole, leaving both accounts as they were,
+    /// and the others are still settled, returning the first such error.
+    pub fn update_accounts(&mut self, fill_result: FillResult) -> Result<(), BalanceError> {
+        let mut result = Ok(());
+        for fill in fill_result.filled_orders {
+            result = result.and(self.settle_fill(&fill));
+        }
+        for order in fill_result.cancelled_orders {
+            self.release_pending_fund(&order);
+        }
+        result
+    }
+
+    /// Settle both legs of a fill and charge their fees, or nothing if either leg does not fit.
+    /// The legs are settled on copies of the accounts, which replace them once both fit.
+    fn settle_fill(&mut self, fill: &Fill) -> Result<(), BalanceError> {
+        let ask_address = self.settling_address(&fill.from);
+        let bid_address = self.settling_address(&fill.to);
+        let mut charged = U256::zero();
+        let mut ask = ask_address.map(|address| self.accounts[&address].clone());
+        if let Some(account) = ask.as_mut() {
+            let fee = self.fee(Side::Ask, fill);
+            account.settle(Side::Ask, fill)?;
+            account.charge_fee(Side::Ask, fee)?;
+            charged += fee;
+        }
+        // both legs of a self trade settle on the same account.
+        let mut bid = if bid_address == ask_address {
+            ask.take()
         } else {
-            None
+            bid_address.map(|address| self.accounts[&address].clone())
+        };
+        if let Some(account) = bid.as_mut() {
+            let fee = self.fee(Side::Bid, fill);
+            account.settle(Side::Bid, fill)?;
+            account.charge_fee(Side::Bid, fee)?;
+            // what the fill leaves of its part of the hold goes back to the balance.
+            let spent = notional(&fill.fill_amount, &fill.price, Rounding::Down)
+                .and_then(|notional| notional.checked_add(fee))
+                .ok_or(BalanceError::Overflow(Asset::Usd))?;
+            account.release_usd(self.bid_hold_taken(fill)?.saturating_sub(spent))?;
+            charged += fee;
         }
+        for account in ask.into_iter().chain(bid) {
+            self.accounts.insert(account.trader_address, account);
+        }
+        self.fee_pool += charged;
+        Ok(())
     }
 
-    pub fn update_accounts(&mut self, fill_result: FillResult) {
-        for fill in fill_result.filled_orders {
-            if self.accounts.contains_key(&fill.from) {
-                let account = self.accounts.get_mut(&fill.from).unwrap();
-                account.update(Side::Ask, &fill);
-            }
-            if self.accounts.contains_key(&fill.to) {
-                let account = self.accounts.get_mut(&fill.to).unwrap();
-                account.update(Side::Bid, &fill);
-            }
+    /// Part of the bid order's hold taken by a fill: the hold of its open amount less the hold
+    /// of what the fill leaves open, all of it once the order is filled. The parts of the fills
+    /// of an order add up to its hold, so none of it is left once the order leaves the book.
+    fn bid_hold_taken(&self, fill: &Fill) -> Result<U256, BalanceError> {
+        let hold = |amount: &U256| {
+            bid_hold(amount, &fill.bid_price, &self.fees).ok_or(BalanceError::Overflow(Asset::Usd))
+        };
+        let open = fill.bid_amount.saturating_sub(fill.fill_amount);
+        if open <= U256::from(ERROR) {
+            hold(&fill.bid_amount)
+        } else {
+            Ok(hold(&fill.bid_amount)?.saturating_sub(hold(&open)?))
         }
     }
+
+    /// Fee of the `side` leg of a fill, at the taker or maker rate.
+    fn fee(&self, side: Side, fill: &Fill) -> U256 {
+        let is_taker = matches!(
+            (&side, &fill.taker_side),
+            (Side::Bid, Side::Bid) | (Side::Ask, Side::Ask)
+        );
+        let fee_bps = if is_taker {
+            self.fees.taker_bps
+        } else {
+            self.fees.maker_bps
+        };
+        notional(&fill.fill_amount, &fill.price, Rounding::Down)
+            .unwrap_or(U256::MAX)
+            .saturating_mul(U256::from(fee_bps))
+            / U256::from(10_000u32)
+    }
 }
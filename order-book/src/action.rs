@@ -0,0 +1,166 @@
+//! Log of the actions accepted by the book, replayed to rebuild the book and the accounts.
+use crate::account::AccountManager;
+use crate::error::OrderBookError;
+use crate::fill::FillResult;
+use crate::json::{JsonCancel, JsonCancelAll, JsonOrder};
+use crate::peg::Peg;
+use crate::primitive::{Address, Decimal, Hash};
+use crate::reservation::ReservationToken;
+use crate::OrderBook;
+use serde::{Deserialize, Serialize};
+
+/// Change of the book requested by a trader. Applying the accepted actions of a session in
+/// order, to the book and the accounts it started from, rebuilds their state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Action {
+    /// Submit an order.
+    Add(JsonOrder),
+    /// Submit an all-or-none order.
+    AddAllOrNone(JsonOrder),
+    /// Submit a fill-or-kill order.
+    AddFillOrKill(JsonOrder),
+    /// Submit a market order.
+    AddMarket(JsonOrder),
+    /// Rest an order priced by a peg.
+    AddPegged { order: JsonOrder, peg: Peg },
+    /// Cancel a resting order without a signature.
+    Cancel(Hash),
+    /// Cancel a resting order with a cancel signed by its trader.
+    CancelSigned(JsonCancel),
+    /// Cancel the oldest `count` resting orders of a trader.
+    CancelOldest { trader: Address, count: usize },
+    /// Cancel every resting order of a trader without a signature.
+    CancelAll(Address),
+    /// Cancel every resting order of a trader with a cancel signed by the trader.
+    CancelAllSigned(JsonCancelAll),
+    /// Cancel a resting order and submit another one of the same trader in its place.
+    Replace { old_hash: Hash, order: JsonOrder },
+    /// Lower the amount of a resting order.
+    Reduce { order_hash: Hash, amount: Decimal },
+    /// Cancel the orders expired by `now`, in unix seconds.
+    PruneExpired { now: u64 },
+    /// Hold the funds of an order without matching it.
+    Reserve(JsonOrder),
+    /// Match a reserved order, or drop it if the book moved against it.
+    Commit(ReservationToken),
+    /// Drop a reserved order.
+    Abort(ReservationToken),
+}
+
+/// What an applied action did.
+#[derive(Debug)]
+pub enum ActionOutcome {
+    /// Fills of the submitted order, settled by the caller with
+    /// `AccountManager::update_accounts`.
+    Order(FillResult),
+    /// The cancelled order, whose funds are already released.
+    Cancelled(JsonOrder),
+    /// The cancelled orders, oldest first, whose funds are already released.
+    CancelledOrders(Vec<JsonOrder>),
+    /// The reduced order.
+    Reduced(JsonOrder),
+    /// Hash of the resting pegged order.
+    Pegged(Hash),
+    /// Token to commit or abort the reserved order with.
+    Reserved(ReservationToken),
+}
+
+impl OrderBook {
+    /// Apply an action, appending it to the action log once accepted if the market records
+    /// actions. Live submissions and `replay` both go through here.
+    pub fn apply(
+        &mut self,
+        manager: &mut AccountManager,
+        action: Action,
+    ) -> Result<ActionOutcome, OrderBookError> {
+        let outcome = match &action {
+            Action::Add(order) => ActionOutcome::Order(self.add_order(manager, order.clone())?),
+            Action::AddAllOrNone(order) => {
+                ActionOutcome::Order(self.add_order_all_or_none(manager, order.clone())?)
+            }
+            Action::AddFillOrKill(order) => {
+                ActionOutcome::Order(self.add_order_fill_or_kill(manager, order.clone())?)
+            }
+            Action::AddMarket(order) => {
+                ActionOutcome::Order(self.add_market_order(manager, order.clone())?)
+            }
+            Action::AddPegged { order, peg } => {
+                ActionOutcome::Pegged(self.add_pegged_order(manager, order.clone(), peg.clone())?)
+            }
+            Action::Cancel(order_id) => {
+                ActionOutcome::Cancelled(self.cancel_order(manager, order_id.clone())?)
+            }
+            Action::CancelSigned(cancel) => {
+                ActionOutcome::Cancelled(self.cancel_signed_order(manager, cancel)?)
+            }
+            Action::CancelOldest { trader, count } => {
+                ActionOutcome::CancelledOrders(self.cancel_oldest(manager, trader, *count)?)
+            }
+            Action::CancelAll(trader) => {
+                ActionOutcome::CancelledOrders(self.cancel_all(manager, trader)?)
+            }
+            Action::CancelAllSigned(cancel) => {
+                ActionOutcome::CancelledOrders(self.cancel_all_signed(manager, cancel)?)
+            }
+            Action::Replace { old_hash, order } => ActionOutcome::Order(self.replace_order(
+                manager,
+                old_hash.clone(),
+                order.clone(),
+            )?),
+            Action::Reduce { order_hash, amount } => {
+                ActionOutcome::Reduced(self.reduce_order(manager, order_hash.clone(), amount)?)
+            }
+            Action::PruneExpired { now } => {
+                ActionOutcome::CancelledOrders(self.prune_expired(manager, *now)?)
+            }
+            Action::Reserve(order) => ActionOutcome::Reserved(
+                self.reserve(manager, order.clone())
+                    .ok_or(OrderBookError::InvalidOrder)?,
+            ),
+            Action::Commit(token) => {
+                // a commit finding the book moved against the order drops it, which is logged
+                // as well.
+                let (_, order, _) = self
+                    .reservations
+                    .get(token)
+                    .ok_or(OrderBookError::NoSuchOrder)?;
+                let order = order.clone();
+                match self.commit(manager, *token) {
+                    Some(fill_result) => ActionOutcome::Order(fill_result),
+                    None => ActionOutcome::Cancelled(order),
+                }
+            }
+            Action::Abort(token) => ActionOutcome::Cancelled(
+                self.abort(manager, *token)
+                    .ok_or(OrderBookError::NoSuchOrder)?,
+            ),
+        };
+        if self.config.record_actions {
+            self.actions.push(action);
+        }
+        Ok(outcome)
+    }
+
+    /// Take the recorded actions out of the log, oldest first, e.g. to append them to durable
+    /// storage next to the latest snapshot.
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Apply `actions` in order and settle their fills, as the service does with live
+    /// submissions. Stops at the first rejected action, which an action log never has when it
+    /// is replayed from the state it started from. Time dependent rules, i.e. expiries, the
+    /// protected open and the age based trade retention, see the time of the replay.
+    pub fn replay(
+        &mut self,
+        manager: &mut AccountManager,
+        actions: &[Action],
+    ) -> Result<(), OrderBookError> {
+        for action in actions {
+            if let ActionOutcome::Order(fill_result) = self.apply(manager, action.clone())? {
+                manager.update_accounts(fill_result)?;
+            }
+        }
+        Ok(())
+    }
+}
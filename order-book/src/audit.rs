@@ -0,0 +1,34 @@
+//! Matching fairness audit: which resting orders were eligible for each fill and why the
+//! filled one was chosen.
+use crate::primitive::{Decimal, Hash};
+use crate::OrderBook;
+use serde::{Deserialize, Serialize};
+
+/// Why a resting order was chosen among the candidates at its price level.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriorityBasis {
+    /// Price-time priority, at the given position of the level queue.
+    Fifo { position: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Hash of the incoming order.
+    pub incoming_hash: Hash,
+    /// Price of the touched level.
+    pub price: Decimal,
+    /// Resting orders of the level still open when the fill happened, in queue order.
+    pub queue: Vec<Hash>,
+    /// Hash of the filled resting order.
+    pub selected_hash: Hash,
+    pub basis: PriorityBasis,
+    // unix timestamp in milliseconds.
+    pub timestamp: u64,
+}
+
+impl OrderBook {
+    /// Audit records of all fills since the audit mode was enabled, in time order.
+    pub fn audit_log(&self) -> &[AuditRecord] {
+        &self.audit_log
+    }
+}
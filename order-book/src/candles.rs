@@ -0,0 +1,104 @@
+//! OHLCV candles aggregated from fills.
+use crate::fill::Fill;
+use crate::json::JsonCandle;
+use crate::primitive::u256_to_decimal;
+use ethers::types::U256;
+use std::collections::BTreeMap;
+
+/// Prices and traded amount of the fills in one interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candle {
+    /// Unix milliseconds the interval starts at, a multiple of the interval length.
+    pub(crate) start: u64,
+    pub(crate) open: U256,
+    pub(crate) high: U256,
+    pub(crate) low: U256,
+    pub(crate) close: U256,
+    /// Traded amount of DDX.
+    pub(crate) volume: U256,
+}
+
+impl Candle {
+    pub fn to_json(&self) -> JsonCandle {
+        JsonCandle {
+            start: self.start,
+            open: u256_to_decimal(&self.open),
+            high: u256_to_decimal(&self.high),
+            low: u256_to_decimal(&self.low),
+            close: u256_to_decimal(&self.close),
+            volume: u256_to_decimal(&self.volume),
+        }
+    }
+}
+
+/// Buckets fills into fixed intervals of unix time. Intervals without fills have no candle.
+#[derive(Debug, Clone)]
+pub struct CandleAggregator {
+    interval: u64,
+    candles: BTreeMap<u64, Candle>,
+}
+
+impl CandleAggregator {
+    /// Aggregator for intervals of `interval` milliseconds, which must not be zero.
+    pub fn new(interval: u64) -> Self {
+        assert!(interval > 0, "candle interval must not be zero");
+        CandleAggregator {
+            interval,
+            candles: BTreeMap::new(),
+        }
+    }
+
+    /// Add a fill to the candle of its interval. Fills of an interval are expected in the order
+    /// they executed, which sets its open and close.
+    pub fn push(&mut self, fill: &Fill) {
+        let start = fill.timestamp - fill.timestamp % self.interval;
+        self.candles
+            .entry(start)
+            .and_modify(|candle| {
+                candle.high = candle.high.max(fill.price);
+                candle.low = candle.low.min(fill.price);
+                candle.close = fill.price;
+                candle.volume += fill.fill_amount;
+            })
+            .or_insert(Candle {
+                start,
+                open: fill.price,
+                high: fill.price,
+                low: fill.price,
+                close: fill.price,
+                volume: fill.fill_amount,
+            });
+    }
+
+    /// Candles of the intervals with fills, oldest first.
+    pub fn candles(&self) -> Vec<Candle> {
+        self.candles.values().cloned().collect()
+    }
+}
+
+impl<'a> Extend<&'a Fill> for CandleAggregator {
+    fn extend<I: IntoIterator<Item = &'a Fill>>(&mut self, fills: I) {
+        for fill in fills {
+            self.push(fill);
+        }
+    }
+}
+
+/// Parse an interval such as "30s", "1m", "4h" or "1d" into milliseconds.
+pub fn parse_interval(interval: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid candle interval {:?}", interval);
+    let unit = match interval.chars().last() {
+        Some('s') => 1_000,
+        Some('m') => 60_000,
+        Some('h') => 3_600_000,
+        Some('d') => 86_400_000,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = interval[..interval.len() - 1]
+        .parse()
+        .map_err(|_| invalid())?;
+    match count.checked_mul(unit) {
+        Some(millis) if millis > 0 => Ok(millis),
+        _ => Err(invalid()),
+    }
+}
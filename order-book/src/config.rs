@@ -0,0 +1,182 @@
+//! Market configuration for the limit order book.
+use crate::order::default_domain;
+use ethers::types::transaction::eip712::EIP712Domain;
+use ethers::types::U256;
+
+/// What to do with a resting order left below the minimum order amount by a partial fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MakerDustPolicy {
+    /// Keep the remainder resting.
+    Leave,
+    /// Cancel the remainder and release its held funds. Over-filling the incoming order
+    /// instead is not supported, since its held funds only cover the amount it asked for.
+    Cancel,
+}
+
+/// What to do with an order whose hash is already resting in the book, i.e. a retried or
+/// replayed submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateOrderPolicy {
+    /// Reject the order.
+    Reject,
+    /// Accept the order without effect, leaving the resting one untouched.
+    Idempotent,
+}
+
+/// What to do with a market order when the opposite side has no liquidity from other traders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneSidedMarketPolicy {
+    /// Reject the order with `InsufficientLiquidity`, without using its nonce.
+    Reject,
+    /// Accept the order and cancel it whole, so that it is reported with no fills and its held
+    /// funds are released when the accounts are updated.
+    Cancel,
+}
+
+/// What to do with an order whose price is not a multiple of the tick size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffTickPolicy {
+    /// Reject the order.
+    Reject,
+    /// Move the price to the grid away from the opposite side, i.e. down for a bid and up for
+    /// an ask. Signed orders are rejected instead, since their signature covers the price.
+    Snap,
+}
+
+/// Which fills the trade log keeps, older ones are dropped as new fills come in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeRetention {
+    /// Keep every fill.
+    Unbounded,
+    /// Keep the latest fills up to the given count.
+    MaxEntries(usize),
+    /// Keep fills at most the given milliseconds older than the latest fill.
+    MaxAgeMillis(u64),
+}
+
+/// Symbols of the traded assets, used to label account balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetSymbols {
+    /// Symbol of the asset held in `ddxBalance`.
+    pub base: String,
+    /// Symbol of the asset held in `usdBalance`.
+    pub quote: String,
+}
+
+impl Default for AssetSymbols {
+    fn default() -> Self {
+        AssetSymbols {
+            base: "DDX".to_string(),
+            quote: "USD".to_string(),
+        }
+    }
+}
+
+/// Trading fees in basis points of a fill's USD notional, charged to the maker and the taker
+/// of each fill when accounts are settled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub maker_bps: u32,
+    pub taker_bps: u32,
+}
+
+impl FeeSchedule {
+    /// Fee of a fill of `notional` USD at the higher of the two rates, rounded up, so that it
+    /// covers the fee of either role. Returns `None` if it overflows.
+    pub fn max_fee(&self, notional: U256) -> Option<U256> {
+        let bps = U256::from(self.maker_bps.max(self.taker_bps));
+        let (fee, rem) = notional.checked_mul(bps)?.div_mod(U256::from(10_000u32));
+        if rem.is_zero() {
+            Some(fee)
+        } else {
+            fee.checked_add(U256::one())
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MarketConfig {
+    /// Minimum price increment of the market. Prices of new orders must be a multiple of it,
+    /// unless it is zero, the default.
+    pub tick_size: U256,
+    /// Treatment of a new order priced off the tick grid.
+    pub off_tick_policy: OffTickPolicy,
+    /// Max distance in basis points of a new passive order's price from the touch of its own
+    /// side, or of the opposite side if its own side is empty. Disabled if `None`.
+    pub maker_price_band_bps: Option<u32>,
+    /// Minimum order amount of the market.
+    pub min_amount: U256,
+    /// Minimum notional, i.e. amount times price, of a new order.
+    pub min_notional: U256,
+    /// Treatment of resting orders left below `min_amount` by a partial fill.
+    pub maker_dust_policy: MakerDustPolicy,
+    /// Decimals of the amount and price of orders as signed on chain. Order hashes are computed
+    /// with this precision, while the engine itself keeps 18 decimals.
+    pub decimals: u32,
+    /// Treatment of an order whose hash is already resting.
+    pub duplicate_order_policy: DuplicateOrderPolicy,
+    /// Treatment of a market order into an empty opposite side.
+    pub one_sided_market_policy: OneSidedMarketPolicy,
+    /// Record the candidates and the priority basis of every fill in the audit log.
+    pub fairness_audit: bool,
+    /// Decimal separator accepted in the amount and price of submitted orders.
+    pub decimal_separator: char,
+    /// Retention of the trade log, the latest 10,000 fills by default.
+    pub trade_retention: TradeRetention,
+    /// Max fills of a single submission. Matching stops at the cap, and the remainder rests if
+    /// it no longer crosses the book or is cancelled otherwise. Unlimited if `None`.
+    pub max_fills_per_submission: Option<usize>,
+    /// Reject an order that would rest at the best opposite price, i.e. lock the book, because
+    /// it cannot fill completely against the other traders' orders at that price.
+    pub reject_locking_orders: bool,
+    /// Price bucket width of the L2 view, coarser than `tick_size`. Price levels are listed
+    /// one by one if `None`.
+    pub l2_display_tick: Option<U256>,
+    /// Capacity of the cache of formatted L2 prices. Prices are formatted on every request if
+    /// `None`.
+    pub l2_price_cache: Option<usize>,
+    /// Keep orders that leave the book, filled or cancelled, so that `get_order` still resolves
+    /// the hashes referenced by fills. The archive grows with every removed order.
+    pub archive_removed_orders: bool,
+    /// Milliseconds after the market opens, i.e. after the book is created or leaves read-only
+    /// mode, during which orders that would match are rejected and only passive ones rest.
+    /// Disabled if `None`.
+    pub protected_open_millis: Option<u64>,
+    /// Reject orders and cancels without a signature. Signed orders are verified either way.
+    pub require_signatures: bool,
+    /// EIP-712 domain orders and cancels are hashed and signed in. Setting a chain id or a
+    /// verifying contract separates the signatures of one deployment from another's.
+    pub domain: EIP712Domain,
+    /// Log the actions applied through `OrderBook::apply`, to be taken with `take_actions`.
+    /// The log grows with every accepted action until it is taken.
+    pub record_actions: bool,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig {
+            // 0.01 in 18 decimals.
+            tick_size: U256::zero(),
+            off_tick_policy: OffTickPolicy::Reject,
+            maker_price_band_bps: None,
+            min_amount: U256::zero(),
+            min_notional: U256::zero(),
+            maker_dust_policy: MakerDustPolicy::Leave,
+            decimals: 18,
+            duplicate_order_policy: DuplicateOrderPolicy::Reject,
+            one_sided_market_policy: OneSidedMarketPolicy::Reject,
+            fairness_audit: false,
+            decimal_separator: '.',
+            trade_retention: TradeRetention::MaxEntries(10_000),
+            max_fills_per_submission: None,
+            reject_locking_orders: false,
+            l2_display_tick: None,
+            l2_price_cache: None,
+            archive_removed_orders: false,
+            require_signatures: false,
+            protected_open_millis: None,
+            domain: default_domain(),
+            record_actions: false,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Errors returned by the order book.
+use crate::json::Asset;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderBookError {
+    /// The engine only applies replicated events and rejects local submissions.
+    ReadOnly,
+    /// The order does not pass the market checks, e.g. the maker price band.
+    InvalidOrder,
+    /// The trader has no account or not enough available funds for the order.
+    InsufficientFunds,
+    /// No resting order has the given hash.
+    NoSuchOrder,
+    /// No account has the given trader address.
+    NoSuchAccount,
+    /// An order with the same hash is already resting.
+    DuplicateOrder,
+    /// Not enough resting liquidity to fill an all-or-none order.
+    InsufficientLiquidity,
+    /// The order cannot be EIP-712 encoded, e.g. because of a malformed nonce.
+    Eip712Encoding(String),
+    /// A decimal field is malformed or ambiguous.
+    InvalidDecimal(String),
+    /// The trader has already submitted an order with the same nonce.
+    NonceUsed,
+    /// The order signature is missing, malformed or not made by the order's trader.
+    InvalidSignature(String),
+    /// A post-only order would match on arrival.
+    WouldCross,
+    /// An order check registered by the operator rejected the order, for the given reason.
+    Rejected(String),
+    /// The order would match during the protected period after the market opened.
+    ProtectedOpen,
+    /// A reduce-only order would increase the trader's position.
+    ReduceOnly,
+    /// A snapshot cannot be restored, e.g. because it is malformed.
+    InvalidSnapshot(String),
+    /// A fill or a deposit does not fit an account's balance.
+    Balance(BalanceError),
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderBookError::ReadOnly => write!(f, "Order book is read-only"),
+            OrderBookError::InvalidOrder => write!(f, "Invalid order"),
+            OrderBookError::InsufficientFunds => {
+                write!(f, "Account not found or balance is not enough")
+            }
+            OrderBookError::NoSuchOrder => write!(f, "No such order id"),
+            OrderBookError::NoSuchAccount => write!(f, "No such account"),
+            OrderBookError::DuplicateOrder => write!(f, "Order is already resting"),
+            OrderBookError::InsufficientLiquidity => write!(f, "Not enough liquidity at price"),
+            OrderBookError::Eip712Encoding(reason) => {
+                write!(f, "Failed to EIP-712 encode order: {}", reason)
+            }
+            OrderBookError::InvalidDecimal(reason) => write!(f, "Invalid decimal {}", reason),
+            OrderBookError::NonceUsed => write!(f, "Order nonce is already used"),
+            OrderBookError::InvalidSignature(reason) => write!(f, "Invalid signature: {}", reason),
+            OrderBookError::WouldCross => write!(f, "Post-only order would match"),
+            OrderBookError::Rejected(reason) => write!(f, "Order rejected: {}", reason),
+            OrderBookError::ProtectedOpen => {
+                write!(f, "Only passive orders are accepted right after the open")
+            }
+            OrderBookError::ReduceOnly => {
+                write!(f, "Reduce-only order would increase the position")
+            }
+            OrderBookError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {}", reason),
+            OrderBookError::Balance(e) => write!(f, "Failed to update balance: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OrderBookError {}
+
+/// Failure to convert a decimal string to an integer amount, holding the string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// Not a decimal number.
+    Unparseable(String),
+    /// Below zero.
+    Negative(String),
+    /// Beyond the largest representable amount.
+    Overflow(String),
+    /// Finer than the smallest unit of its scale.
+    TooPrecise(String),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConversionError::Unparseable(from) => write!(f, "{:?}: not a number", from),
+            ConversionError::Negative(from) => write!(f, "{:?}: negative", from),
+            ConversionError::Overflow(from) => write!(f, "{:?}: too large", from),
+            ConversionError::TooPrecise(from) => write!(f, "{:?}: too many decimals", from),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl From<ConversionError> for OrderBookError {
+    fn from(e: ConversionError) -> Self {
+        OrderBookError::InvalidDecimal(e.to_string())
+    }
+}
+
+/// Failure to settle a fill against an account or to credit it, holding the asset whose balance
+/// it would break. The account is left as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceError {
+    /// The fill takes more than the account holds.
+    Underflow(Asset),
+    /// The fill or deposit credits beyond the largest representable amount.
+    Overflow(Asset),
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::Underflow(asset) => write!(f, "{:?} hold is less than the fill", asset),
+            BalanceError::Overflow(asset) => write!(f, "{:?} balance overflows", asset),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+impl From<BalanceError> for OrderBookError {
+    fn from(e: BalanceError) -> Self {
+        OrderBookError::Balance(e)
+    }
+}
@@ -1,21 +1,58 @@
 //! Fill results for the limit order match engine.
-use crate::json::{JsonFill, Side};
-use crate::primitive::{Address, Hash, OrderStatus, u256_to_decimal};
+use crate::error::BalanceError;
+use crate::json::{Asset, JsonFill, Side};
+use crate::order::Order;
+use crate::primitive::{
+    Address, Decimal, Hash, notional, OrderStatus, Rounding, Scale, u256_to_decimal,
+};
 use ethers::types::U256;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fill {
     pub(crate) from: Address,
     pub(crate) to: Address,
+    // hash of the resting order.
     pub(crate) maker_hash: Hash,
+    // hash of the incoming order.
     pub(crate) taker_hash: Hash,
+    pub(crate) taker_side: Side,
     pub(crate) fill_amount: U256,
     pub(crate) price: U256,
+    // limit price of the bid order and its open amount before the fill, which tell the part of
+    // its hold the fill takes.
+    pub(crate) bid_price: U256,
+    pub(crate) bid_amount: U256,
+    // mid price of the book when the incoming order arrived, if both sides had orders.
+    pub(crate) mid: Option<U256>,
+    // sequence numbers of the resting and the incoming order, see `OrderBook::sequence`.
+    pub(crate) maker_seq: u64,
+    pub(crate) taker_seq: u64,
+    // unix timestamp in milliseconds.
+    pub(crate) timestamp: u64,
+}
+
+impl Fill {
+    pub fn to_json(&self) -> JsonFill {
+        JsonFill {
+            maker_hash: self.maker_hash.clone(),
+            taker_hash: self.taker_hash.clone(),
+            fill_amount: u256_to_decimal(&self.fill_amount),
+            price: u256_to_decimal(&self.price),
+            maker_seq: self.maker_seq,
+            taker_seq: self.taker_seq,
+            timestamp: self.timestamp,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct FillResult {
     pub filled_orders: Vec<Fill>,
+    // resting orders cancelled while matching, whose funds are released on settlement.
+    pub cancelled_orders: Vec<Order>,
+    // asset and amount held for the submitted order, if any.
+    pub reserved: Option<(Asset, U256)>,
     pub remaining: U256,
     pub status: OrderStatus,
     pub side: Side,
@@ -25,22 +62,54 @@ impl FillResult {
     pub fn new(remaining: U256, side: Side) -> Self {
         FillResult {
             filled_orders: Vec::new(),
+            cancelled_orders: Vec::new(),
+            reserved: None,
             remaining,
             status: OrderStatus::Created,
             side,
         }
     }
     pub fn generate_filled_orders(&self) -> Vec<JsonFill> {
-        let mut filled_orders = Vec::new();
-        for fill in &self.filled_orders {
-            let json_fill = JsonFill {
-                maker_hash: fill.maker_hash.clone(),
-                taker_hash: fill.taker_hash.clone(),
-                fill_amount: u256_to_decimal(&fill.fill_amount),
-                price: u256_to_decimal(&fill.price),
-            };
-            filled_orders.push(json_fill);
+        self.filled_orders.iter().map(Fill::to_json).collect()
+    }
+
+    /// Total amount traded by the submitted order.
+    pub fn total_filled(&self) -> Decimal {
+        u256_to_decimal(&self.filled_amount())
+    }
+
+    /// Total quote value of the fills, each rounded down to the smallest unit as settled.
+    pub fn total_notional(&self) -> Result<Decimal, BalanceError> {
+        Ok(u256_to_decimal(&self.notional()?))
+    }
+
+    /// Volume weighted average price of the fills, zero without any fill.
+    pub fn average_price(&self) -> Result<Decimal, BalanceError> {
+        let filled = self.filled_amount();
+        if filled.is_zero() {
+            return Ok(u256_to_decimal(&filled));
         }
-        filled_orders
+        let price_volume = self
+            .notional()?
+            .checked_mul(Scale::ENGINE.unit())
+            .ok_or(BalanceError::Overflow(Asset::Usd))?;
+        Ok(u256_to_decimal(&(price_volume / filled)))
+    }
+
+    fn filled_amount(&self) -> U256 {
+        self.filled_orders
+            .iter()
+            .fold(U256::zero(), |filled, fill| filled + fill.fill_amount)
+    }
+
+    // sum of the notional settled for each fill.
+    fn notional(&self) -> Result<U256, BalanceError> {
+        self.filled_orders
+            .iter()
+            .try_fold(U256::zero(), |total, fill| {
+                notional(&fill.fill_amount, &fill.price, Rounding::Down)
+                    .and_then(|notional| total.checked_add(notional))
+                    .ok_or(BalanceError::Overflow(Asset::Usd))
+            })
     }
 }
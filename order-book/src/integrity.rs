@@ -0,0 +1,158 @@
+//! Consistency checks of the book's indices and of the funds held for resting orders.
+use crate::account::{AccountManager, ERROR, required_hold};
+use crate::json::{Asset, Side};
+use crate::primitive::{Address, Decimal, Hash, u256_to_decimal};
+use crate::OrderBook;
+use ethers::types::U256;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// An inconsistency found by `OrderBook::check_integrity`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "violation", rename_all = "camelCase")]
+pub enum IntegrityViolation {
+    /// An `order_loc` entry that does not resolve to an order at its price level.
+    #[serde(rename_all = "camelCase")]
+    DanglingLocation { order_hash: Hash },
+    /// A resting order without a matching `order_loc` entry.
+    #[serde(rename_all = "camelCase")]
+    UnindexedOrder { order_hash: Hash },
+    /// A `price_map` entry pointing outside of the price levels, or at a level holding orders
+    /// of another price.
+    #[serde(rename_all = "camelCase")]
+    InvalidPriceLevel { side: Side, price: Decimal },
+    /// The best bid is at or above the best ask.
+    #[serde(rename_all = "camelCase")]
+    CrossedBook {
+        best_bid: Decimal,
+        best_ask: Decimal,
+    },
+    /// The per-trader index disagrees with the orders resting in the book.
+    #[serde(rename_all = "camelCase")]
+    TraderIndexMismatch { trader: Address },
+    /// Held funds of an account differ from what its resting and reserved orders require.
+    #[serde(rename_all = "camelCase")]
+    HoldMismatch {
+        trader: Address,
+        ddx_hold: Decimal,
+        ddx_required: Decimal,
+        usd_hold: Decimal,
+        usd_required: Decimal,
+    },
+}
+
+impl OrderBook {
+    /// Run consistency checks of the book and the account holds, returning every violation
+    /// found. A healthy book reports none.
+    pub fn check_integrity(&self, manager: &AccountManager) -> Vec<IntegrityViolation> {
+        let mut violations = Vec::new();
+        // resting orders of each trader and the best prices, from the price levels themselves.
+        let mut resting: HashMap<Address, Vec<Hash>> = HashMap::new();
+        let mut best = [None, None];
+        for (i, book) in [&self.bid_book, &self.ask_book].into_iter().enumerate() {
+            for (price, loc) in book.price_map.iter() {
+                let level = match book.price_levels.get(*loc) {
+                    Some(level) if level.values().all(|o| o.price == *price) => level,
+                    _ => {
+                        violations.push(IntegrityViolation::InvalidPriceLevel {
+                            side: book.side.clone(),
+                            price: u256_to_decimal(price),
+                        });
+                        continue;
+                    }
+                };
+                // prices ascend, so the last bid and the first ask level are the best.
+                if !level.is_empty() && (matches!(book.side, Side::Bid) || best[i].is_none()) {
+                    best[i] = Some(*price);
+                }
+                for (order_id, order) in level {
+                    match self.order_loc.get(order_id) {
+                        Some((_, l)) if l == loc => {}
+                        _ => violations.push(IntegrityViolation::UnindexedOrder {
+                            order_hash: order_id.clone(),
+                        }),
+                    }
+                    resting
+                        .entry(order.traderAddress)
+                        .or_default()
+                        .push(order_id.clone());
+                }
+            }
+        }
+        for order_id in self.order_loc.keys() {
+            if self.get_resting(order_id).is_none() {
+                violations.push(IntegrityViolation::DanglingLocation {
+                    order_hash: order_id.clone(),
+                });
+            }
+        }
+        if let [Some(best_bid), Some(best_ask)] = best {
+            if best_bid >= best_ask {
+                violations.push(IntegrityViolation::CrossedBook {
+                    best_bid: u256_to_decimal(&best_bid),
+                    best_ask: u256_to_decimal(&best_ask),
+                });
+            }
+        }
+        let mut traders: Vec<&Address> = resting.keys().chain(self.trader_orders.keys()).collect();
+        traders.sort();
+        traders.dedup();
+        for trader in traders {
+            let indexed = self.trader_orders.get(trader);
+            let in_book = resting.get(trader);
+            let consistent = match (indexed, in_book) {
+                (Some(indexed), Some(in_book)) => {
+                    indexed.len() == in_book.len() && in_book.iter().all(|id| indexed.contains(id))
+                }
+                _ => false,
+            };
+            if !consistent {
+                violations.push(IntegrityViolation::TraderIndexMismatch { trader: *trader });
+            }
+        }
+        self.check_holds(manager, &mut violations);
+        violations
+    }
+
+    /// Compare the held funds of each account with the funds its resting and reserved orders
+    /// require, allowing for rounding of each order.
+    fn check_holds(&self, manager: &AccountManager, violations: &mut Vec<IntegrityViolation>) {
+        let mut required: HashMap<Address, (U256, U256, usize)> = HashMap::new();
+        let resting_orders = self
+            .order_loc
+            .keys()
+            .filter_map(|order_id| self.get_resting(order_id));
+        let reserved_orders = self.reservations.values().map(|(_, _, order)| order);
+        for order in resting_orders.chain(reserved_orders) {
+            let entry = required.entry(order.traderAddress).or_default();
+            match required_hold(order, &manager.fee_schedule()) {
+                Some((Asset::Usd, hold)) => entry.1 = entry.1.saturating_add(hold),
+                Some((Asset::Ddx, hold)) => entry.0 = entry.0.saturating_add(hold),
+                // a hold too large to represent cannot match any account.
+                None => entry.1 = U256::MAX,
+            }
+            entry.2 += 1;
+        }
+        let mut accounts: Vec<(&Address, U256, U256)> = manager
+            .accounts
+            .iter()
+            .map(|(address, account)| (address, account.ddx_hold(), account.usd_hold()))
+            .collect();
+        accounts.sort_by_key(|(address, _, _)| **address);
+        for (trader, ddx_hold, usd_hold) in accounts {
+            let (ddx_required, usd_required, count) =
+                required.get(trader).cloned().unwrap_or_default();
+            let tolerance = U256::from(ERROR) * U256::from(count + 1);
+            let off = |held: U256, needed: U256| held.max(needed) - held.min(needed) > tolerance;
+            if off(ddx_hold, ddx_required) || off(usd_hold, usd_required) {
+                violations.push(IntegrityViolation::HoldMismatch {
+                    trader: *trader,
+                    ddx_hold: u256_to_decimal(&ddx_hold),
+                    ddx_required: u256_to_decimal(&ddx_required),
+                    usd_hold: u256_to_decimal(&usd_hold),
+                    usd_required: u256_to_decimal(&usd_required),
+                });
+            }
+        }
+    }
+}
@@ -1,20 +1,132 @@
 //! Basic data structures for JSON serialization.
-use crate::primitive::{Address, Decimal, Hash};
-use serde::{Deserialize, Serialize};
+use crate::primitive::{Address, Decimal, Hash, OrderStatus, u256_to_decimal};
+use ethers::types::U256;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Side {
     Bid = 0,
     Ask = 1,
 }
 
+impl FromStr for Side {
+    type Err = String;
+
+    /// Case-insensitive "buy", "bid" or "0" for bids, "sell", "ask" or "1" for asks.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "buy" | "bid" | "0" => Ok(Side::Bid),
+            "sell" | "ask" | "1" => Ok(Side::Ask),
+            _ => Err(format!("unknown side {:?}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Side {
+    /// Either a string accepted by `Side::from_str` or the number 0 or 1.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SideVisitor)
+    }
+}
+
+struct SideVisitor;
+
+impl<'de> Visitor<'de> for SideVisitor {
+    type Value = Side;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"buy\", \"bid\", \"sell\", \"ask\", 0 or 1")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Side, E> {
+        Side::from_str(value).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Side, E> {
+        match value {
+            0 => Ok(Side::Bid),
+            1 => Ok(Side::Ask),
+            _ => Err(E::invalid_value(de::Unexpected::Unsigned(value), &self)),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Side, E> {
+        match u64::try_from(value) {
+            Ok(value) => self.visit_u64(value),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Signed(value), &self)),
+        }
+    }
+}
+
+/// Currency a notional figure is expressed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Denomination {
+    /// The traded asset, i.e. DDX.
+    Base,
+    /// The pricing asset, i.e. USD.
+    #[default]
+    Quote,
+}
+
+/// Asset held by accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Asset {
+    #[serde(rename = "DDX")]
+    Ddx,
+    #[serde(rename = "USD")]
+    Usd,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct JsonAccount {
+    /// Base asset balance, kept for clients that predate `balances`.
     pub ddxBalance: Decimal,
+    /// Quote asset balance, kept for clients that predate `balances`.
     pub usdBalance: Decimal,
     pub traderAddress: Address,
+    /// Balances keyed by asset symbol. Ignored when creating an account.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub balances: BTreeMap<String, Decimal>,
+    /// Split of the balances into available and held funds. Ignored when creating an account.
+    #[serde(flatten)]
+    pub funds: JsonFunds,
+}
+
+/// Funds of an account free to trade or withdraw, and held for its resting orders.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(non_snake_case)]
+pub struct JsonFunds {
+    pub ddxAvailable: Decimal,
+    pub ddxHold: Decimal,
+    pub usdAvailable: Decimal,
+    pub usdHold: Decimal,
+}
+
+/// Cancel of a resting order signed by the order's trader.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct JsonCancel {
+    pub orderHash: Hash,
+    pub nonce: Hash,
+    /// Hex encoded 65 byte signature of the cancel's EIP-712 digest.
+    pub signature: String,
+}
+
+/// Cancel of every resting order of a trader signed by the trader.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct JsonCancelAll {
+    pub traderAddress: Address,
+    pub nonce: Hash,
+    /// Hex encoded 65 byte signature of the cancel's EIP-712 digest.
+    pub signature: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +137,25 @@ pub struct JsonOrder {
     pub price: Decimal,
     pub side: Side,
     pub traderAddress: Address,
+    /// Only ever rest: the order is rejected if it would match on arrival. Not part of the
+    /// signed order.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub postOnly: bool,
+    /// Only ever reduce the trader's DDX position: a bid is rejected, and so is an ask for more
+    /// than the available DDX. Not part of the signed order.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reduceOnly: bool,
+    /// Hex encoded 65 byte signature of the order's EIP-712 digest by `traderAddress`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Unix seconds from which a resting order no longer matches and is pruned from the book,
+    /// good till cancelled if `None`. Not part of the signed order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiresAt: Option<u64>,
+    /// Largest amount of a resting order shown in the book, the rest staying hidden until the
+    /// shown slice is filled. Shows the whole amount if `None`. Not part of the signed order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displayAmount: Option<Decimal>,
 }
 
 // Implement `Display` for `JsonOrder`.
@@ -35,15 +166,52 @@ impl fmt::Display for JsonOrder {
     }
 }
 
+/// Order with the amount it has left and how far it got.
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+pub struct JsonOrderStatus {
+    #[serde(flatten)]
+    pub order: JsonOrder,
+    pub remaining: Decimal,
+    pub status: OrderStatus,
+    /// Sequence number the book assigned the order when accepting it.
+    pub seq: u64,
+    /// Unix milliseconds when the book accepted the order.
+    pub createdAt: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonTransfer {
+    pub from: Address,
+    pub to: Address,
+    pub asset: Asset,
+    pub amount: Decimal,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonFill {
     pub(crate) maker_hash: Hash,
     pub(crate) taker_hash: Hash,
     pub(crate) fill_amount: Decimal,
     pub(crate) price: Decimal,
+    pub(crate) maker_seq: u64,
+    pub(crate) taker_seq: u64,
+    pub(crate) timestamp: u64,
 }
 
+/// Open, high, low and close prices and traded amount of the fills in one interval.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct JsonCandle {
+    /// Unix milliseconds the interval starts at.
+    pub(crate) start: u64,
+    pub(crate) open: Decimal,
+    pub(crate) high: Decimal,
+    pub(crate) low: Decimal,
+    pub(crate) close: Decimal,
+    pub(crate) volume: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleOrder {
     pub(crate) amount: Decimal,
     pub(crate) price: Decimal,
@@ -55,6 +223,27 @@ pub struct L2OrderBook {
     pub(crate) bids: Vec<SimpleOrder>,
 }
 
+/// Order by order view of the book.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct L3OrderBook {
+    pub(crate) asks: Vec<JsonOrder>,
+    pub(crate) bids: Vec<JsonOrder>,
+}
+
+/// State a client starts from, taken at a single point in time.
+#[derive(Debug, Serialize)]
+pub struct BootstrapPayload {
+    /// Epoch of the book, see `OrderBook::epoch`.
+    pub(crate) epoch: u64,
+    /// Sequence number of the latest order accepted, see `OrderBook::sequence`.
+    pub(crate) sequence: u64,
+    pub(crate) book: L2OrderBook,
+    /// Latest retained fills, oldest first.
+    pub(crate) trades: Vec<JsonFill>,
+    /// Account of the requesting trader, if any.
+    pub(crate) account: Option<JsonAccount>,
+}
+
 impl L2OrderBook {
     pub fn new() -> Self {
         Self {
@@ -62,4 +251,37 @@ impl L2OrderBook {
             bids: Vec::new(),
         }
     }
+
+    /// Levels of each side whose amount differs from `previous`, in the order of this book,
+    /// followed by the levels of `previous` no longer present with a zero amount.
+    pub fn changes_since(&self, previous: &L2OrderBook) -> L2OrderBook {
+        L2OrderBook {
+            asks: level_changes(&self.asks, &previous.asks),
+            bids: level_changes(&self.bids, &previous.bids),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.asks.is_empty() && self.bids.is_empty()
+    }
+}
+
+fn level_changes(levels: &[SimpleOrder], previous: &[SimpleOrder]) -> Vec<SimpleOrder> {
+    let previous_amounts: HashMap<&Decimal, &Decimal> = previous
+        .iter()
+        .map(|level| (&level.price, &level.amount))
+        .collect();
+    let prices: HashSet<&Decimal> = levels.iter().map(|level| &level.price).collect();
+    let changed = levels
+        .iter()
+        .filter(|level| previous_amounts.get(&level.price) != Some(&&level.amount))
+        .cloned();
+    let removed = previous
+        .iter()
+        .filter(|level| !prices.contains(&level.price))
+        .map(|level| SimpleOrder {
+            amount: u256_to_decimal(&U256::zero()),
+            price: level.price.clone(),
+        });
+    changed.chain(removed).collect()
 }
@@ -1,94 +1,845 @@
 //! A limit-order match engine supports ETH account address and EIP-712 hashing signature
 //! with a l2 order-book generator.
 pub mod account;
+pub mod action;
+pub mod audit;
+pub mod candles;
+pub mod config;
+pub mod error;
 pub mod fill;
+pub mod integrity;
 pub mod json;
 pub mod order;
+pub mod peg;
 pub mod primitive;
+pub mod reservation;
+pub mod reserves;
+pub mod seed;
+pub mod settlement;
+pub mod snapshot;
+pub mod trades;
 
-use crate::account::{AccountManager, ERROR};
+use crate::account::{AccountManager, ERROR, required_hold};
+use crate::action::Action;
+use crate::audit::{AuditRecord, PriorityBasis};
+use crate::candles::{Candle, CandleAggregator};
+use crate::config::{
+    DuplicateOrderPolicy, MakerDustPolicy, MarketConfig, OffTickPolicy, OneSidedMarketPolicy,
+};
+use crate::error::OrderBookError;
 use crate::fill::{Fill, FillResult};
-use crate::json::{JsonOrder, L2OrderBook, Side, SimpleOrder};
+use crate::json::{
+    BootstrapPayload, Denomination, JsonCancel, JsonCancelAll, JsonCandle, JsonFill, JsonOrder,
+    JsonOrderStatus, L2OrderBook, L3OrderBook, Side, SimpleOrder,
+};
 use crate::order::Order;
-use crate::primitive::{Address, Hash, OrderStatus, u256_to_decimal};
+use crate::peg::Peg;
+use crate::primitive::{
+    Address, Decimal, decimal_to_u256, DecimalCache, format_units, Hash, normalize_decimal,
+    notional, now_millis, OrderStatus, Rounding, Scale, u256_to_decimal,
+};
+use crate::reservation::ReservationToken;
+use crate::trades::TradeHistory;
 use ethers::types::U256;
+use indexmap::{IndexMap, IndexSet};
 use log::debug;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::mpsc::Sender;
 // constants
 const ORDER_BOOK_INIT_CAP: usize = 50_000;
 const L2_MAX: usize = 50;
+// Most price levels an L2 snapshot returns a side, whatever depth is requested.
+const L2_DEPTH_LIMIT: usize = 500;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct HalfBook {
-    _side: Side,
+    side: Side,
     price_map: BTreeMap<U256, usize>,
-    price_levels: Vec<HashMap<Hash, Order>>,
+    // Orders of each price level in arrival order.
+    price_levels: Vec<IndexMap<Hash, Order>>,
+    // Slots of dropped price levels, reused by new prices.
+    free_levels: Vec<usize>,
+    // Resting orders showing only part of their amount, by hash.
+    icebergs: HashMap<Hash, Iceberg>,
+}
+
+/// Resting order showing at most a slice of its amount.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Iceberg {
+    // amount shown again each time the shown slice is filled.
+    display: U256,
+    // what is left of the shown slice.
+    visible: U256,
 }
 
 impl HalfBook {
     pub fn new(side: Side) -> Self {
         HalfBook {
-            _side: side,
+            side,
             price_map: BTreeMap::new(),
             price_levels: Vec::with_capacity(ORDER_BOOK_INIT_CAP),
+            free_levels: Vec::new(),
+            icebergs: HashMap::new(),
+        }
+    }
+
+    /// Resting orders from the best price on in queue order, skipping missing slots and
+    /// orders without amount left.
+    fn resting_orders(&self) -> impl Iterator<Item = (&Hash, &Order)> {
+        let locs: Box<dyn Iterator<Item = &usize>> = match self.side {
+            Side::Bid => Box::new(self.price_map.values().rev()),
+            Side::Ask => Box::new(self.price_map.values()),
+        };
+        locs.filter_map(|loc| self.price_levels.get(*loc))
+            .flat_map(|price_level| price_level.iter())
+            .filter(|(_, order)| !order.amount.is_zero())
+    }
+
+    /// Amount of a resting order shown to the market, at most the shown slice of an iceberg.
+    fn visible_amount(&self, order_id: &Hash, order: &Order) -> U256 {
+        self.icebergs
+            .get(order_id)
+            .map_or(order.amount, |iceberg| iceberg.visible.min(order.amount))
+    }
+
+    /// A resting order as shown to the market.
+    fn public_order(&self, order_id: &Hash, order: &Order) -> JsonOrder {
+        let mut json = order.to_json();
+        if self.icebergs.contains_key(order_id) {
+            json.amount = u256_to_decimal(&self.visible_amount(order_id, order));
+        }
+        json
+    }
+
+    /// Take a fill of a resting iceberg off its shown slice, showing the next slice once the
+    /// current one is filled, and forget icebergs that left the book. A new slice joins the back
+    /// of its level, behind the orders shown before it.
+    fn consume_iceberg(&mut self, fill: &Fill) {
+        let loc = self.price_map.get(&fill.price).copied();
+        let remaining = loc
+            .and_then(|loc| self.price_levels[loc].get(&fill.maker_hash))
+            .map(|order| order.amount);
+        match (self.icebergs.get_mut(&fill.maker_hash), remaining) {
+            (Some(iceberg), Some(remaining)) => {
+                iceberg.visible = iceberg.visible.saturating_sub(fill.fill_amount);
+                if iceberg.visible <= U256::from(ERROR) {
+                    iceberg.visible = iceberg.display.min(remaining);
+                    let price_level = &mut self.price_levels[loc.unwrap()];
+                    if let Some(order) = price_level.shift_remove(&fill.maker_hash) {
+                        price_level.insert(fill.maker_hash.clone(), order);
+                    }
+                }
+            }
+            (Some(_), None) => {
+                self.icebergs.remove(&fill.maker_hash);
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Total resting amount of up to `depth` price levels from the best price on. With a
+    /// display tick, levels are summed into buckets of that width instead, each labelled with
+    /// its lower price bound.
+    fn l2_orders(
+        &self,
+        depth: usize,
+        display_tick: Option<U256>,
+        mut price_cache: Option<&mut DecimalCache>,
+    ) -> Vec<SimpleOrder> {
+        let tick = display_tick.filter(|tick| !tick.is_zero());
+        let mut buckets: Vec<(U256, U256)> = Vec::new();
+        for (order_id, order) in self.resting_orders() {
+            let lower = match tick {
+                Some(tick) => order.price / tick * tick,
+                None => order.price,
+            };
+            let visible = self.visible_amount(order_id, order);
+            match buckets.last_mut() {
+                Some((price, amount)) if *price == lower => *amount += visible,
+                _ => {
+                    if buckets.len() == depth {
+                        break;
+                    }
+                    buckets.push((lower, visible));
+                }
+            }
+        }
+        buckets
+            .iter()
+            .map(|(price, amount)| SimpleOrder {
+                amount: u256_to_decimal(amount),
+                price: match price_cache.as_deref_mut() {
+                    Some(cache) => cache.format(price),
+                    None => u256_to_decimal(price),
+                },
+            })
+            .collect()
+    }
+
+    /// Drop a price from the book once its level has no orders left, freeing its slot.
+    fn drop_empty_level(&mut self, price: &U256) {
+        if let Some(loc) = self.price_map.get(price) {
+            if self.price_levels[*loc].is_empty() {
+                self.free_levels.push(*loc);
+                self.price_map.remove(price);
+            }
+        }
+    }
+
+    /// The best price with resting orders, i.e. the highest bid or the lowest ask.
+    fn best_price(&self) -> Option<U256> {
+        let levels = &self.price_levels;
+        let non_empty =
+            |(price, loc): (&U256, &usize)| (!levels[*loc].is_empty()).then_some(*price);
+        match self.side {
+            Side::Bid => self.price_map.iter().rev().find_map(non_empty),
+            Side::Ask => self.price_map.iter().find_map(non_empty),
+        }
+    }
+
+    /// The best price with the total amount shown at it.
+    fn best_level(&self) -> Option<SimpleOrder> {
+        let price = self.best_price()?;
+        let amount = self.price_levels[self.price_map[&price]]
+            .iter()
+            .fold(U256::zero(), |amount, (order_id, order)| {
+                amount + self.visible_amount(order_id, order)
+            });
+        Some(SimpleOrder {
+            amount: u256_to_decimal(&amount),
+            price: u256_to_decimal(&price),
+        })
+    }
+
+    /// The least aggressive price with resting orders, i.e. the lowest bid or the highest ask.
+    fn worst_price(&self) -> Option<U256> {
+        let levels = &self.price_levels;
+        let non_empty =
+            |(price, loc): (&U256, &usize)| (!levels[*loc].is_empty()).then_some(*price);
+        match self.side {
+            Side::Bid => self.price_map.iter().find_map(non_empty),
+            Side::Ask => self.price_map.iter().rev().find_map(non_empty),
         }
     }
 }
 
-#[derive(Debug)]
+/// Matching engine of one market. Snapshots leave out its configuration, which stays with the
+/// instance, as do its epoch and what only lives while it runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
     _symbol: String,
+    #[serde(skip)]
+    config: MarketConfig,
     bid_book: HalfBook,
     ask_book: HalfBook,
     // For fast cancels Order Hash -> (Side, Price_level)
     order_loc: HashMap<Hash, (Side, usize)>,
+    // Resting orders of each trader in the order they were added to the book.
+    trader_orders: HashMap<Address, IndexSet<Hash>>,
+    // Retained fills in time order.
+    trades: TradeHistory,
+    // Orders with held funds waiting to be committed.
+    reservations: HashMap<ReservationToken, (Hash, JsonOrder, Order)>,
+    reservation_count: u64,
+    // Reject local submissions, only replicated events mutate the book.
+    #[serde(skip)]
+    read_only: bool,
+    // Fairness audit records of fills, if enabled.
+    audit_log: Vec<AuditRecord>,
+    // Pegged resting orders in submission order.
+    pegged: IndexMap<Hash, Peg>,
+    // Orders that left the book, if archived, by hash.
+    archive: HashMap<Hash, Order>,
+    // Formatted L2 prices, filled while generating the read-only view.
+    #[serde(skip)]
+    price_cache: RefCell<Option<DecimalCache>>,
+    // Random id of this instance of the book.
+    #[serde(skip)]
+    epoch: u64,
+    // Unix milliseconds when the market last opened for local submissions.
+    #[serde(skip)]
+    opened_at: u64,
+    // Expiry in unix seconds of good-till-time orders. Entries of orders that left the book are
+    // dropped when expired orders are looked up.
+    expiries: HashMap<Hash, u64>,
+    // Channel every fill is published to as it is recorded, if subscribed.
+    #[serde(skip)]
+    fill_sender: Option<Sender<Fill>>,
+    // Amount filled so far of orders found by `get_order`, as maker or taker.
+    filled: HashMap<Hash, U256>,
+    // Last sequence number assigned to an accepted order.
+    sequence: u64,
+    // Sequence number and unix milliseconds of acceptance of orders found by `get_order`.
+    accepted: HashMap<Hash, (u64, u64)>,
+    // Actions applied since the log was last taken, if the market records them.
+    #[serde(skip)]
+    actions: Vec<Action>,
 }
 
 impl OrderBook {
     pub fn new(symbol: String) -> Self {
+        Self::with_config(symbol, MarketConfig::default())
+    }
+
+    pub fn with_config(symbol: String, config: MarketConfig) -> Self {
         OrderBook {
             _symbol: symbol,
+            config,
             bid_book: HalfBook::new(Side::Bid),
             ask_book: HalfBook::new(Side::Ask),
             order_loc: HashMap::with_capacity(ORDER_BOOK_INIT_CAP),
+            trader_orders: HashMap::new(),
+            trades: TradeHistory::new(),
+            reservations: HashMap::new(),
+            reservation_count: 0,
+            read_only: false,
+            audit_log: Vec::new(),
+            pegged: IndexMap::new(),
+            archive: HashMap::new(),
+            price_cache: RefCell::new(None),
+            epoch: rand::random(),
+            opened_at: now_millis(),
+            expiries: HashMap::new(),
+            fill_sender: None,
+            filled: HashMap::new(),
+            sequence: 0,
+            accepted: HashMap::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    /// Id of this instance of the book, drawn when it is created. A client seeing it change
+    /// knows that the engine restarted and has to resync from a snapshot.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Publish every fill to `sender` in execution order from now on, or stop publishing with
+    /// `None`. Fills are still returned by the submitting call. Publishing stops by itself
+    /// once the receiver is dropped.
+    pub fn set_fill_sender(&mut self, sender: Option<Sender<Fill>>) {
+        self.fill_sender = sender;
+    }
+
+    /// Last sequence number assigned to an accepted order. Every order the book accepts gets
+    /// the next number, so they order submissions even within the same millisecond.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Assign the next sequence number to an order accepted at `created_at`, in unix
+    /// milliseconds.
+    pub(crate) fn assign_sequence(&mut self, order_id: &Hash, created_at: u64) -> u64 {
+        self.sequence += 1;
+        self.accepted
+            .insert(order_id.clone(), (self.sequence, created_at));
+        self.sequence
+    }
+
+    pub fn config(&self) -> &MarketConfig {
+        &self.config
+    }
+
+    /// Switch the observer mode of a standby engine, in which `add_order` and `cancel_order`
+    /// fail with `ReadOnly` and only the replication applier mutates the book. Leaving it opens
+    /// the market, which starts the protected period if one is configured.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        if self.read_only && !read_only {
+            self.opened_at = now_millis();
         }
+        self.read_only = read_only;
+    }
+
+    /// Whether the market is within its protected period after opening.
+    fn protected_open(&self) -> bool {
+        self.config.protected_open_millis.map_or(false, |millis| {
+            now_millis() < self.opened_at.saturating_add(millis)
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn get_order(&self, order_id: Hash) -> Result<JsonOrder, &str> {
+    pub fn config_mut(&mut self) -> &mut MarketConfig {
+        &mut self.config
+    }
+
+    /// A resting order, or an archived one that left the book with its amount left then.
+    pub fn get_order(&self, order_id: Hash) -> Result<JsonOrder, OrderBookError> {
         if let Some((side, price_level)) = self.order_loc.get(&order_id) {
-            let current_map = match side {
-                Side::Bid => self.bid_book.price_levels.get(*price_level).unwrap(),
-                Side::Ask => self.ask_book.price_levels.get(*price_level).unwrap(),
+            let book = match side {
+                Side::Bid => &self.bid_book,
+                Side::Ask => &self.ask_book,
             };
-            let order = current_map.get(&order_id).unwrap();
+            let order = book.price_levels[*price_level].get(&order_id).unwrap();
+            let mut json = order.to_json();
+            json.expiresAt = self.expiries.get(&order_id).copied();
+            json.displayAmount = book
+                .icebergs
+                .get(&order_id)
+                .map(|iceberg| u256_to_decimal(&iceberg.display));
+            Ok(json)
+        } else if let Some(order) = self.archive.get(&order_id) {
             Ok(order.to_json())
         } else {
-            Err("No such order id")
+            Err(OrderBookError::NoSuchOrder)
+        }
+    }
+
+    /// Order with its remaining amount and status. A resting order is `Created` until it is
+    /// partially filled. An archived order is `Filled` once nothing is left, and otherwise
+    /// `Cancelled`, or `PartiallyFilled` if it traded before being cancelled.
+    pub fn get_order_status(&self, order_id: Hash) -> Result<JsonOrderStatus, OrderBookError> {
+        let order = self.get_order(order_id.clone())?;
+        let filled = self
+            .filled
+            .get(&order_id)
+            .map_or(false, |filled| !filled.is_zero());
+        let status = match self.get_resting(&order_id) {
+            Some(_) if filled => OrderStatus::PartiallyFilled,
+            Some(_) => OrderStatus::Created,
+            None if self.archive[&order_id].amount <= U256::from(ERROR) => OrderStatus::Filled,
+            None if filled => OrderStatus::PartiallyFilled,
+            None => OrderStatus::Cancelled,
+        };
+        let (seq, created_at) = self.accepted.get(&order_id).copied().unwrap_or_default();
+        Ok(JsonOrderStatus {
+            remaining: order.amount.clone(),
+            order,
+            status,
+            seq,
+            createdAt: created_at,
+        })
+    }
+
+    /// Keep an order that left the book if the market archives them.
+    pub(crate) fn archive_order(&mut self, order_id: Hash, order: Order) {
+        if self.config.archive_removed_orders {
+            self.archive.insert(order_id, order);
+        } else {
+            self.accepted.remove(&order_id);
         }
     }
 
+    /// Trader of a resting order, or `None` if no order rests with this hash.
+    pub fn order_owner(&self, order_id: &Hash) -> Option<Address> {
+        let (side, price_level) = self.order_loc.get(order_id)?;
+        let half_book = match side {
+            Side::Bid => &self.bid_book,
+            Side::Ask => &self.ask_book,
+        };
+        half_book.price_levels[*price_level]
+            .get(order_id)
+            .map(|order| order.traderAddress)
+    }
+
+    /// Cancel an order without a signature, which is rejected if the market requires them.
     pub fn cancel_order(
         &mut self,
         manager: &mut AccountManager,
         order_id: Hash,
-    ) -> Result<JsonOrder, &str> {
-        if let Some((side, price_level)) = self.order_loc.get(&order_id) {
-            let current_map = match side {
-                Side::Bid => self.bid_book.price_levels.get_mut(*price_level).unwrap(),
-                Side::Ask => self.ask_book.price_levels.get_mut(*price_level).unwrap(),
-            };
-            let order = current_map.remove(&order_id).unwrap();
-            self.order_loc.remove(&order_id);
+    ) -> Result<JsonOrder, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.config.require_signatures {
+            return Err(OrderBookError::InvalidSignature("missing".to_string()));
+        }
+        self.apply_replicated_cancel(manager, order_id)
+    }
+
+    /// Cancel an order on behalf of its trader, who must have signed the cancel.
+    pub fn cancel_signed_order(
+        &mut self,
+        manager: &mut AccountManager,
+        cancel: &JsonCancel,
+    ) -> Result<JsonOrder, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let owner = self
+            .order_owner(&cancel.orderHash)
+            .ok_or(OrderBookError::NoSuchOrder)?;
+        let signer = cancel
+            .encode_cancel()?
+            .recover_signer(&cancel.signature, &self.config.domain)?;
+        if signer != owner {
+            return Err(OrderBookError::InvalidSignature(format!(
+                "signed by {:?}",
+                signer
+            )));
+        }
+        self.apply_replicated_cancel(manager, cancel.orderHash.clone())
+    }
+
+    /// Lower the amount of a resting order to `new_amount`, keeping its place in the queue, and
+    /// release the funds held for the difference. The amount can neither grow nor drop below the
+    /// market minimum, cancel the order instead. The trader is not authenticated, so a market
+    /// requiring signatures rejects it.
+    pub fn reduce_order(
+        &mut self,
+        manager: &mut AccountManager,
+        order_id: Hash,
+        new_amount: &Decimal,
+    ) -> Result<JsonOrder, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.config.require_signatures {
+            return Err(OrderBookError::InvalidSignature("missing".to_string()));
+        }
+        let new_amount = decimal_to_u256(new_amount)?;
+        let (side, price_level) = self
+            .order_loc
+            .get(&order_id)
+            .ok_or(OrderBookError::NoSuchOrder)?;
+        let order = match side {
+            Side::Bid => &mut self.bid_book.price_levels[*price_level],
+            Side::Ask => &mut self.ask_book.price_levels[*price_level],
+        }
+        .get_mut(&order_id)
+        .unwrap();
+        if new_amount > order.amount || new_amount.is_zero() || new_amount < self.config.min_amount
+        {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        manager.release_reduced(order, new_amount);
+        order.amount = new_amount;
+        Ok(order.to_json())
+    }
+
+    /// Apply a cancel replicated from the primary engine, regardless of read-only mode.
+    pub fn apply_replicated_cancel(
+        &mut self,
+        manager: &mut AccountManager,
+        order_id: Hash,
+    ) -> Result<JsonOrder, OrderBookError> {
+        if let Some(order) = self.remove_order(&order_id) {
             // restore user's account balance after cancellation.
             manager.release_pending_fund(&order);
+            self.archive_order(order_id.clone(), order);
+            self.pegged.shift_remove(&order_id);
+            self.expiries.remove(&order_id);
+            self.reprice_pegged(manager);
             Ok(order.to_json())
         } else {
-            Err("No such order id")
+            Err(OrderBookError::NoSuchOrder)
+        }
+    }
+
+    /// Cancel the resting good-till-time orders expired by `now`, in unix seconds, and release
+    /// their funds.
+    pub fn prune_expired(
+        &mut self,
+        manager: &mut AccountManager,
+        now: u64,
+    ) -> Result<Vec<JsonOrder>, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let expired = self.take_expired(now);
+        for order in expired.iter() {
+            manager.release_pending_fund(order);
+        }
+        if !expired.is_empty() {
+            self.reprice_pegged(manager);
+        }
+        Ok(expired.iter().map(Order::to_json).collect())
+    }
+
+    /// Whether a resting order has expired by `now`, in unix seconds.
+    fn is_expired(&self, order_id: &Hash, now: u64) -> bool {
+        self.expiries
+            .get(order_id)
+            .map_or(false, |expires_at| *expires_at <= now)
+    }
+
+    /// Take the orders expired by `now` out of the book without releasing their funds, in hash
+    /// order.
+    fn take_expired(&mut self, now: u64) -> Vec<Order> {
+        let order_loc = &self.order_loc;
+        self.expiries
+            .retain(|order_id, _| order_loc.contains_key(order_id));
+        let mut expired: Vec<Hash> = self
+            .expiries
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(order_id, _)| order_id.clone())
+            .collect();
+        expired.sort();
+        let mut orders = Vec::with_capacity(expired.len());
+        for order_id in expired {
+            debug!("Cancel resting order {} expired at {}", order_id, now);
+            self.expiries.remove(&order_id);
+            self.pegged.shift_remove(&order_id);
+            let order = self.remove_order(&order_id).unwrap();
+            self.archive_order(order_id, order);
+            orders.push(order);
+        }
+        orders
+    }
+
+    /// Take a resting order out of the book without releasing its funds.
+    fn remove_order(&mut self, order_id: &Hash) -> Option<Order> {
+        let (side, price_level) = self.order_loc.remove(order_id)?;
+        let current_map = match side {
+            Side::Bid => self.bid_book.price_levels.get_mut(price_level).unwrap(),
+            Side::Ask => self.ask_book.price_levels.get_mut(price_level).unwrap(),
+        };
+        let order = current_map.shift_remove(order_id).unwrap();
+        let book = match side {
+            Side::Bid => &mut self.bid_book,
+            Side::Ask => &mut self.ask_book,
+        };
+        book.drop_empty_level(&order.price);
+        book.icebergs.remove(order_id);
+        Self::untrack_order(&mut self.trader_orders, &order.traderAddress, order_id);
+        Some(order)
+    }
+
+    fn get_resting(&self, order_id: &Hash) -> Option<&Order> {
+        let (side, price_level) = self.order_loc.get(order_id)?;
+        let current_map = match side {
+            Side::Bid => &self.bid_book.price_levels[*price_level],
+            Side::Ask => &self.ask_book.price_levels[*price_level],
+        };
+        current_map.get(order_id)
+    }
+
+    /// Cancel the last matched resting order if the fill left it below the minimum amount.
+    fn cancel_maker_dust(&mut self, fill_result: &mut FillResult) {
+        if self.config.maker_dust_policy != MakerDustPolicy::Cancel {
+            return;
+        }
+        let order_id = match fill_result.filled_orders.last() {
+            Some(fill) => fill.maker_hash.clone(),
+            None => return,
+        };
+        let is_dust = self
+            .get_resting(&order_id)
+            .map_or(false, |order| order.amount < self.config.min_amount);
+        if is_dust {
+            let order = self.remove_order(&order_id).unwrap();
+            debug!("Cancel resting order {} below the minimum amount", order_id);
+            self.archive_order(order_id, order);
+            fill_result.cancelled_orders.push(order);
+        }
+    }
+
+    /// Replace every resting order with the passive orders of `snapshot`, e.g. to reload the
+    /// book around fresh reference prices. Current orders are cancelled and their funds
+    /// released before the snapshot orders reserve theirs. The swap bumps the sequence once, and
+    /// the snapshot orders all share that sequence number. Either the whole snapshot is loaded
+    /// or, if an order crosses the book or its trader lacks funds, nothing changes.
+    pub fn replace_book(
+        &mut self,
+        manager: &mut AccountManager,
+        snapshot: Vec<JsonOrder>,
+    ) -> Result<(), OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let mut book = self.clone();
+        let mut accounts = manager.clone();
+        book.pegged.clear();
+        let order_ids: Vec<Hash> = book.order_loc.keys().cloned().collect();
+        for order_id in order_ids {
+            // cancelled by the operator, so no trader signature is needed.
+            book.apply_replicated_cancel(&mut accounts, order_id)
+                .unwrap();
+        }
+        book.sequence += 1;
+        let accepted = (book.sequence, now_millis());
+        for order in snapshot {
+            let order = book.normalize_order(order)?;
+            let side = order.side.clone();
+            if book.crosses(&side, &decimal_to_u256(&order.price)?) {
+                return Err(OrderBookError::InvalidOrder);
+            }
+            let encoded_order = accounts.validate_order(order.clone())?;
+            let order_id = book.order_hash(&encoded_order)?;
+            if book.order_loc.contains_key(&order_id) {
+                return Err(OrderBookError::DuplicateOrder);
+            }
+            book.create_new_limit_order(side, order_id.clone(), encoded_order);
+            book.accepted.insert(order_id.clone(), accepted);
+            book.track_iceberg(&order, &order_id);
         }
+        *self = book;
+        *manager = accounts;
+        Ok(())
     }
 
-    fn create_new_limit_order(&mut self, side: Side, order: Order) -> Hash {
-        let order_id = order.hash_hex();
+    /// Cancel a resting order and submit `new_order` of the same trader in its place, e.g. to
+    /// amend its price or amount. The new order goes to the back of its level's queue like any
+    /// new submission, and it may match on arrival. It is checked before the old order is
+    /// cancelled, with the old order's funds counted as released, so if it is rejected the old
+    /// order keeps resting with its funds and queue position as if nothing happened.
+    pub fn replace_order(
+        &mut self,
+        manager: &mut AccountManager,
+        old_hash: Hash,
+        new_order: JsonOrder,
+    ) -> Result<FillResult, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let old_order = *self
+            .get_resting(&old_hash)
+            .ok_or(OrderBookError::NoSuchOrder)?;
+        if new_order.traderAddress != old_order.traderAddress {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        let order = self.normalize_order(new_order.clone())?;
+        let encoded_order = order.encode_order()?;
+        let crosses = self.crosses(&order.side, &encoded_order.price);
+        if self.protected_open() && crosses {
+            return Err(OrderBookError::ProtectedOpen);
+        }
+        if !self.check_order(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        if order.postOnly && crosses {
+            return Err(OrderBookError::WouldCross);
+        }
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.get_resting(&order_id).is_some() || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        manager.check_replacement(&old_order, &order, &encoded_order)?;
+        self.apply_replicated_cancel(manager, old_hash)?;
+        self.add_order(manager, new_order)
+    }
+
+    /// Resting orders of a trader with their remaining amounts, in the order they were added to
+    /// the book.
+    pub fn get_orders_by_trader(&self, trader: &Address) -> Vec<JsonOrder> {
+        self.trader_orders
+            .get(trader)
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| self.get_order(order_id.clone()).ok())
+            .collect()
+    }
+
+    /// Number of resting orders of a trader.
+    pub fn open_order_count(&self, trader: &Address) -> usize {
+        self.trader_orders.get(trader).map_or(0, IndexSet::len)
+    }
+
+    /// Cancel the `n` resting orders a trader added to the book first, returning them oldest
+    /// first. A repriced pegged order counts as added when it moved. The trader is not
+    /// authenticated, callers acting for the trader go through `cancel_all_signed`.
+    pub fn cancel_oldest(
+        &mut self,
+        manager: &mut AccountManager,
+        trader: &Address,
+        n: usize,
+    ) -> Result<Vec<JsonOrder>, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let order_ids: Vec<Hash> = match self.trader_orders.get(trader) {
+            Some(orders) => orders.iter().take(n).cloned().collect(),
+            None => Vec::new(),
+        };
+        order_ids
+            .into_iter()
+            .map(|order_id| self.apply_replicated_cancel(manager, order_id))
+            .collect()
+    }
+
+    /// Cancel every resting order of a trader and release their funds, returning the cancelled
+    /// orders oldest first.
+    pub fn cancel_all(
+        &mut self,
+        manager: &mut AccountManager,
+        trader: &Address,
+    ) -> Result<Vec<JsonOrder>, OrderBookError> {
+        self.cancel_oldest(manager, trader, usize::MAX)
+    }
+
+    /// Cancel every resting order of a trader on behalf of the trader, who must have signed the
+    /// cancel. Its nonce is used up like an order's, so a replay is rejected.
+    pub fn cancel_all_signed(
+        &mut self,
+        manager: &mut AccountManager,
+        cancel: &JsonCancelAll,
+    ) -> Result<Vec<JsonOrder>, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let encoded = cancel.encode_cancel_all()?;
+        let signer = encoded.recover_signer(&cancel.signature, &self.config.domain)?;
+        if signer != encoded.traderAddress {
+            return Err(OrderBookError::InvalidSignature(format!(
+                "signed by {:?}",
+                signer
+            )));
+        }
+        if manager.nonce_used(&encoded.traderAddress, &encoded.nonce) {
+            return Err(OrderBookError::NonceUsed);
+        }
+        manager
+            .used_nonces
+            .entry(encoded.traderAddress)
+            .or_default()
+            .insert(encoded.nonce);
+        self.cancel_all(manager, &encoded.traderAddress)
+    }
+
+    fn untrack_order(
+        trader_orders: &mut HashMap<Address, IndexSet<Hash>>,
+        trader: &Address,
+        order_id: &Hash,
+    ) {
+        if let Some(orders) = trader_orders.get_mut(trader) {
+            orders.shift_remove(order_id);
+            if orders.is_empty() {
+                trader_orders.remove(trader);
+            }
+        }
+    }
+
+    /// Id of an order in the book, which is the EIP-712 digest of the order as signed with the
+    /// market's decimals.
+    fn order_hash(&self, order: &Order) -> Result<Hash, OrderBookError> {
+        order
+            .with_decimals(self.config.decimals)
+            .hash_hex_in(&self.config.domain)
+    }
+
+    /// Show only slices of a resting order submitted with a display amount.
+    fn track_iceberg(&mut self, order: &JsonOrder, order_id: &Hash) {
+        let display = match order.displayAmount.as_ref().map(decimal_to_u256) {
+            Some(Ok(display)) => display,
+            _ => return,
+        };
+        let book = match order.side {
+            Side::Bid => &mut self.bid_book,
+            Side::Ask => &mut self.ask_book,
+        };
+        let iceberg = Iceberg {
+            display,
+            visible: display,
+        };
+        book.icebergs.insert(order_id.clone(), iceberg);
+    }
+
+    fn create_new_limit_order(&mut self, side: Side, order_id: Hash, order: Order) -> Hash {
+        assert!(
+            !self.order_loc.contains_key(&order_id),
+            "Order {} is already resting!",
+            order_id
+        );
+        self.trader_orders
+            .entry(order.traderAddress)
+            .or_default()
+            .insert(order_id.clone());
         let book = match side {
             Side::Ask => &mut self.ask_book,
             Side::Bid => &mut self.bid_book,
@@ -98,29 +849,104 @@ impl OrderBook {
             book.price_levels[*val].insert(order_id.clone(), order);
             self.order_loc.insert(order_id.clone(), (side, *val));
         } else {
-            let new_loc = book.price_levels.len();
-            book.price_map.insert(order.price, new_loc);
-            let mut new_map = HashMap::new();
+            let mut new_map = IndexMap::new();
             new_map.insert(order_id.clone(), order);
-            book.price_levels.push(new_map);
+            let new_loc = match book.free_levels.pop() {
+                Some(loc) => {
+                    book.price_levels[loc] = new_map;
+                    loc
+                }
+                None => {
+                    book.price_levels.push(new_map);
+                    book.price_levels.len() - 1
+                }
+            };
+            book.price_map.insert(order.price, new_loc);
             self.order_loc.insert(order_id.clone(), (side, new_loc));
         }
         order_id
     }
 
+    /// Check if an incoming order at the given price would cross the opposite half book.
+    fn crosses(&self, side: &Side, price: &U256) -> bool {
+        match side {
+            Side::Bid => self
+                .ask_book
+                .best_price()
+                .map_or(false, |ask| *price >= ask),
+            Side::Ask => self
+                .bid_book
+                .best_price()
+                .map_or(false, |bid| *price <= bid),
+        }
+    }
+
+    /// Check if a passive order is priced within the configured maker band around the touch.
+    fn within_maker_band(&self, side: &Side, price: &U256) -> bool {
+        let band = match self.config.maker_price_band_bps {
+            Some(band) => band,
+            None => return true,
+        };
+        let (same_book, opposite_book) = match side {
+            Side::Bid => (&self.bid_book, &self.ask_book),
+            Side::Ask => (&self.ask_book, &self.bid_book),
+        };
+        // the first order of an empty book has nothing to be compared with.
+        let reference = match same_book
+            .best_price()
+            .or_else(|| opposite_book.best_price())
+        {
+            Some(reference) => reference,
+            None => return true,
+        };
+        let distance = if *price > reference {
+            *price - reference
+        } else {
+            reference - *price
+        };
+        distance.saturating_mul(U256::from(10_000)) <= reference.saturating_mul(U256::from(band))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn match_at_price_level(
         fill_result: &mut FillResult,
-        price_level: &mut HashMap<Hash, Order>,
+        price_level: &mut IndexMap<Hash, Order>,
         order_loc: &mut HashMap<Hash, (Side, usize)>,
-        maker_order: &Hash,
-        trader_addr: &Address,
-        side: Side,
+        trader_orders: &mut HashMap<Address, IndexSet<Hash>>,
+        incoming_hash: &Hash,
+        incoming: &Order,
+        timestamp: u64,
+        max_fills: usize,
+        mut audit_log: Option<&mut Vec<AuditRecord>>,
+        mut archive: Option<&mut HashMap<Hash, Order>>,
     ) {
+        // open orders of the level in queue order, kept for the audit log.
+        let mut queue: Vec<Hash> = match audit_log {
+            Some(_) => price_level
+                .iter()
+                .filter(|(_, o)| o.amount > U256::from(ERROR))
+                .map(|(order_id, _)| order_id.clone())
+                .collect(),
+            None => Vec::new(),
+        };
         for (order_id, order) in price_level.iter_mut() {
+            if fill_result.filled_orders.len() >= max_fills {
+                break;
+            }
             let fill: Fill;
-            let (from, to) = match side {
-                Side::Bid => (order.traderAddress, trader_addr.clone()),
-                Side::Ask => (trader_addr.clone(), order.traderAddress),
+            let (from, to, bid_price, bid_amount) = match fill_result.side {
+                Side::Bid => (
+                    order.traderAddress,
+                    incoming.traderAddress,
+                    incoming.price,
+                    fill_result.remaining,
+                ),
+                Side::Ask => (
+                    incoming.traderAddress,
+                    order.traderAddress,
+                    order.price,
+                    order.amount,
+                ),
             };
             // self-match prevention.
             if from != to {
@@ -128,26 +954,53 @@ impl OrderBook {
                     fill = Fill {
                         from,
                         to,
-                        maker_hash: maker_order.clone(),
-                        taker_hash: order_id.clone(),
+                        maker_hash: order_id.clone(),
+                        taker_hash: incoming_hash.clone(),
+                        taker_side: fill_result.side.clone(),
                         fill_amount: order.amount.clone(),
                         price: order.price.clone(),
+                        bid_price,
+                        bid_amount,
+                        mid: None,
+                        maker_seq: 0,
+                        taker_seq: 0,
+                        timestamp,
                     };
                     fill_result.remaining -= order.amount;
                     order.amount = U256::zero();
-                    order_loc.remove(order_id);
                 } else {
                     fill = Fill {
                         from,
                         to,
-                        maker_hash: maker_order.clone(),
-                        taker_hash: order_id.clone(),
+                        maker_hash: order_id.clone(),
+                        taker_hash: incoming_hash.clone(),
+                        taker_side: fill_result.side.clone(),
                         fill_amount: fill_result.remaining.clone(),
                         price: order.price.clone(),
+                        bid_price,
+                        bid_amount,
+                        mid: None,
+                        maker_seq: 0,
+                        taker_seq: 0,
+                        timestamp,
                     };
                     order.amount -= fill_result.remaining;
                     fill_result.remaining = U256::zero();
                 }
+                if let Some(audit_log) = audit_log.as_deref_mut() {
+                    let position = queue.iter().position(|id| id == order_id).unwrap();
+                    audit_log.push(AuditRecord {
+                        incoming_hash: incoming_hash.clone(),
+                        price: u256_to_decimal(&order.price),
+                        queue: queue.clone(),
+                        selected_hash: order_id.clone(),
+                        basis: PriorityBasis::Fifo { position },
+                        timestamp,
+                    });
+                    if order.amount <= U256::from(ERROR) {
+                        queue.remove(position);
+                    }
+                }
                 fill_result.filled_orders.push(fill);
                 if fill_result.remaining <= U256::from(ERROR) {
                     // order is all filled.
@@ -156,152 +1009,959 @@ impl OrderBook {
             }
         }
         // remove filled orders from the order book.
-        price_level.retain(|_, o| o.amount > U256::from(ERROR));
+        price_level.retain(|order_id, o| {
+            let resting = o.amount > U256::from(ERROR);
+            if !resting {
+                order_loc.remove(order_id);
+                Self::untrack_order(trader_orders, &o.traderAddress, order_id);
+                if let Some(archive) = archive.as_deref_mut() {
+                    archive.insert(order_id.clone(), *o);
+                }
+            }
+            resting
+        });
     }
 
     pub fn add_order(
         &mut self,
         manager: &mut AccountManager,
         order: JsonOrder,
-    ) -> Option<FillResult> {
-        if let Some(encoded_order) = manager.validate_order(order.clone()) {
-            let maker_order = encoded_order.hash_hex();
-            debug!(
-                "Got order with amount {}, at price {}",
-                order.amount, order.price
-            );
-            let mut fill_result = FillResult::new(encoded_order.amount, order.side.clone());
-            match order.side {
-                Side::Bid => {
-                    let ask_book = &mut self.ask_book;
-                    let price_map = &mut ask_book.price_map;
-                    let price_levels = &mut ask_book.price_levels;
-                    let mut price_map_iter = price_map.iter();
-
-                    if let Some((mut x, _)) = price_map_iter.next() {
-                        while &encoded_order.price >= x {
-                            let curr_level = price_map[x];
-                            Self::match_at_price_level(
-                                &mut fill_result,
-                                &mut price_levels[curr_level],
-                                &mut self.order_loc,
-                                &maker_order,
-                                &order.traderAddress,
-                                Side::Bid,
-                            );
-                            if let Some((a, _)) = price_map_iter.next() {
-                                x = a;
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                }
-                Side::Ask => {
-                    let bid_book = &mut self.bid_book;
-                    let price_map = &mut bid_book.price_map;
-                    let price_levels = &mut bid_book.price_levels;
-                    let mut price_map_iter = price_map.iter();
-
-                    if let Some((mut x, _)) = price_map_iter.next_back() {
-                        while &encoded_order.price <= x {
-                            let curr_level = price_map[x];
-                            Self::match_at_price_level(
-                                &mut fill_result,
-                                &mut price_levels[curr_level],
-                                &mut self.order_loc,
-                                &maker_order,
-                                &order.traderAddress,
-                                Side::Ask,
-                            );
-                            if let Some((a, _)) = price_map_iter.next_back() {
-                                x = a;
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-            if fill_result.remaining > U256::from(ERROR) {
-                let remaining_decimal = u256_to_decimal(&fill_result.remaining);
-                debug!(
-                    "Still remaining amount {} at price level {}",
-                    remaining_decimal, order.price
-                );
-                fill_result.status = OrderStatus::PartiallyFilled;
-                let mut new_order = encoded_order.clone();
-                new_order.amount = fill_result.remaining;
-                self.create_new_limit_order(order.side, new_order);
-            } else {
-                fill_result.status = OrderStatus::Filled;
+    ) -> Result<FillResult, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.protected_open() {
+            let price = self.normalize_order(order.clone())?.encode_order()?.price;
+            if self.crosses(&order.side, &price) {
+                return Err(OrderBookError::ProtectedOpen);
             }
-            Some(fill_result)
-        } else {
-            None
         }
+        self.apply_replicated_order(manager, order)
     }
 
-    pub fn generate_l2_order_book(&self) -> L2OrderBook {
-        let mut l2 = L2OrderBook::new();
-        let mut ask_price_map_iter = self.ask_book.price_map.iter();
-        let mut count = L2_MAX;
-        // get lowest ask prices.
-        while count > 0 {
-            if let Some((x, _)) = ask_price_map_iter.next() {
-                let curr_level = self.ask_book.price_map[x];
-                let price_level = &self.ask_book.price_levels[curr_level];
-                for (_, order) in price_level {
-                    let simple = SimpleOrder {
-                        amount: u256_to_decimal(&order.amount),
-                        price: u256_to_decimal(&order.price),
-                    };
-                    l2.asks.push(simple);
-                    count -= 1;
-                    if count <= 0 {
-                        break;
-                    }
-                }
-            } else {
-                break;
-            }
+    /// Apply an order replicated from the primary engine, regardless of read-only mode.
+    pub fn apply_replicated_order(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+    ) -> Result<FillResult, OrderBookError> {
+        let order = self.normalize_order(order)?;
+        let encoded_order = order.encode_order()?;
+        if !self.check_order(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
         }
-        let mut bid_price_map_iter = self.bid_book.price_map.iter();
-        count = L2_MAX;
-        // get highest bid price.
-        while count > 0 {
-            if let Some((x, _)) = bid_price_map_iter.next_back() {
-                let curr_level = self.bid_book.price_map[x];
-                let price_level = &self.bid_book.price_levels[curr_level];
-                for (_, order) in price_level {
-                    let simple = SimpleOrder {
-                        amount: u256_to_decimal(&order.amount),
-                        price: u256_to_decimal(&order.price),
-                    };
-                    l2.bids.push(simple);
-                    count -= 1;
-                    if count <= 0 {
-                        break;
-                    }
-                }
-            } else {
-                break;
-            }
+        if order.postOnly && self.crosses(&order.side, &encoded_order.price) {
+            return Err(OrderBookError::WouldCross);
         }
-        l2
+        // a retried order must neither replace the resting one nor hold funds twice.
+        let order_id = self.order_hash(&encoded_order)?;
+        if let Some(resting) = self.get_resting(&order_id) {
+            return match self.config.duplicate_order_policy {
+                DuplicateOrderPolicy::Reject => Err(OrderBookError::DuplicateOrder),
+                DuplicateOrderPolicy::Idempotent => Ok(FillResult::new(resting.amount, order.side)),
+            };
+        }
+        if self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        let encoded_order = manager.validate_order(order.clone())?;
+        let reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        let mut fill_result = self.match_order(order, encoded_order, order_id, true);
+        fill_result.reserved = reserved;
+        self.reprice_pegged(manager);
+        Ok(fill_result)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::json::JsonAccount;
-    use hex;
-    use num_bigint::{BigUint, RandomBits};
-    use rand::{rngs::StdRng, Rng, SeedableRng};
+    /// Submit an all-or-none order, which fills entirely against the opposite level at exactly
+    /// its price or not at all. Better priced levels are left untouched and nothing rests.
+    pub fn add_order_all_or_none(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+    ) -> Result<FillResult, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.protected_open() {
+            return Err(OrderBookError::ProtectedOpen);
+        }
+        let order = self.normalize_order(order)?;
+        let encoded_order = order.encode_order()?;
+        if self.below_minimum(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        if encoded_order.amount.is_zero() {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        if self.level_liquidity(&encoded_order) < encoded_order.amount {
+            return Err(OrderBookError::InsufficientLiquidity);
+        }
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.get_resting(&order_id).is_some() || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        let encoded_order = manager.validate_order(order.clone())?;
+        let reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        let price = encoded_order.price;
+        // all-or-none orders fill completely regardless of the fill cap.
+        let mut fill_result = self.match_order_within(
+            order,
+            encoded_order,
+            order_id,
+            false,
+            (Bound::Included(price), Bound::Included(price)),
+            usize::MAX,
+        );
+        fill_result.reserved = reserved;
+        self.reprice_pegged(manager);
+        Ok(fill_result)
+    }
 
-    #[test]
-    fn eip_712() {
+    /// Submit a fill-or-kill order, which fills its entire amount against the opposite book at
+    /// its price or better, or is rejected without any fill or balance change.
+    pub fn add_order_fill_or_kill(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+    ) -> Result<FillResult, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.protected_open() {
+            return Err(OrderBookError::ProtectedOpen);
+        }
+        let order = self.normalize_order(order)?;
+        let encoded_order = order.encode_order()?;
+        if self.below_minimum(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        if !self.fillable(&encoded_order) {
+            return Err(OrderBookError::InsufficientLiquidity);
+        }
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.order_loc.contains_key(&order_id) || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        let encoded_order = manager.validate_order(order.clone())?;
+        let reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        let mut fill_result = self.match_order(order, encoded_order, order_id, false);
+        fill_result.reserved = reserved;
+        self.reprice_pegged(manager);
+        Ok(fill_result)
+    }
+
+    /// Dry run of matching: whether the opposite book holds enough of other traders' liquidity
+    /// at the order's price or better to fill it completely within the fill cap.
+    fn fillable(&self, order: &Order) -> bool {
+        // levels best price first, in the order matching visits them.
+        let (book, levels): (&HalfBook, Vec<usize>) = match order.get_side() {
+            Side::Bid => (
+                &self.ask_book,
+                self.ask_book
+                    .price_map
+                    .range(..=order.price)
+                    .map(|(_, loc)| *loc)
+                    .collect(),
+            ),
+            Side::Ask => (
+                &self.bid_book,
+                self.bid_book
+                    .price_map
+                    .range(order.price..)
+                    .rev()
+                    .map(|(_, loc)| *loc)
+                    .collect(),
+            ),
+        };
+        let now = now_millis() / 1000;
+        let makers = levels
+            .into_iter()
+            .flat_map(|loc| book.price_levels[loc].iter())
+            .filter(|(order_id, o)| {
+                o.traderAddress != order.traderAddress && !self.is_expired(order_id, now)
+            })
+            .map(|(_, o)| o);
+        let max_fills = self.config.max_fills_per_submission.unwrap_or(usize::MAX);
+        let mut filled = U256::zero();
+        for maker in makers.take(max_fills) {
+            filled += maker.amount;
+            if filled >= order.amount {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Submit a market order, which matches the opposite book at any price until it is filled or
+    /// the book runs out of liquidity. The order's own price is ignored, and a signature has to
+    /// cover a zero price. Nothing rests: funds are held as for a limit at the furthest price
+    /// the order can reach, and the unfilled remainder is cancelled.
+    pub fn add_market_order(
+        &mut self,
+        manager: &mut AccountManager,
+        mut order: JsonOrder,
+    ) -> Result<FillResult, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        if self.protected_open() {
+            return Err(OrderBookError::ProtectedOpen);
+        }
+        order.price = "0".to_string();
+        let order = self.normalize_order(order)?;
+        let mut encoded_order = order.encode_order()?;
+        encoded_order.price = match self.market_price(&encoded_order) {
+            Some(price) => price,
+            None => {
+                return match self.config.one_sided_market_policy {
+                    OneSidedMarketPolicy::Reject => Err(OrderBookError::InsufficientLiquidity),
+                    OneSidedMarketPolicy::Cancel => {
+                        self.cancel_market_order(manager, order, encoded_order)
+                    }
+                };
+            }
+        };
+        if self.below_minimum(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.order_loc.contains_key(&order_id) || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        manager.accept_order(&order, &encoded_order)?;
+        let reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        let mut fill_result = self.match_order(order, encoded_order, order_id, false);
+        fill_result.reserved = reserved;
+        self.reprice_pegged(manager);
+        Ok(fill_result)
+    }
+
+    /// Accept a market order that has nothing to match and cancel it whole. A bid is priced at
+    /// zero and holds no USD, while an ask holds its DDX until the accounts are updated.
+    fn cancel_market_order(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+        encoded_order: Order,
+    ) -> Result<FillResult, OrderBookError> {
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.order_loc.contains_key(&order_id) || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        manager.accept_order(&order, &encoded_order)?;
+        debug!(
+            "Cancel market order {} with no opposite liquidity",
+            order_id
+        );
+        let mut fill_result = FillResult::new(encoded_order.amount, order.side);
+        fill_result.reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        fill_result.status = OrderStatus::Cancelled;
+        fill_result.cancelled_orders.push(encoded_order);
+        Ok(fill_result)
+    }
+
+    /// Furthest opposite price a market order has to reach to fill, or the last price with
+    /// liquidity if the book cannot fill it. Its own trader's orders are not counted.
+    fn market_price(&self, order: &Order) -> Option<U256> {
+        let book = match order.get_side() {
+            Side::Bid => &self.ask_book,
+            Side::Ask => &self.bid_book,
+        };
+        let now = now_millis() / 1000;
+        let liquidity = |(price, loc): (&U256, &usize)| {
+            let amount = book.price_levels[*loc]
+                .iter()
+                .filter(|(order_id, o)| {
+                    o.traderAddress != order.traderAddress && !self.is_expired(order_id, now)
+                })
+                .fold(U256::zero(), |amount, (_, o)| amount + o.amount);
+            (!amount.is_zero()).then_some((*price, amount))
+        };
+        let levels: Vec<(U256, U256)> = match order.get_side() {
+            Side::Bid => book.price_map.iter().filter_map(liquidity).collect(),
+            Side::Ask => book.price_map.iter().rev().filter_map(liquidity).collect(),
+        };
+        let mut filled = U256::zero();
+        for (price, amount) in levels.iter() {
+            filled += *amount;
+            if filled >= order.amount {
+                return Some(*price);
+            }
+        }
+        levels.last().map(|(price, _)| *price)
+    }
+
+    /// Amount an order can match at exactly its price, excluding its own trader's orders.
+    fn level_liquidity(&self, order: &Order) -> U256 {
+        let book = match order.get_side() {
+            Side::Bid => &self.ask_book,
+            Side::Ask => &self.bid_book,
+        };
+        let now = now_millis() / 1000;
+        match book.price_map.get(&order.price) {
+            Some(loc) => book.price_levels[*loc]
+                .iter()
+                .filter(|(order_id, o)| {
+                    o.traderAddress != order.traderAddress && !self.is_expired(order_id, now)
+                })
+                .fold(U256::zero(), |amount, (_, o)| amount + o.amount),
+            None => U256::zero(),
+        }
+    }
+
+    /// Append fills to the trade log and drop the ones beyond the retention.
+    fn record_trades(&mut self, fills: &[Fill]) {
+        if let Some(sender) = &self.fill_sender {
+            if fills.iter().any(|fill| sender.send(fill.clone()).is_err()) {
+                self.fill_sender = None;
+            }
+        }
+        if !fills.is_empty() {
+            // forget orders no longer found, before the remainder of the taker rests.
+            let (order_loc, archive) = (&self.order_loc, &self.archive);
+            self.filled.retain(|order_id, _| {
+                order_loc.contains_key(order_id) || archive.contains_key(order_id)
+            });
+            self.accepted.retain(|order_id, _| {
+                order_loc.contains_key(order_id)
+                    || archive.contains_key(order_id)
+                    || fills.iter().any(|fill| &fill.taker_hash == order_id)
+            });
+            for fill in fills {
+                for order_id in [&fill.maker_hash, &fill.taker_hash] {
+                    *self.filled.entry(order_id.clone()).or_default() += fill.fill_amount;
+                }
+            }
+        }
+        for fill in fills {
+            self.trades.push(fill.clone(), self.config.trade_retention);
+        }
+    }
+
+    /// Timestamp of the next fills, which keeps the trade log in time order even if the
+    /// clock goes backwards.
+    fn next_timestamp(&self) -> u64 {
+        self.trades
+            .back()
+            .map_or(now_millis(), |last| now_millis().max(last.timestamp))
+    }
+
+    /// Check that an order was signed by its trader, as represented on chain. Unsigned orders
+    /// pass unless the market requires signatures. The signed struct does not cover the order
+    /// flags, so a signed order must leave them unset lest a relayer changes them.
+    fn check_signature(&self, order: &JsonOrder) -> Result<(), OrderBookError> {
+        let signature = match &order.signature {
+            Some(signature) => signature,
+            None if self.config.require_signatures => {
+                return Err(OrderBookError::InvalidSignature("missing".to_string()))
+            }
+            None => return Ok(()),
+        };
+        if order.postOnly
+            || order.reduceOnly
+            || order.expiresAt.is_some()
+            || order.displayAmount.is_some()
+        {
+            return Err(OrderBookError::InvalidSignature(
+                "flags are not signed".to_string(),
+            ));
+        }
+        let signer = order
+            .encode_order()?
+            .with_decimals(self.config.decimals)
+            .recover_signer(signature, &self.config.domain)?;
+        if signer != order.traderAddress {
+            return Err(OrderBookError::InvalidSignature(format!(
+                "signed by {:?}",
+                signer
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bring the amount and price of a submitted order to canonical decimals and check that it
+    /// can be encoded and is signed by its trader.
+    fn normalize_order(&self, mut order: JsonOrder) -> Result<JsonOrder, OrderBookError> {
+        let separator = self.config.decimal_separator;
+        order.amount = normalize_decimal(&order.amount, separator)?;
+        order.price = normalize_decimal(&order.price, separator)?;
+        if let Some(display) = &order.displayAmount {
+            let display = normalize_decimal(display, separator)?;
+            if decimal_to_u256(&display)?.is_zero() {
+                return Err(OrderBookError::InvalidOrder);
+            }
+            order.displayAmount = Some(display);
+        }
+        order.encode_order()?;
+        self.check_signature(&order)?;
+        self.check_tick(&mut order)?;
+        if order
+            .expiresAt
+            .map_or(false, |expires_at| expires_at <= now_millis() / 1000)
+        {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        Ok(order)
+    }
+
+    /// Check that the price of a submitted order is on the tick grid, snapping it there if the
+    /// market allows it.
+    fn check_tick(&self, order: &mut JsonOrder) -> Result<(), OrderBookError> {
+        let tick_size = self.config.tick_size;
+        if tick_size.is_zero() {
+            return Ok(());
+        }
+        // the price as it is stored once the order is encoded.
+        let price = decimal_to_u256(&order.price)?;
+        let (ticks, rem) = price.div_mod(tick_size);
+        if rem.is_zero() {
+            return Ok(());
+        }
+        let snapped = match order.side {
+            Side::Bid => ticks * tick_size,
+            Side::Ask => (ticks + 1) * tick_size,
+        };
+        if self.config.off_tick_policy == OffTickPolicy::Reject
+            || order.signature.is_some()
+            || snapped.is_zero()
+        {
+            debug!("Reject order at price {} off the tick grid", order.price);
+            return Err(OrderBookError::InvalidOrder);
+        }
+        order.price = format_units(&snapped, Scale::ENGINE);
+        Ok(())
+    }
+
+    /// Book level checks of a new order, before any funds are reserved.
+    fn check_order(&self, order: &Order) -> bool {
+        if self.below_minimum(order) {
+            return false;
+        }
+        let side = order.get_side();
+        if !self.crosses(&side, &order.price) && !self.within_maker_band(&side, &order.price) {
+            debug!(
+                "Reject passive order at price {} outside of the maker band",
+                u256_to_decimal(&order.price)
+            );
+            return false;
+        }
+        if self.config.reject_locking_orders && self.locks(order) {
+            debug!(
+                "Reject order at price {} locking the book",
+                u256_to_decimal(&order.price)
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Check if a new order is smaller than the minimum amount or notional of the market.
+    pub(crate) fn below_minimum(&self, order: &Order) -> bool {
+        let notional = notional(&order.amount, &order.price, Rounding::Down).unwrap_or(U256::MAX);
+        if order.amount < self.config.min_amount || notional < self.config.min_notional {
+            debug!(
+                "Reject order of {} at price {} below the market minimum",
+                u256_to_decimal(&order.amount),
+                u256_to_decimal(&order.price)
+            );
+            return true;
+        }
+        false
+    }
+
+    /// Check if an order at the best opposite price would leave a remainder resting there.
+    fn locks(&self, order: &Order) -> bool {
+        let opposite = match order.get_side() {
+            Side::Bid => self.ask_book.best_price(),
+            Side::Ask => self.bid_book.best_price(),
+        };
+        opposite == Some(order.price) && self.level_liquidity(order) < order.amount
+    }
+
+    /// Match a validated order with hash `incoming_hash` against the book. Any remaining amount
+    /// rests if `rest_remainder`, otherwise it is cancelled.
+    fn match_order(
+        &mut self,
+        order: JsonOrder,
+        encoded_order: Order,
+        incoming_hash: Hash,
+        rest_remainder: bool,
+    ) -> FillResult {
+        let levels = match order.side {
+            Side::Bid => (Bound::Unbounded, Bound::Included(encoded_order.price)),
+            Side::Ask => (Bound::Included(encoded_order.price), Bound::Unbounded),
+        };
+        let max_fills = self.config.max_fills_per_submission.unwrap_or(usize::MAX);
+        self.match_order_within(
+            order,
+            encoded_order,
+            incoming_hash,
+            rest_remainder,
+            levels,
+            max_fills,
+        )
+    }
+
+    /// Match a validated order against the opposite levels with prices in `levels`, best price
+    /// first, stopping after `max_fills` fills.
+    fn match_order_within(
+        &mut self,
+        order: JsonOrder,
+        encoded_order: Order,
+        incoming_hash: Hash,
+        rest_remainder: bool,
+        levels: (Bound<U256>, Bound<U256>),
+        max_fills: usize,
+    ) -> FillResult {
+        debug!(
+            "Got order with amount {}, at price {}",
+            order.amount, order.price
+        );
+        let mut fill_result = FillResult::new(encoded_order.amount, order.side.clone());
+        let timestamp = self.next_timestamp();
+        let taker_seq = self.assign_sequence(&incoming_hash, timestamp);
+        // expired makers are cancelled rather than matched.
+        let expired = self.take_expired(timestamp / 1000);
+        fill_result.cancelled_orders.extend(expired);
+        let mid = self.mid();
+        // prices of the opposite levels matched against.
+        let mut visited = Vec::new();
+        // a passive order away from the touch goes straight to the book.
+        if self.crosses(&order.side, &encoded_order.price) {
+            let mut audit_log = self.config.fairness_audit.then_some(&mut self.audit_log);
+            let mut archive = self
+                .config
+                .archive_removed_orders
+                .then_some(&mut self.archive);
+            let book = match order.side {
+                Side::Bid => &mut self.ask_book,
+                Side::Ask => &mut self.bid_book,
+            };
+            let mut prices = book.price_map.range(levels);
+            // the lowest ask or the highest bid first.
+            let mut next_level = || match order.side {
+                Side::Bid => prices.next(),
+                Side::Ask => prices.next_back(),
+            };
+            while let Some((price, loc)) = next_level() {
+                // stop once filled.
+                if fill_result.remaining <= U256::from(ERROR)
+                    || fill_result.filled_orders.len() >= max_fills
+                {
+                    break;
+                }
+                Self::match_at_price_level(
+                    &mut fill_result,
+                    &mut book.price_levels[*loc],
+                    &mut self.order_loc,
+                    &mut self.trader_orders,
+                    &incoming_hash,
+                    &encoded_order,
+                    timestamp,
+                    max_fills,
+                    audit_log.as_deref_mut(),
+                    archive.as_deref_mut(),
+                );
+                visited.push(*price);
+            }
+        }
+        let opposite_book = match order.side {
+            Side::Bid => &mut self.ask_book,
+            Side::Ask => &mut self.bid_book,
+        };
+        for price in visited {
+            opposite_book.drop_empty_level(&price);
+        }
+        for fill in fill_result.filled_orders.iter() {
+            opposite_book.consume_iceberg(fill);
+        }
+        for fill in fill_result.filled_orders.iter_mut() {
+            fill.mid = mid;
+            fill.maker_seq = self
+                .accepted
+                .get(&fill.maker_hash)
+                .map_or(0, |(seq, _)| *seq);
+            fill.taker_seq = taker_seq;
+        }
+        self.cancel_maker_dust(&mut fill_result);
+        self.record_trades(&fill_result.filled_orders);
+        if fill_result.remaining > U256::from(ERROR) {
+            let remaining_decimal = u256_to_decimal(&fill_result.remaining);
+            debug!(
+                "Still remaining amount {} at price level {}",
+                remaining_decimal, order.price
+            );
+            fill_result.status = OrderStatus::PartiallyFilled;
+            let mut new_order = encoded_order.clone();
+            new_order.amount = fill_result.remaining;
+            if !rest_remainder {
+                fill_result.cancelled_orders.push(new_order);
+            } else if fill_result.filled_orders.len() >= max_fills
+                && self.crosses(&order.side, &encoded_order.price)
+            {
+                // stopped at the fill cap, the remainder cannot rest on a crossed book.
+                fill_result.cancelled_orders.push(new_order);
+            } else {
+                let order_id =
+                    self.create_new_limit_order(order.side.clone(), incoming_hash, new_order);
+                self.track_iceberg(&order, &order_id);
+                if let Some(expires_at) = order.expiresAt {
+                    self.expiries.insert(order_id, expires_at);
+                }
+            }
+        } else {
+            fill_result.status = OrderStatus::Filled;
+        }
+        fill_result
+    }
+
+    /// Resting orders at a price level of the given side, in queue order.
+    pub fn level_detail(&self, side: Side, price: &Decimal) -> Option<Vec<JsonOrder>> {
+        let book = match side {
+            Side::Bid => &self.bid_book,
+            Side::Ask => &self.ask_book,
+        };
+        let loc = book.price_map.get(&decimal_to_u256(price).ok()?)?;
+        let price_level = &book.price_levels[*loc];
+        if price_level.is_empty() {
+            return None;
+        }
+        Some(
+            price_level
+                .iter()
+                .map(|(order_id, order)| book.public_order(order_id, order))
+                .collect(),
+        )
+    }
+
+    /// Fills an order would get without changing the book or any account. With
+    /// `check_balances` the order is validated against its trader's available funds as on
+    /// submission, otherwise it is matched against the resting liquidity alone.
+    pub fn simulate_order(
+        &self,
+        manager: &AccountManager,
+        order: JsonOrder,
+        check_balances: bool,
+    ) -> Result<FillResult, OrderBookError> {
+        let mut book = self.clone();
+        // simulated fills are not trades, so subscribers must not see them.
+        book.fill_sender = None;
+        if check_balances {
+            return book.apply_replicated_order(&mut manager.clone(), order);
+        }
+        let order = book.normalize_order(order)?;
+        let encoded_order = order.encode_order()?;
+        if !book.check_order(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        let order_id = book.order_hash(&encoded_order)?;
+        Ok(book.match_order(order, encoded_order, order_id, true))
+    }
+
+    /// The resting order an incoming order of the given side and price would hit first, i.e. the
+    /// oldest order at the best opposite price if that price crosses. Self-match prevention
+    /// is not taken into account.
+    pub fn next_match(&self, side: Side, price: &Decimal) -> Option<JsonOrder> {
+        let price = decimal_to_u256(price).ok()?;
+        if !self.crosses(&side, &price) {
+            return None;
+        }
+        let book = match side {
+            Side::Bid => &self.ask_book,
+            Side::Ask => &self.bid_book,
+        };
+        let loc = book.price_map.get(&book.best_price()?)?;
+        let (_, order) = book.price_levels[*loc].first()?;
+        Some(order.to_json())
+    }
+
+    /// The least aggressive resting price of the given side of the book.
+    pub fn worst_price(&self, side: Side) -> Option<Decimal> {
+        let price = match side {
+            Side::Bid => self.bid_book.worst_price()?,
+            Side::Ask => self.ask_book.worst_price()?,
+        };
+        Some(u256_to_decimal(&price))
+    }
+
+    /// Notional an aggressive order of the given side has to trade to move the touch of the
+    /// opposite book by `ticks` price increments, i.e. the value of all resting orders priced
+    /// within `ticks` of the best opposite price. In base currency this is the amount resting
+    /// there, converted at each order's own price. `None` if the opposite book is empty or the
+    /// market has no tick size.
+    pub fn cost_to_move(&self, side: Side, ticks: u64, denom: Denomination) -> Option<Decimal> {
+        if self.config.tick_size.is_zero() {
+            return None;
+        }
+        let distance = self.config.tick_size.saturating_mul(U256::from(ticks));
+        let (book, range) = match side {
+            Side::Bid => {
+                let touch = self.ask_book.best_price()?;
+                let upper = touch.saturating_add(distance);
+                (
+                    &self.ask_book,
+                    (Bound::Included(touch), Bound::Excluded(upper)),
+                )
+            }
+            Side::Ask => {
+                let touch = self.bid_book.best_price()?;
+                let lower = touch.saturating_sub(distance);
+                (
+                    &self.bid_book,
+                    (Bound::Excluded(lower), Bound::Included(touch)),
+                )
+            }
+        };
+        let mut total = U256::zero();
+        for (price, loc) in book.price_map.range(range) {
+            for order in book.price_levels[*loc].values() {
+                total += match denom {
+                    Denomination::Base => order.amount,
+                    Denomination::Quote => {
+                        notional(&order.amount, price, Rounding::Down).unwrap_or(U256::MAX)
+                    }
+                };
+            }
+        }
+        Some(u256_to_decimal(&total))
+    }
+
+    /// Every price level of the given side with its number of resting orders and their total
+    /// volume, best priced first.
+    pub fn price_level_summary(&self, side: Side) -> Vec<(Decimal, usize, Decimal)> {
+        let book = match side {
+            Side::Bid => &self.bid_book,
+            Side::Ask => &self.ask_book,
+        };
+        let summary = |(price, loc): (&U256, &usize)| {
+            let level = &book.price_levels[*loc];
+            if level.is_empty() {
+                return None;
+            }
+            let volume = level
+                .values()
+                .fold(U256::zero(), |volume, order| volume + order.amount);
+            Some((
+                u256_to_decimal(price),
+                level.len(),
+                u256_to_decimal(&volume),
+            ))
+        };
+        match side {
+            Side::Bid => book.price_map.iter().rev().filter_map(summary).collect(),
+            Side::Ask => book.price_map.iter().filter_map(summary).collect(),
+        }
+    }
+
+    /// Resting volume of the given side grouped into `count` consecutive price buckets of
+    /// `bucket_size`, starting with the bucket of the touch and moving away from it. Buckets are
+    /// aligned to multiples of `bucket_size` and labelled with their lower bound. A malformed
+    /// or zero bucket size gives no buckets.
+    pub fn depth_buckets(
+        &self,
+        side: Side,
+        bucket_size: &Decimal,
+        count: usize,
+    ) -> Vec<(Decimal, Decimal)> {
+        let bucket_size = decimal_to_u256(bucket_size).unwrap_or_default();
+        let book = match side {
+            Side::Bid => &self.bid_book,
+            Side::Ask => &self.ask_book,
+        };
+        let touch = match book.best_price() {
+            Some(touch) if !bucket_size.is_zero() => touch,
+            _ => return Vec::new(),
+        };
+        let mut lower = touch / bucket_size * bucket_size;
+        let mut buckets = Vec::with_capacity(count);
+        for _ in 0..count {
+            let upper = lower.saturating_add(bucket_size);
+            let volume = book
+                .price_map
+                .range(lower..upper)
+                .flat_map(|(_, loc)| book.price_levels[*loc].values())
+                .fold(U256::zero(), |volume, order| volume + order.amount);
+            buckets.push((u256_to_decimal(&lower), u256_to_decimal(&volume)));
+            match side {
+                Side::Bid if lower.is_zero() => break,
+                Side::Bid => lower -= bucket_size,
+                Side::Ask => lower = upper,
+            }
+        }
+        buckets
+    }
+
+    /// Book snapshot, latest `trade_count` fills and account of `trader` for a starting client.
+    /// The caller holds the book and the accounts at once, so that they are consistent.
+    pub fn bootstrap(
+        &self,
+        manager: &AccountManager,
+        trader: &Address,
+        trade_count: usize,
+    ) -> Result<BootstrapPayload, OrderBookError> {
+        let account = match manager.get_json_account(trader) {
+            Ok(account) => Some(account),
+            Err(OrderBookError::NoSuchAccount) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(BootstrapPayload {
+            epoch: self.epoch,
+            sequence: self.sequence(),
+            book: self.generate_l2_order_book(),
+            trades: self.latest_trades(trade_count),
+            account,
+        })
+    }
+
+    /// Mean effective spread paid by takers over the fills at most `window` milliseconds older
+    /// than the latest fill, i.e. twice the distance of each fill price from the mid price when
+    /// its taker arrived. Fills into a one-sided book have no mid and are left out. Takers
+    /// always trade at maker prices here, which are never better than the mid.
+    pub fn effective_spread(&self, window: u64) -> Option<Decimal> {
+        let start = self.trades.back()?.timestamp.saturating_sub(window);
+        let spreads: Vec<U256> = self
+            .trades
+            .between(start, u64::MAX)
+            .filter_map(|fill| {
+                let mid = fill.mid?;
+                let half_spread = match fill.taker_side {
+                    Side::Bid => fill.price.saturating_sub(mid),
+                    Side::Ask => mid.saturating_sub(fill.price),
+                };
+                Some(half_spread * 2)
+            })
+            .collect();
+        if spreads.is_empty() {
+            return None;
+        }
+        let total = spreads
+            .iter()
+            .fold(U256::zero(), |total, spread| total + spread);
+        Some(u256_to_decimal(&(total / U256::from(spreads.len()))))
+    }
+
+    /// Mean of the best bid and the best ask, if both sides have orders.
+    fn mid(&self) -> Option<U256> {
+        let bid = self.bid_book.best_price()?;
+        let ask = self.ask_book.best_price()?;
+        Some((bid + ask) / 2)
+    }
+
+    /// Retained fills executed from `start` (inclusive) to `end` (exclusive), in unix milliseconds.
+    pub fn trades_in_range(&self, start: u64, end: u64) -> Vec<JsonFill> {
+        self.trades.query(start, end, usize::MAX)
+    }
+
+    /// The latest `limit` retained fills, oldest first.
+    pub fn latest_trades(&self, limit: usize) -> Vec<JsonFill> {
+        self.trades.query(0, u64::MAX, limit)
+    }
+
+    /// Candles of `interval` milliseconds over the retained fills executed from `start`
+    /// (inclusive) to `end` (exclusive), oldest first.
+    pub fn candles(&self, interval: u64, start: u64, end: u64) -> Vec<JsonCandle> {
+        let mut aggregator = CandleAggregator::new(interval);
+        aggregator.extend(self.trades.between(start, end));
+        aggregator.candles().iter().map(Candle::to_json).collect()
+    }
+
+    /// Retained fills, bounded by the market's trade retention.
+    pub fn trade_history(&self) -> &TradeHistory {
+        &self.trades
+    }
+
+    /// Highest bid price with the total amount resting at it.
+    pub fn best_bid(&self) -> Option<SimpleOrder> {
+        self.bid_book.best_level()
+    }
+
+    /// Lowest ask price with the total amount resting at it.
+    pub fn best_ask(&self) -> Option<SimpleOrder> {
+        self.ask_book.best_level()
+    }
+
+    /// Best ask price minus best bid price, if both sides have orders.
+    pub fn spread(&self) -> Option<Decimal> {
+        let bid = self.bid_book.best_price()?;
+        let ask = self.ask_book.best_price()?;
+        Some(u256_to_decimal(&ask.saturating_sub(bid)))
+    }
+
+    /// Mean of the best bid and the best ask, if both sides have orders.
+    pub fn mid_price(&self) -> Option<Decimal> {
+        self.mid().map(|mid| u256_to_decimal(&mid))
+    }
+
+    pub fn generate_l2_order_book(&self) -> L2OrderBook {
+        self.generate_l2_order_book_with_depth(L2_MAX)
+    }
+
+    /// L2 snapshot of up to `depth` price levels a side, at most `L2_DEPTH_LIMIT`.
+    pub fn generate_l2_order_book_with_depth(&self, depth: usize) -> L2OrderBook {
+        let depth = depth.min(L2_DEPTH_LIMIT);
+        let mut l2 = L2OrderBook::new();
+        let mut price_cache = self.price_cache.borrow_mut();
+        let mut price_cache = match self.config.l2_price_cache {
+            Some(capacity) => {
+                if price_cache.as_ref().map(DecimalCache::capacity) != Some(capacity) {
+                    *price_cache = Some(DecimalCache::new(capacity));
+                }
+                price_cache.as_mut()
+            }
+            None => None,
+        };
+        let tick = self.config.l2_display_tick;
+        l2.asks = self
+            .ask_book
+            .l2_orders(depth, tick, price_cache.as_deref_mut());
+        l2.bids = self.bid_book.l2_orders(depth, tick, price_cache);
+        l2
+    }
+
+    /// Every resting order up to `depth` orders a side, best priced first and in queue order
+    /// within a price level.
+    pub fn generate_l3_order_book(&self, depth: usize) -> L3OrderBook {
+        L3OrderBook {
+            asks: self
+                .ask_book
+                .resting_orders()
+                .take(depth)
+                .map(|(order_id, order)| self.ask_book.public_order(order_id, order))
+                .collect(),
+            bids: self
+                .bid_book
+                .resting_orders()
+                .take(depth)
+                .map(|(order_id, order)| self.bid_book.public_order(order_id, order))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{reserved_notional, OrphanPolicy};
+    use crate::action::ActionOutcome;
+    use crate::candles::parse_interval;
+    use crate::config::{AssetSymbols, FeeSchedule, TradeRetention};
+    use crate::error::{BalanceError, ConversionError, OrderBookError};
+    use crate::integrity::IntegrityViolation;
+    use crate::json::{Asset, JsonAccount, JsonCancel};
+    use crate::order::{default_domain, eip712_digest, market_domain};
+    use crate::peg::Peg;
+    use crate::primitive::{from_decimal, to_decimal};
+    use crate::seed::SeedConfig;
+    use crate::settlement::{NetTransfer, SettlementBatcher};
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::transaction::eip712::{EIP712Domain, Eip712};
+    use ethers::types::H256;
+    use hex;
+    use num_bigint::{BigUint, RandomBits};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn eip_712() {
         let order = Order {
             amount: U256::from(1234),
             nonce: U256::from(12),
@@ -311,7 +1971,7 @@ mod tests {
                 .parse::<Address>()
                 .expect("Failed to parse trader's address!"),
         };
-        let hash_str = order.hash_hex();
+        let hash_str = order.hash_hex().unwrap();
         assert_eq!(
             "0x15a7b83cc86b50aaa2fa0c0871d5dbaae62f116436291e976c84b034b58cb728",
             hash_str
@@ -334,6 +1994,26 @@ mod tests {
             traderAddress: "0xb794f5ea0ba39494ce839613fffba74279579268"
                 .parse::<Address>()
                 .expect("Failed to parse trader's address!"),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        }
+    }
+
+    fn new_order(side: Side, trader: &Address, amount: &str, price: &str, seed: u64) -> JsonOrder {
+        JsonOrder {
+            amount: amount.to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         }
     }
 
@@ -341,10 +2021,11 @@ mod tests {
     fn json_order() {
         let json_order = order_init(9998);
         debug!("{:}", json_order);
-        let order = json_order.encode_order();
-        let hash_str = order.hash_hex();
+        let order = json_order.encode_order().unwrap();
+        assert_eq!(order.amount, U256::exp10(18));
+        let hash_str = order.hash_hex().unwrap();
         assert_eq!(
-            "0x47f84837be59a0e7c6f9bc9af3c3e80971d8a589002dea75732137fe17ec3e1e",
+            "0x9b01bfdce518f6249e49fc55ed705f49bf720e184e2afde71d9ff5cf85b15eb5",
             hash_str
         );
     }
@@ -360,11 +2041,16 @@ mod tests {
             side: Side::Bid,
             nonce: get_nonce(1),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         order_book
             .add_order(&mut manager, alice_order.clone())
             .unwrap();
-        let hash_str = alice_order.encode_order().hash_hex();
+        let hash_str = alice_order.encode_order().unwrap().hash_hex().unwrap();
         let order = order_book.get_order(hash_str);
         assert!(order.is_ok(), "Cannot get order with EIP712 hash!");
     }
@@ -380,11 +2066,16 @@ mod tests {
             side: Side::Bid,
             nonce: get_nonce(1),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         order_book
             .add_order(&mut manager, alice_order.clone())
             .unwrap();
-        let hash_str = alice_order.encode_order().hash_hex();
+        let hash_str = alice_order.encode_order().unwrap().hash_hex().unwrap();
         let order = order_book.cancel_order(&mut manager, hash_str);
         assert!(order.is_ok(), "Cannot get order with EIP712 hash!");
     }
@@ -402,14 +2093,18 @@ mod tests {
             ddxBalance: alice_ddx.to_string(),
             usdBalance: alice_usd.to_string(),
             traderAddress: alice_addr.clone(),
+            balances: Default::default(),
+            funds: Default::default(),
         };
-        manager.add_json_account("alice", alice_json);
+        manager.add_json_account("alice", alice_json).unwrap();
         let bob_json = JsonAccount {
             ddxBalance: bob_ddx.to_string(),
             usdBalance: bob_usd.to_string(),
             traderAddress: bob_addr.clone(),
+            balances: Default::default(),
+            funds: Default::default(),
         };
-        manager.add_json_account("bob", bob_json);
+        manager.add_json_account("bob", bob_json).unwrap();
         manager
     }
 
@@ -434,18 +2129,28 @@ mod tests {
             side: Side::Bid,
             nonce: get_nonce(1),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         let bob_order = JsonOrder {
             amount: "1.0".to_string(),
             price: "8.0".to_string(),
             side: Side::Ask,
             nonce: get_nonce(2),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         // check if order book is empty.
         assert_eq!(order_book.order_loc.len(), 0);
         // check the balance of alice and bob.
@@ -470,18 +2175,28 @@ mod tests {
             side: Side::Ask,
             nonce: get_nonce(1),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         let alice_order = JsonOrder {
             amount: "0.5".to_string(),
             price: "12.0".to_string(),
             side: Side::Bid,
             nonce: get_nonce(2),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         // check if order book has a partially filled order.
         assert_eq!(order_book.order_loc.len(), 1);
         // check the balance of alice and bob.
@@ -504,36 +2219,56 @@ mod tests {
             side: Side::Bid,
             nonce: get_nonce(1),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         let bob_order = JsonOrder {
             amount: "1.0".to_string(),
             price: "10.0".to_string(),
             side: Side::Bid,
             nonce: get_nonce(2),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         let bob_order = JsonOrder {
             amount: "1.0".to_string(),
             price: "11.0".to_string(),
             side: Side::Ask,
             nonce: get_nonce(3),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         let bob_order = JsonOrder {
             amount: "2.0".to_string(),
             price: "9.0".to_string(),
             side: Side::Ask,
             nonce: get_nonce(4),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
-        manager.update_accounts(fill_result);
+        manager.update_accounts(fill_result).unwrap();
         // check if order book has a partially filled order.
         assert_eq!(order_book.order_loc.len(), 3);
         // check the balance of alice and bob.
@@ -556,10 +2291,15 @@ mod tests {
             side: Side::Bid,
             nonce: get_nonce(1),
             traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, alice_order);
         assert!(
-            fill_result.is_none(),
+            fill_result.is_err(),
             "The trader makes bids more than its available liquidation"
         );
         let bob_order = JsonOrder {
@@ -568,10 +2308,15 @@ mod tests {
             side: Side::Ask,
             nonce: get_nonce(2),
             traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
         };
         let fill_result = order_book.add_order(&mut manager, bob_order);
         assert!(
-            fill_result.is_none(),
+            fill_result.is_err(),
             "The trader makes asks more than its available liquidation"
         );
     }
@@ -597,19 +2342,5236 @@ mod tests {
                 side: Side::Bid,
                 nonce: get_nonce(1),
                 traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
             };
-            order_book.add_order(&mut manager, alice_order);
+            let _ = order_book.add_order(&mut manager, alice_order);
             let bob_order = JsonOrder {
                 amount: format!("{:.2}", rng.gen_range(0.0..10.0)),
                 price: format!("{:.2}", rng.gen_range(10.0..20.0)),
                 side: Side::Ask,
                 nonce: get_nonce(2),
                 traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
             };
-            order_book.add_order(&mut manager, bob_order);
+            let _ = order_book.add_order(&mut manager, bob_order);
         }
         let l2_book = order_book.generate_l2_order_book();
         assert!(l2_book.asks.len() <= 50);
         assert!(l2_book.bids.len() <= 50);
     }
+
+    #[test]
+    fn passive_order_rests_without_matching() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (i, price) in ["11.0", "12.0", "13.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "5.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(10),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let encoded = alice_order.encode_order().unwrap();
+        // the bid is below the best ask so the opposite book is never walked.
+        assert!(!order_book.crosses(&Side::Bid, &encoded.price));
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        assert!(fill_result.filled_orders.is_empty());
+        assert!(order_book.get_order(encoded.hash_hex().unwrap()).is_ok());
+        assert_eq!(order_book.order_loc.len(), 4);
+    }
+
+    #[test]
+    fn cost_to_move() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().tick_size = U256::exp10(18);
+        assert!(order_book
+            .cost_to_move(Side::Bid, 2, Denomination::Quote)
+            .is_none());
+        for (i, (amount, price)) in [
+            ("1.0", "10.0"),
+            ("2.0", "11.0"),
+            ("1.0", "12.0"),
+            ("5.0", "13.0"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let bob_order = JsonOrder {
+                amount: amount.to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // moving the best ask from 10 to 12 consumes the 10 and 11 levels.
+        assert_eq!(
+            order_book
+                .cost_to_move(Side::Bid, 2, Denomination::Quote)
+                .unwrap(),
+            "32.00"
+        );
+        assert_eq!(
+            order_book
+                .cost_to_move(Side::Bid, 0, Denomination::Quote)
+                .unwrap(),
+            "0.00"
+        );
+        // the same levels hold 3 DDX.
+        assert_eq!(
+            order_book
+                .cost_to_move(Side::Bid, 2, Denomination::Base)
+                .unwrap(),
+            "3.00"
+        );
+        assert!(order_book
+            .cost_to_move(Side::Ask, 2, Denomination::Quote)
+            .is_none());
+    }
+
+    #[test]
+    fn seed_around() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "0.0",
+            "0.0",
+            &bob_address,
+            "100.0",
+            "10000.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let config = SeedConfig {
+            trader: bob_address,
+            levels: 5,
+            spread: 1.0,
+            step: 0.5,
+            size: 2.0,
+            size_decay: 0.5,
+        };
+        assert_eq!(order_book.seed_around(&mut manager, 100.0, &config), 10);
+        assert_eq!(order_book.bid_book.price_map.len(), 5);
+        assert_eq!(order_book.ask_book.price_map.len(), 5);
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.bids[0].price, "99.50");
+        assert_eq!(l2_book.asks[0].price, "100.50");
+        assert_eq!(l2_book.bids[4].price, "97.50");
+        assert_eq!(l2_book.asks[4].price, "102.50");
+        assert_eq!(l2_book.asks[0].amount, "2.00");
+        assert_eq!(l2_book.asks[1].amount, "1.00");
+        assert_eq!(l2_book.bids[1].amount, "1.00");
+    }
+
+    #[test]
+    fn worst_price() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        assert!(order_book.worst_price(Side::Bid).is_none());
+        let orders = [
+            ("11.0", Side::Ask, &bob_address),
+            ("12.0", Side::Ask, &bob_address),
+            ("13.0", Side::Ask, &bob_address),
+            ("8.0", Side::Bid, &alice_address),
+            ("9.0", Side::Bid, &alice_address),
+        ];
+        for (i, (price, side, trader)) in orders.iter().enumerate() {
+            let order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: side.clone(),
+                nonce: get_nonce(i as u64),
+                traderAddress: (*trader).clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, order).unwrap();
+        }
+        assert_eq!(order_book.worst_price(Side::Ask).unwrap(), "13.00");
+        assert_eq!(order_book.worst_price(Side::Bid).unwrap(), "8.00");
+        // the emptied 13.0 level is skipped.
+        let worst_ask = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "13.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let hash_str = worst_ask.encode_order().unwrap().hash_hex().unwrap();
+        order_book.cancel_order(&mut manager, hash_str).unwrap();
+        assert_eq!(order_book.worst_price(Side::Ask).unwrap(), "12.00");
+    }
+
+    #[test]
+    fn orphaned_fill_settles_against_clearing_account() {
+        let (alice_address, bob_address) = address_init();
+        let clearing_address = "0x00000000000000000000000000000000000000cc"
+            .parse::<Address>()
+            .expect("Failed to parse trader's address!");
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        manager.set_orphan_policy(OrphanPolicy::Clearing(clearing_address));
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        // bob leaves while his ask is still resting.
+        manager.delete_account(&bob_address).unwrap();
+        let clearing_json = manager.get_json_account(&clearing_address).unwrap();
+        assert_eq!(clearing_json.ddxBalance, "1.00");
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(2),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        // 1 DDX and 10 USD are still accounted for.
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "1.00");
+        assert_eq!(alice_json.usdBalance, "0.00");
+        let clearing_json = manager.get_json_account(&clearing_address).unwrap();
+        assert_eq!(clearing_json.ddxBalance, "0.00");
+        assert_eq!(clearing_json.usdBalance, "10.00");
+    }
+
+    #[test]
+    fn maker_price_band() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().maker_price_band_bps = Some(500);
+        let mut alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(1),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // the first order has no touch to be compared with.
+        assert!(order_book
+            .add_order(&mut manager, alice_order.clone())
+            .is_ok());
+        alice_order.price = "5.0".to_string();
+        alice_order.nonce = get_nonce(2);
+        assert!(order_book
+            .add_order(&mut manager, alice_order.clone())
+            .is_err());
+        alice_order.price = "9.8".to_string();
+        alice_order.nonce = get_nonce(3);
+        assert!(order_book.add_order(&mut manager, alice_order).is_ok());
+        // an ask on the empty side is compared with the best bid.
+        let mut bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "20.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(4),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        assert!(order_book
+            .add_order(&mut manager, bob_order.clone())
+            .is_err());
+        bob_order.price = "10.3".to_string();
+        assert!(order_book.add_order(&mut manager, bob_order).is_ok());
+        assert_eq!(order_book.order_loc.len(), 3);
+        assert_eq!(
+            manager.get_json_account(&alice_address).unwrap().usdBalance,
+            "30.00"
+        );
+    }
+
+    #[test]
+    fn trades_in_range() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(1),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(order_book.trades.len(), 1);
+        assert_eq!(order_book.trades_in_range(0, u64::MAX).len(), 1);
+        // replace the log with fills on both sides of the range boundaries.
+        let fill = order_book.trades.iter().next().unwrap().clone();
+        order_book.trades = [100, 200, 300]
+            .iter()
+            .map(|timestamp| Fill {
+                timestamp: *timestamp,
+                ..fill.clone()
+            })
+            .collect();
+        let trades = order_book.trades_in_range(150, 300);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].timestamp, 200);
+        assert_eq!(order_book.trades_in_range(100, 301).len(), 3);
+        assert!(order_book.trades_in_range(301, 400).is_empty());
+        assert!(order_book.trades_in_range(300, 100).is_empty());
+    }
+
+    #[test]
+    fn reserve_then_commit() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(2),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let token = order_book.reserve(&mut manager, alice_order).unwrap();
+        // funds are held but nothing is matched yet.
+        assert_eq!(order_book.order_loc.len(), 1);
+        assert!(order_book.reserve(&mut manager, order_init(3)).is_none());
+        let fill_result = order_book.commit(&mut manager, token).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        manager.update_accounts(fill_result).unwrap();
+        assert_eq!(order_book.order_loc.len(), 0);
+        assert!(order_book.commit(&mut manager, token).is_none());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "1.00");
+        assert_eq!(alice_json.usdBalance, "0.00");
+    }
+
+    #[test]
+    fn reserve_then_abort() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let alice_order = order_init(2);
+        let token = order_book.reserve(&mut manager, alice_order).unwrap();
+        // the whole balance is held, so a second order can't be reserved.
+        assert!(order_book.reserve(&mut manager, order_init(3)).is_none());
+        assert!(order_book.abort(&mut manager, token).is_some());
+        assert!(order_book.abort(&mut manager, token).is_none());
+        assert!(order_book.commit(&mut manager, token).is_none());
+        assert_eq!(order_book.order_loc.len(), 1);
+        assert!(order_book.trades.is_empty());
+        // released funds can be used again.
+        let token = order_book.reserve(&mut manager, order_init(3)).unwrap();
+        assert!(order_book.abort(&mut manager, token).is_some());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "0.00");
+        assert_eq!(alice_json.usdBalance, "10.00");
+    }
+
+    #[test]
+    fn level_detail_in_queue_order() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "0.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut nonces = Vec::new();
+        for (seed, price) in [(7, "10.0"), (3, "11.0"), (5, "10.0"), (1, "10.0")] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            if price == "10.0" {
+                nonces.push(bob_order.encode_order().unwrap().nonce);
+            }
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let queue = order_book
+            .level_detail(Side::Ask, &"10.0".to_string())
+            .unwrap();
+        let queued_nonces: Vec<U256> = queue
+            .iter()
+            .map(|o| o.encode_order().unwrap().nonce)
+            .collect();
+        assert_eq!(queued_nonces, nonces);
+        assert!(order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .is_none());
+        assert!(order_book
+            .level_detail(Side::Ask, &"12.0".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn reserved_notional_rounding() {
+        // overflowing notional is rejected instead of saturated.
+        assert!(reserved_notional(&U256::MAX, &U256::from(2)).is_none());
+        // 3 wei at 0.5 is 1.5 wei which is held as 2 wei.
+        let half = U256::exp10(17) * 5;
+        assert_eq!(
+            reserved_notional(&U256::from(3), &half).unwrap(),
+            U256::from(2)
+        );
+        assert_eq!(reserved_notional(&U256::exp10(18), &half).unwrap(), half);
+    }
+
+    #[test]
+    fn open_order_count() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        let mut alice_orders = Vec::new();
+        for (i, price) in ["10.0", "9.0", "8.0"].iter().enumerate() {
+            let alice_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(i as u64),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            alice_orders.push(alice_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        assert_eq!(order_book.open_order_count(&alice_address), 3);
+        order_book
+            .cancel_order(&mut manager, alice_orders[2].clone())
+            .unwrap();
+        assert_eq!(order_book.open_order_count(&alice_address), 2);
+        // bob fully fills the 10.0 bid and partially fills the 9.0 bid.
+        let bob_order = JsonOrder {
+            amount: "1.5".to_string(),
+            price: "9.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(10),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        assert_eq!(order_book.open_order_count(&alice_address), 1);
+        assert_eq!(order_book.open_order_count(&bob_address), 0);
+    }
+
+    fn maker_dust_case(policy: MakerDustPolicy) -> (OrderBook, AccountManager, Hash) {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().min_amount = U256::exp10(17) * 5;
+        order_book.config_mut().maker_dust_policy = policy;
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let bob_hash = bob_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        // leave 0.25 of bob's ask, below the 0.5 minimum.
+        let alice_order = JsonOrder {
+            amount: "0.75".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(2),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        (order_book, manager, bob_hash)
+    }
+
+    fn dust_ask(bob_address: &Address) -> JsonOrder {
+        JsonOrder {
+            amount: "0.2".to_string(),
+            price: "11.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(3),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        }
+    }
+
+    #[test]
+    fn maker_dust_leave() {
+        let (_, bob_address) = address_init();
+        let (mut order_book, mut manager, bob_hash) = maker_dust_case(MakerDustPolicy::Leave);
+        let bob_order = order_book.get_order(bob_hash).unwrap();
+        assert_eq!(bob_order.amount, "0.25");
+        // the remainder still holds bob's DDX.
+        order_book.config_mut().min_amount = U256::zero();
+        assert!(order_book
+            .add_order(&mut manager, dust_ask(&bob_address))
+            .is_err());
+    }
+
+    #[test]
+    fn maker_dust_cancel() {
+        let (_, bob_address) = address_init();
+        let (mut order_book, mut manager, bob_hash) = maker_dust_case(MakerDustPolicy::Cancel);
+        assert!(order_book.get_order(bob_hash).is_err());
+        assert_eq!(order_book.order_loc.len(), 0);
+        assert_eq!(order_book.open_order_count(&bob_address), 0);
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob_json.ddxBalance, "0.25");
+        assert_eq!(bob_json.usdBalance, "7.50");
+        // the released DDX can be used again.
+        order_book.config_mut().min_amount = U256::zero();
+        assert!(order_book
+            .add_order(&mut manager, dust_ask(&bob_address))
+            .is_ok());
+    }
+
+    #[test]
+    fn asset_symbol_labels() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        manager.set_asset_symbols(AssetSymbols {
+            base: "ETH".to_string(),
+            quote: "USDC".to_string(),
+        });
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob_json.balances.len(), 2);
+        assert_eq!(bob_json.balances["ETH"], "1.00");
+        assert_eq!(bob_json.balances["USDC"], "0.00");
+        // legacy fields are still reported.
+        assert_eq!(bob_json.ddxBalance, "1.00");
+        let value = serde_json::to_value(&bob_json).unwrap();
+        assert_eq!(value["balances"]["ETH"], "1.00");
+        assert_eq!(value["usdBalance"], "0.00");
+    }
+
+    #[test]
+    fn next_match() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (seed, price) in [(1, "11.0"), (2, "10.0"), (3, "10.0")] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        assert!(order_book
+            .next_match(Side::Bid, &"9.0".to_string())
+            .is_none());
+        let first = order_book
+            .next_match(Side::Bid, &"11.0".to_string())
+            .unwrap();
+        assert_eq!(first.price, "10.00");
+        assert_eq!(first.nonce, get_nonce(2));
+        // the query does not touch the book.
+        assert_eq!(
+            order_book
+                .level_detail(Side::Ask, &"10.0".to_string())
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(order_book
+            .next_match(Side::Ask, &"1.0".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn order_hash_with_market_decimals() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().decimals = 6;
+        let alice_order = JsonOrder {
+            amount: "1.5".to_string(),
+            price: "2.25".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(1),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // the contract signs 1.5 @ 2.25 as 1500000 @ 2250000.
+        let mut onchain_order = alice_order.encode_order().unwrap();
+        onchain_order.amount = U256::from(1_500_000u64);
+        onchain_order.price = U256::from(2_250_000u64);
+        let digest = onchain_order.hash_hex().unwrap();
+        assert_ne!(
+            digest,
+            alice_order.encode_order().unwrap().hash_hex().unwrap()
+        );
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let resting = order_book.get_order(digest).unwrap();
+        assert_eq!(resting.amount, "1.50");
+        assert_eq!(resting.price, "2.25");
+    }
+
+    #[test]
+    fn l2_after_emptying_best_level() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut ask_hashes = Vec::new();
+        for (seed, price) in [(1, "10.0"), (2, "11.0")] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            ask_hashes.push(bob_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // empty the best ask level.
+        order_book
+            .cancel_order(&mut manager, ask_hashes[0].clone())
+            .unwrap();
+        order_book.add_order(&mut manager, order_init(3)).unwrap();
+        let l2 = order_book.generate_l2_order_book();
+        assert_eq!(l2.asks.len(), 1);
+        assert_eq!(l2.asks[0].price, "11.00");
+        assert_eq!(l2.asks[0].amount, "1.00");
+        assert_eq!(l2.bids.len(), 1);
+        assert_eq!(l2.bids[0].price, "10.00");
+    }
+
+    #[test]
+    fn replace_book() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_order = order_init(1);
+        let old_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let snapshot = vec![
+            JsonOrder {
+                amount: "1.0".to_string(),
+                price: "15.0".to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(2),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            },
+            JsonOrder {
+                amount: "2.0".to_string(),
+                price: "16.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(3),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            },
+        ];
+        let new_hashes: Vec<Hash> = snapshot
+            .iter()
+            .map(|o| o.encode_order().unwrap().hash_hex().unwrap())
+            .collect();
+        let sequence = order_book.sequence();
+        order_book.replace_book(&mut manager, snapshot).unwrap();
+        assert!(order_book.get_order(old_hash).is_err());
+        // the swap takes a single sequence number.
+        assert_eq!(order_book.sequence(), sequence + 1);
+        for hash in new_hashes {
+            assert_eq!(order_book.get_order_status(hash).unwrap().seq, sequence + 1);
+        }
+        assert_eq!(
+            order_book
+                .level_detail(Side::Bid, &"15.0".to_string())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .is_none());
+        // alice's 10 USD hold is released and 15 USD reserved, leaving room for 5 USD only.
+        let mut too_big = order_init(4);
+        too_big.price = "6.0".to_string();
+        assert!(order_book.add_order(&mut manager, too_big).is_err());
+        let mut fits = order_init(6);
+        fits.price = "5.0".to_string();
+        assert!(order_book.add_order(&mut manager, fits).is_ok());
+        // bob's DDX is all held by the snapshot ask.
+        let bob_ask = JsonOrder {
+            amount: "0.5".to_string(),
+            price: "17.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(7),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        assert!(order_book.add_order(&mut manager, bob_ask).is_err());
+    }
+
+    #[test]
+    fn replace_book_is_atomic() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_order = order_init(1);
+        let old_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        // the ask crosses the snapshot bid.
+        let snapshot = vec![
+            order_init(2),
+            JsonOrder {
+                amount: "1.0".to_string(),
+                price: "9.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(3),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            },
+        ];
+        assert!(order_book.replace_book(&mut manager, snapshot).is_err());
+        assert!(order_book.get_order(old_hash).is_ok());
+        assert_eq!(order_book.open_order_count(&alice_address), 1);
+        // alice's funds are still held by the original bid.
+        assert!(order_book.add_order(&mut manager, order_init(4)).is_err());
+    }
+
+    #[test]
+    fn read_only_mode() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_order = order_init(1);
+        let alice_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        order_book.set_read_only(true);
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, bob_order.clone())
+                .unwrap_err(),
+            OrderBookError::ReadOnly
+        );
+        assert_eq!(
+            order_book
+                .cancel_order(&mut manager, alice_hash.clone())
+                .unwrap_err(),
+            OrderBookError::ReadOnly
+        );
+        assert!(order_book.reserve(&mut manager, order_init(3)).is_none());
+        assert!(order_book.get_order(alice_hash.clone()).is_ok());
+        // replicated events still apply.
+        let fill_result = order_book
+            .apply_replicated_order(&mut manager, bob_order)
+            .unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        manager.update_accounts(fill_result).unwrap();
+        assert!(order_book.get_order(alice_hash).is_err());
+        let alice_order = order_init(4);
+        let alice_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book
+            .apply_replicated_order(&mut manager, alice_order)
+            .unwrap();
+        assert!(order_book
+            .apply_replicated_cancel(&mut manager, alice_hash.clone())
+            .is_ok());
+        assert!(order_book.get_order(alice_hash).is_err());
+    }
+
+    #[test]
+    fn duplicate_order_hash() {
+        for policy in [
+            DuplicateOrderPolicy::Reject,
+            DuplicateOrderPolicy::Idempotent,
+        ] {
+            let (alice_address, bob_address) = address_init();
+            let mut manager =
+                account_init(&alice_address, "0.0", "20.0", &bob_address, "0.0", "0.0");
+            let mut order_book = OrderBook::new("DDX".to_string());
+            order_book.config_mut().duplicate_order_policy = policy;
+            let alice_order = order_init(1);
+            let alice_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+            order_book
+                .add_order(&mut manager, alice_order.clone())
+                .unwrap();
+            let retried = order_book.add_order(&mut manager, alice_order.clone());
+            match policy {
+                DuplicateOrderPolicy::Reject => {
+                    assert_eq!(retried.unwrap_err(), OrderBookError::DuplicateOrder)
+                }
+                DuplicateOrderPolicy::Idempotent => {
+                    let fill_result = retried.unwrap();
+                    assert!(fill_result.filled_orders.is_empty());
+                    manager.update_accounts(fill_result).unwrap();
+                }
+            }
+            assert!(order_book.reserve(&mut manager, alice_order).is_none());
+            assert_eq!(order_book.open_order_count(&alice_address), 1);
+            assert_eq!(order_book.get_order(alice_hash).unwrap().amount, "1.00");
+            // only 10 of alice's 20 USD are held.
+            assert!(order_book.add_order(&mut manager, order_init(2)).is_ok());
+            assert!(order_book.add_order(&mut manager, order_init(3)).is_err());
+        }
+    }
+
+    #[test]
+    fn fairness_audit() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().fairness_audit = true;
+        let mut ask_hashes = Vec::new();
+        for seed in 1..=3 {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: "10.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            ask_hashes.push(bob_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let mut alice_order = order_init(4);
+        alice_order.amount = "1.5".to_string();
+        let alice_hash = alice_order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let audit_log = order_book.audit_log();
+        assert_eq!(audit_log.len(), 2);
+        assert_eq!(audit_log[0].incoming_hash, alice_hash);
+        assert_eq!(audit_log[0].price, "10.00");
+        assert_eq!(audit_log[0].queue, ask_hashes);
+        assert_eq!(audit_log[0].selected_hash, ask_hashes[0]);
+        assert_eq!(audit_log[0].basis, PriorityBasis::Fifo { position: 0 });
+        // the fully filled head left the queue before the next fill.
+        assert_eq!(audit_log[1].queue, ask_hashes[1..].to_vec());
+        assert_eq!(audit_log[1].selected_hash, ask_hashes[1]);
+        assert_eq!(audit_log[1].basis, PriorityBasis::Fifo { position: 0 });
+    }
+
+    #[test]
+    fn normalize_decimal_input() {
+        assert_eq!(normalize_decimal(" 1.5\n", '.').unwrap(), "1.5");
+        assert_eq!(normalize_decimal("10", '.').unwrap(), "10");
+        assert_eq!(normalize_decimal("1,25", ',').unwrap(), "1.25");
+        // a comma is a thousands separator unless configured as the decimal one.
+        for (input, separator) in [
+            ("1,000.5", '.'),
+            ("1,000", '.'),
+            ("1.000,5", ','),
+            ("1 000", '.'),
+        ] {
+            assert_eq!(
+                normalize_decimal(input, separator).unwrap_err(),
+                OrderBookError::InvalidDecimal(format!(
+                    "{:?}: thousands separators are not accepted",
+                    input
+                ))
+            );
+        }
+        for input in ["", "  ", "1.2.3", ".5", "5.", "-1", "1e3", "abc"] {
+            assert!(normalize_decimal(input, '.').is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn order_with_decimal_comma() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut alice_order = order_init(1);
+        alice_order.amount = " 0,5 ".to_string();
+        alice_order.price = "10,0".to_string();
+        assert!(matches!(
+            order_book.add_order(&mut manager, alice_order.clone()),
+            Err(OrderBookError::InvalidDecimal(_))
+        ));
+        order_book.config_mut().decimal_separator = ',';
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let resting = order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .unwrap();
+        assert_eq!(resting[0].amount, "0.50");
+    }
+
+    #[test]
+    fn depth_buckets() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (seed, amount, price) in [(1, "1.0", "10.5"), (2, "2.0", "11.5"), (3, "3.0", "12.0")] {
+            let bob_order = JsonOrder {
+                amount: amount.to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        for (seed, amount, price) in [(4, "1.0", "9.5"), (5, "0.5", "8.0")] {
+            let alice_order = JsonOrder {
+                amount: amount.to_string(),
+                price: price.to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(seed),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        let bucket_size = "2.0".to_string();
+        // asks from 10.5 fall in [10, 12) and [12, 14).
+        let asks = order_book.depth_buckets(Side::Ask, &bucket_size, 2);
+        assert_eq!(
+            asks,
+            vec![
+                ("10.00".to_string(), "3.00".to_string()),
+                ("12.00".to_string(), "3.00".to_string())
+            ]
+        );
+        // bids from 9.5 fall in [8, 10) and nothing below.
+        let bids = order_book.depth_buckets(Side::Bid, &bucket_size, 2);
+        assert_eq!(
+            bids,
+            vec![
+                ("8.00".to_string(), "1.50".to_string()),
+                ("6.00".to_string(), "0.00".to_string())
+            ]
+        );
+        assert!(order_book
+            .depth_buckets(Side::Ask, &"0".to_string(), 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn match_at_exact_price_boundary() {
+        let (alice_address, bob_address) = address_init();
+        // a bid at exactly the sole ask price fully matches.
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let fill_result = order_book.add_order(&mut manager, order_init(2)).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        assert!(order_book.order_loc.is_empty());
+        // an ask at exactly the sole bid price fully matches.
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.add_order(&mut manager, order_init(3)).unwrap();
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(4),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        assert!(order_book.order_loc.is_empty());
+    }
+
+    #[test]
+    fn match_through_last_eligible_level() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "4.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (seed, price) in [(1, "9.0"), (2, "10.0"), (3, "11.0")] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // the 10 level is the last one within the limit and must be reached.
+        let mut alice_order = order_init(4);
+        alice_order.amount = "3.0".to_string();
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        let prices: Vec<String> = fill_result
+            .filled_orders
+            .iter()
+            .map(|fill| u256_to_decimal(&fill.price))
+            .collect();
+        assert_eq!(prices, vec!["9.00", "10.00"]);
+        assert_eq!(u256_to_decimal(&fill_result.remaining), "1.00");
+        assert_eq!(order_book.worst_price(Side::Ask).unwrap(), "11.00");
+        assert!(order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .is_some());
+        let mut alice_order = order_init(6);
+        alice_order.price = "8.0".to_string();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        // once filled at 10 the traversal stops without a zero amount fill at 8.
+        let bob_order = JsonOrder {
+            amount: "0.5".to_string(),
+            price: "8.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(5),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert!(!fill_result.filled_orders[0].fill_amount.is_zero());
+    }
+
+    #[test]
+    fn all_or_none_at_price() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (seed, price) in [(1, "9.0"), (2, "10.0"), (3, "10.0")] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // the 10 level only holds 2 DDX.
+        let mut alice_order = order_init(4);
+        alice_order.amount = "3.0".to_string();
+        assert_eq!(
+            order_book
+                .add_order_all_or_none(&mut manager, alice_order)
+                .unwrap_err(),
+            OrderBookError::InsufficientLiquidity
+        );
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        assert_eq!(order_book.open_order_count(&bob_address), 3);
+        let mut alice_order = order_init(5);
+        alice_order.amount = "2.0".to_string();
+        let fill_result = order_book
+            .add_order_all_or_none(&mut manager, alice_order)
+            .unwrap();
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        for fill in fill_result.filled_orders.iter() {
+            assert_eq!(u256_to_decimal(&fill.price), "10.00");
+        }
+        manager.update_accounts(fill_result).unwrap();
+        // the better priced 9 level is left alone.
+        assert_eq!(order_book.worst_price(Side::Ask).unwrap(), "9.00");
+        assert_eq!(order_book.open_order_count(&bob_address), 1);
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "2.00");
+        assert_eq!(alice_json.usdBalance, "20.00");
+    }
+
+    #[test]
+    fn malformed_nonce_is_rejected() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for nonce in ["1234", "0xzz", &format!("0x{}", "00".repeat(33))] {
+            let mut alice_order = order_init(1);
+            alice_order.nonce = nonce.to_string();
+            assert!(matches!(
+                order_book.add_order(&mut manager, alice_order.clone()),
+                Err(OrderBookError::Eip712Encoding(_))
+            ));
+            assert!(order_book.reserve(&mut manager, alice_order).is_none());
+        }
+        // no funds were held by the rejected orders.
+        assert!(order_book.add_order(&mut manager, order_init(2)).is_ok());
+    }
+
+    fn trade_once(order_book: &mut OrderBook, seed: u64) {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        order_book
+            .add_order(&mut manager, order_init(seed))
+            .unwrap();
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(seed + 1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+    }
+
+    #[test]
+    fn trade_retention() {
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().trade_retention = TradeRetention::MaxEntries(2);
+        let mut maker_hashes = Vec::new();
+        for seed in [1, 3, 5] {
+            trade_once(&mut order_book, seed);
+            maker_hashes.push(order_book.trades.back().unwrap().maker_hash.clone());
+        }
+        assert_eq!(order_book.trades.len(), 2);
+        let trades = order_book.trades_in_range(0, u64::MAX);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_hash, maker_hashes[1]);
+        assert_eq!(trades[1].maker_hash, maker_hashes[2]);
+
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().trade_retention = TradeRetention::MaxAgeMillis(60_000);
+        trade_once(&mut order_book, 1);
+        let fill = order_book.trades.iter().next().unwrap().clone();
+        let latest = fill.timestamp;
+        order_book.trades = [latest - 120_000, latest - 30_000]
+            .iter()
+            .map(|timestamp| Fill {
+                timestamp: *timestamp,
+                ..fill.clone()
+            })
+            .collect();
+        // the next fill expires the one older than a minute.
+        trade_once(&mut order_book, 3);
+        let timestamps: Vec<u64> = order_book
+            .trades
+            .iter()
+            .map(|fill| fill.timestamp)
+            .collect();
+        assert_eq!(timestamps[0], latest - 30_000);
+        assert_eq!(timestamps.len(), 2);
+        assert_eq!(
+            order_book.trades_in_range(latest - 40_000, u64::MAX).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn transfer() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let five = decimal_to_u256(&"5.0".to_string()).unwrap();
+        manager
+            .transfer(&alice_address, &bob_address, Asset::Usd, five)
+            .unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "5.00");
+        assert_eq!(bob_json.usdBalance, "5.00");
+        assert_eq!(bob_json.ddxBalance, "1.00");
+        // more than the available balance.
+        let two = decimal_to_u256(&"2.0".to_string()).unwrap();
+        assert_eq!(
+            manager
+                .transfer(&bob_address, &alice_address, Asset::Ddx, two)
+                .unwrap_err(),
+            OrderBookError::InsufficientFunds
+        );
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob_json.ddxBalance, "1.00");
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "0.00");
+    }
+
+    #[test]
+    fn transfer_blocked_by_held_funds() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut alice_order = order_init(1);
+        alice_order.price = "8.0".to_string();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        // 8 of alice's 10 USD back her bid.
+        let three = decimal_to_u256(&"3.0".to_string()).unwrap();
+        assert!(manager
+            .transfer(&alice_address, &bob_address, Asset::Usd, three)
+            .is_err());
+        let one = decimal_to_u256(&"1.0".to_string()).unwrap();
+        manager
+            .transfer(&alice_address, &bob_address, Asset::Usd, one)
+            .unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "9.00");
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob_json.usdBalance, "1.00");
+        // a credit beyond the largest amount moves nothing.
+        manager
+            .deposit(&bob_address, Asset::Usd, U256::MAX - one)
+            .unwrap();
+        assert_eq!(
+            manager
+                .transfer(&alice_address, &bob_address, Asset::Usd, one)
+                .unwrap_err(),
+            OrderBookError::Balance(BalanceError::Overflow(Asset::Usd))
+        );
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "9.00");
+        assert_eq!(manager.accounts[&bob_address].total_usd(), Ok(U256::MAX));
+        // nor can the held funds count beyond it.
+        manager
+            .deposit(&alice_address, Asset::Usd, U256::MAX - one)
+            .unwrap();
+        assert_eq!(
+            manager.accounts[&alice_address].total_usd(),
+            Err(BalanceError::Overflow(Asset::Usd))
+        );
+        assert_eq!(
+            manager.get_json_account(&alice_address).unwrap_err(),
+            OrderBookError::Balance(BalanceError::Overflow(Asset::Usd))
+        );
+        assert!(manager.balances_merkle_root().is_err());
+        assert!(manager.accounts_above(Asset::Usd, U256::zero()).is_err());
+        assert!(manager.delete_account(&alice_address).is_err());
+        assert!(manager.accounts.contains_key(&alice_address));
+    }
+
+    #[test]
+    fn pegged_bid_follows_best_bid() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "0.0", "30.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().tick_size = U256::exp10(18);
+        let bob_bid = |nonce: u64, price: &str| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(nonce),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "0.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(3),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let peg = Peg {
+            reference: Side::Bid,
+            offset_ticks: -1,
+        };
+        // nothing to peg to on an empty book.
+        assert!(matches!(
+            order_book.add_pegged_order(&mut manager, alice_order.clone(), peg.clone()),
+            Err(OrderBookError::InvalidOrder)
+        ));
+        order_book
+            .add_order(&mut manager, bob_bid(1, "10.0"))
+            .unwrap();
+        let alice_id = order_book
+            .add_pegged_order(&mut manager, alice_order, peg)
+            .unwrap();
+        assert_eq!(
+            order_book.get_order(alice_id.clone()).unwrap().price,
+            "9.00"
+        );
+        // a better bid drags the pegged bid up a level.
+        let better = bob_bid(2, "11.0");
+        let better_id = better.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, better).unwrap();
+        assert_eq!(
+            order_book.get_order(alice_id.clone()).unwrap().price,
+            "10.00"
+        );
+        assert!(order_book
+            .level_detail(Side::Bid, &"9.0".to_string())
+            .is_none());
+        let level = order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .unwrap();
+        assert_eq!(level.len(), 2);
+        assert_eq!(level[1].traderAddress, alice_address);
+        // and falls back once the better bid is cancelled.
+        order_book.cancel_order(&mut manager, better_id).unwrap();
+        assert_eq!(order_book.get_order(alice_id).unwrap().price, "9.00");
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "20.00");
+    }
+
+    #[test]
+    fn fill_cap_per_submission() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().max_fills_per_submission = Some(2);
+        for (i, price) in ["10.0", "11.0", "12.0", "20.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // the sweep stops after two fills, the remainder would cross 12 so it is cancelled.
+        let sweep = JsonOrder {
+            amount: "4.0".to_string(),
+            price: "15.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(10),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let sweep_id = sweep.encode_order().unwrap().hash_hex().unwrap();
+        let fill_result = order_book.add_order(&mut manager, sweep).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        assert_eq!(fill_result.cancelled_orders.len(), 1);
+        assert_eq!(fill_result.cancelled_orders[0].to_json().amount, "2.00");
+        assert!(order_book.get_order(sweep_id).is_err());
+        manager.update_accounts(fill_result).unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "2.00");
+        assert_eq!(alice_json.usdBalance, "79.00");
+        // a remainder beyond the next level rests as usual.
+        let bid = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "12.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(11),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let bid_id = bid.encode_order().unwrap().hash_hex().unwrap();
+        let fill_result = order_book.add_order(&mut manager, bid).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert!(fill_result.cancelled_orders.is_empty());
+        assert_eq!(order_book.get_order(bid_id).unwrap().amount, "1.00");
+    }
+
+    #[test]
+    fn balances_merkle_proof() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "1.0", "10.0", &bob_address, "2.0", "5.0");
+        let carol_address = Address::random();
+        manager.new_account("carol", carol_address);
+        let root = manager.balances_merkle_root().unwrap();
+        for address in [alice_address, bob_address, carol_address] {
+            assert!(manager
+                .balance_proof(&address)
+                .unwrap()
+                .unwrap()
+                .verify(&root));
+        }
+        let mut proof = manager.balance_proof(&bob_address).unwrap().unwrap();
+        proof.usd_balance += U256::one();
+        assert!(!proof.verify(&root));
+        assert!(manager.balance_proof(&Address::random()).unwrap().is_none());
+        // the root commits to held funds as well.
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.add_order(&mut manager, order_init(1)).unwrap();
+        assert_eq!(manager.balances_merkle_root().unwrap(), root);
+    }
+
+    #[test]
+    fn simulate_order_with_and_without_balances() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (i, price) in ["10.0", "11.0", "12.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let sweep = JsonOrder {
+            amount: "3.0".to_string(),
+            price: "12.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(10),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // alice cannot fund the sweep, but the book could fill it.
+        assert!(matches!(
+            order_book.simulate_order(&manager, sweep.clone(), true),
+            Err(OrderBookError::InsufficientFunds)
+        ));
+        let fill_result = order_book.simulate_order(&manager, sweep, false).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 3);
+        assert!(fill_result.remaining <= U256::from(ERROR));
+        // nothing was matched or held.
+        assert_eq!(order_book.order_loc.len(), 3);
+        assert!(order_book.trades.is_empty());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "20.00");
+    }
+
+    #[test]
+    fn cancel_oldest_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "50.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order_ids = Vec::new();
+        for (i, price) in ["10.0", "9.0", "11.0", "8.0"].iter().enumerate() {
+            let alice_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(i as u64),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_ids.push(alice_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        let cancelled = order_book
+            .cancel_oldest(&mut manager, &alice_address, 2)
+            .unwrap();
+        let prices: Vec<Decimal> = cancelled.into_iter().map(|o| o.price).collect();
+        assert_eq!(prices, vec!["10.00", "9.00"]);
+        assert!(order_book.get_order(order_ids[0].clone()).is_err());
+        assert!(order_book.get_order(order_ids[1].clone()).is_err());
+        assert!(order_book.get_order(order_ids[2].clone()).is_ok());
+        assert!(order_book.get_order(order_ids[3].clone()).is_ok());
+        assert_eq!(order_book.open_order_count(&alice_address), 2);
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "50.00");
+        assert!(order_book
+            .cancel_oldest(&mut manager, &bob_address, 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn reject_locking_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "1.0", "20.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().reject_locking_orders = true;
+        let order = |price: &str, side: Side, nonce: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(nonce),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book
+            .add_order(&mut manager, order("10.0", Side::Ask, 1))
+            .unwrap();
+        // alice's own ask is skipped, so her bid would rest at the best ask.
+        assert!(matches!(
+            order_book.add_order(&mut manager, order("10.0", Side::Bid, 2)),
+            Err(OrderBookError::InvalidOrder)
+        ));
+        let fill_result = order_book
+            .add_order(&mut manager, order("9.0", Side::Bid, 3))
+            .unwrap();
+        assert!(fill_result.filled_orders.is_empty());
+        assert_eq!(order_book.open_order_count(&alice_address), 2);
+        // an ask at the best bid which fills completely does not lock the book.
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "9.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(4),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+    }
+
+    #[test]
+    fn integrity_reports_corruption() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_order = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(1),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        assert!(order_book.check_integrity(&manager).is_empty());
+        // a location left behind by a lost order.
+        let ghost = order_init(3).encode_order().unwrap().hash_hex().unwrap();
+        order_book.order_loc.insert(ghost.clone(), (Side::Bid, 0));
+        let violations = order_book.check_integrity(&manager);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            &violations[0],
+            IntegrityViolation::DanglingLocation { order_hash } if *order_hash == ghost
+        ));
+    }
+
+    #[test]
+    fn fifo_within_price_level() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "50.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order_ids = Vec::new();
+        // nonces out of order, so the queue does not follow the hashes or nonces.
+        for seed in [9, 2, 5] {
+            let alice_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: "10.0".to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(seed),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_ids.push(alice_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        for (seed, expected) in [(20, &order_ids[0]), (21, &order_ids[1])] {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: "10.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(seed),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+            assert_eq!(fill_result.filled_orders.len(), 1);
+            assert_eq!(&fill_result.filled_orders[0].maker_hash, expected);
+        }
+        assert!(order_book.get_order(order_ids[2].clone()).is_ok());
+    }
+
+    #[test]
+    fn emptied_levels_are_dropped() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "50.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order_ids = Vec::new();
+        for (i, price) in ["10.0", "9.0", "8.0"].iter().enumerate() {
+            let alice_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(i as u64),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_ids.push(alice_order.encode_order().unwrap().hash_hex().unwrap());
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        assert_eq!(order_book.bid_book.price_map.len(), 3);
+        // filling the 10 and 9 levels drops both prices.
+        let bob_order = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "9.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(10),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        manager.update_accounts(fill_result).unwrap();
+        assert_eq!(order_book.bid_book.price_map.len(), 1);
+        assert!(order_book.ask_book.price_map.is_empty());
+        // so does cancelling the last order of a level.
+        order_book
+            .cancel_order(&mut manager, order_ids[2].clone())
+            .unwrap();
+        assert!(order_book.bid_book.price_map.is_empty());
+        // and a new price reuses a freed slot.
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "7.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(11),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        assert_eq!(order_book.bid_book.price_levels.len(), 3);
+        assert_eq!(order_book.bid_book.free_levels.len(), 2);
+        assert!(order_book.check_integrity(&manager).is_empty());
+    }
+
+    #[test]
+    fn decimal_scales() {
+        let usdc = Scale::new(6);
+        let btc = Scale::new(8);
+        assert_eq!(
+            from_decimal("12.5", usdc).unwrap(),
+            U256::from(12_500_000u64)
+        );
+        assert_eq!(
+            from_decimal("12.5", btc).unwrap(),
+            U256::from(1_250_000_000u64)
+        );
+        let wei = from_decimal("12.5", Scale::ENGINE).unwrap();
+        for scale in [usdc, btc, Scale::ENGINE] {
+            let value = Scale::ENGINE.rescale(wei, scale);
+            assert_eq!(to_decimal(&value, scale), "12.50");
+            assert_eq!(scale.rescale(value, usdc), U256::from(12_500_000u64));
+        }
+        // decimals without an exact binary form convert exactly too.
+        assert_eq!(from_decimal("0.1", usdc).unwrap(), U256::from(100_000u64));
+        assert_eq!(
+            from_decimal("12.34", btc).unwrap(),
+            U256::from(1_234_000_000u64)
+        );
+        assert_eq!(
+            from_decimal("12.34", Scale::ENGINE).unwrap(),
+            U256::from(1234) * U256::exp10(16)
+        );
+        assert_eq!(from_decimal("1e-18", Scale::ENGINE).unwrap(), U256::one());
+        assert!(from_decimal("1e-18", usdc).is_err());
+        assert_eq!(usdc.unit(), U256::exp10(6));
+        assert_eq!(
+            usdc.rescale(U256::from(7), Scale::ENGINE),
+            U256::from(7) * U256::exp10(12)
+        );
+    }
+
+    #[test]
+    fn accounts_above_threshold() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "5.0", "20.0", &bob_address, "50.0", "1.0");
+        let carol_address = Address::random();
+        manager
+            .add_json_account(
+                "carol",
+                JsonAccount {
+                    ddxBalance: "12.0".to_string(),
+                    usdBalance: "0.0".to_string(),
+                    traderAddress: carol_address,
+                    balances: Default::default(),
+                    funds: Default::default(),
+                },
+            )
+            .unwrap();
+        // held funds count towards the total.
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "40.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let holders: Vec<Address> = manager
+            .accounts_above(Asset::Ddx, decimal_to_u256(&"10.0".to_string()).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|account| account.traderAddress)
+            .collect();
+        assert_eq!(holders, vec![bob_address, carol_address]);
+        let holders = manager
+            .accounts_above(Asset::Usd, decimal_to_u256(&"20.0".to_string()).unwrap())
+            .unwrap();
+        assert!(holders.is_empty());
+    }
+
+    #[test]
+    fn market_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (i, price) in ["10.0", "11.0", "12.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let market_bid = |amount: &str, nonce: u64| JsonOrder {
+            amount: amount.to_string(),
+            price: "".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(nonce),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // fully filled across two levels.
+        let fill_result = order_book
+            .add_market_order(&mut manager, market_bid("1.5", 10))
+            .unwrap();
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        let prices: Vec<Decimal> = fill_result
+            .generate_filled_orders()
+            .into_iter()
+            .map(|fill| fill.price)
+            .collect();
+        assert_eq!(prices, vec!["10.00", "11.00"]);
+        manager.update_accounts(fill_result).unwrap();
+        // the rest of the book is swept and the remainder does not rest.
+        let fill_result = order_book
+            .add_market_order(&mut manager, market_bid("3.0", 11))
+            .unwrap();
+        assert!(matches!(fill_result.status, OrderStatus::PartiallyFilled));
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        assert_eq!(fill_result.cancelled_orders.len(), 1);
+        manager.update_accounts(fill_result).unwrap();
+        assert!(order_book.bid_book.price_map.is_empty());
+        assert!(order_book.ask_book.price_map.is_empty());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "3.00");
+        assert_eq!(alice_json.usdBalance, "67.00");
+        assert!(matches!(
+            order_book.add_market_order(&mut manager, market_bid("1.0", 12)),
+            Err(OrderBookError::InsufficientLiquidity)
+        ));
+    }
+
+    #[test]
+    fn l2_caps_a_busy_level() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "0.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for i in 0..L2_MAX + 10 {
+            let bob_order = JsonOrder {
+                amount: "0.1".to_string(),
+                price: "10.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        // a busy level is a single row.
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.asks.len(), 1);
+        assert_eq!(l2_book.asks[0].amount, "6.00");
+        assert!(l2_book.bids.is_empty());
+        // the cap applies to distinct price levels.
+        for i in 0..L2_MAX + 10 {
+            let bob_order = JsonOrder {
+                amount: "0.05".to_string(),
+                price: format!("{}.0", 11 + i),
+                side: Side::Ask,
+                nonce: get_nonce((L2_MAX + 10 + i) as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.asks.len(), L2_MAX);
+        assert_eq!(l2_book.asks[0].price, "10.00");
+        assert_eq!(
+            l2_book.asks[L2_MAX - 1].price,
+            format!("{}.00", 10 + L2_MAX - 1)
+        );
+    }
+
+    #[test]
+    fn l2_aggregates_price_levels() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (seed, amount) in [(1, "1.0"), (2, "2.0"), (3, "0.5")] {
+            let mut alice_order = order_init(seed);
+            alice_order.traderAddress = alice_address;
+            alice_order.amount = amount.to_string();
+            order_book.add_order(&mut manager, alice_order).unwrap();
+        }
+        let mut lower_bid = order_init(4);
+        lower_bid.traderAddress = alice_address;
+        lower_bid.price = "9.0".to_string();
+        order_book.add_order(&mut manager, lower_bid).unwrap();
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.bids.len(), 2);
+        assert_eq!(l2_book.bids[0].price, "10.00");
+        assert_eq!(l2_book.bids[0].amount, "3.50");
+        assert_eq!(l2_book.bids[1].price, "9.00");
+        assert_eq!(l2_book.bids[1].amount, "1.00");
+    }
+
+    #[test]
+    fn order_checks_reject_before_holding() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "0.0", "0.0");
+        manager.add_order_check(|order, _account| {
+            if decimal_to_u256(&order.amount).unwrap() > U256::exp10(18) * 5 {
+                Err(format!("amount {} above 5", order.amount))
+            } else {
+                Ok(())
+            }
+        });
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut alice_order = order_init(1);
+        alice_order.amount = "6.0".to_string();
+        alice_order.price = "1.0".to_string();
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, alice_order.clone())
+                .unwrap_err(),
+            OrderBookError::Rejected("amount 6.0 above 5".to_string())
+        );
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        alice_order.amount = "5.0".to_string();
+        assert!(order_book.add_order(&mut manager, alice_order).is_ok());
+    }
+
+    #[test]
+    fn fill_or_kill() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (i, price) in ["10.0", "11.0", "13.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let mut fok = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "12.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(10),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // exactly the liquidity at 12 or better is fillable, one wei more is not.
+        let mut encoded = fok.encode_order().unwrap();
+        assert!(order_book.fillable(&encoded));
+        encoded.amount += U256::one();
+        assert!(!order_book.fillable(&encoded));
+        // a rejected order leaves the book and balances untouched.
+        fok.amount = "2.5".to_string();
+        assert_eq!(
+            order_book
+                .add_order_fill_or_kill(&mut manager, fok.clone())
+                .unwrap_err(),
+            OrderBookError::InsufficientLiquidity
+        );
+        assert_eq!(order_book.order_loc.len(), 3);
+        fok.amount = "2.0".to_string();
+        let fill_result = order_book
+            .add_order_fill_or_kill(&mut manager, fok)
+            .unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        manager.update_accounts(fill_result).unwrap();
+        assert_eq!(order_book.order_loc.len(), 1);
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "2.00");
+    }
+
+    #[test]
+    fn post_only_rejects_on_cross() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: true,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        for (nonce, price) in [(2, "10.0"), (3, "11.0")] {
+            let alice_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(nonce),
+                traderAddress: alice_address.clone(),
+                postOnly: true,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            assert_eq!(
+                order_book.add_order(&mut manager, alice_order).unwrap_err(),
+                OrderBookError::WouldCross
+            );
+        }
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        let alice_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "9.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(4),
+            traderAddress: alice_address.clone(),
+            postOnly: true,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        assert!(fill_result.filled_orders.is_empty());
+        assert_eq!(order_book.open_order_count(&alice_address), 1);
+    }
+
+    #[test]
+    fn reported_reservation_matches_hold() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let usd_hold = |manager: &AccountManager| manager.accounts[&alice_address].usd_hold();
+        let before = usd_hold(&manager);
+        let mut alice_order = order_init(1);
+        alice_order.amount = "1.5".to_string();
+        alice_order.price = "10.3".to_string();
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        assert_eq!(
+            fill_result.reserved,
+            Some((Asset::Usd, usd_hold(&manager) - before))
+        );
+        let bob_order = JsonOrder {
+            amount: "0.5".to_string(),
+            price: "11.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        assert_eq!(
+            fill_result.reserved,
+            Some((Asset::Ddx, decimal_to_u256(&"0.5".to_string()).unwrap()))
+        );
+    }
+
+    #[test]
+    fn l2_display_tick_buckets() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().tick_size = U256::exp10(17);
+        order_book.config_mut().l2_display_tick = Some(U256::exp10(18));
+        for (i, price) in ["10.1", "10.9", "11.2"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let l2_book = order_book.generate_l2_order_book();
+        let asks: Vec<(&str, &str)> = l2_book
+            .asks
+            .iter()
+            .map(|o| (o.price.as_str(), o.amount.as_str()))
+            .collect();
+        assert_eq!(asks, vec![("10.00", "2.00"), ("11.00", "1.00")]);
+        // matching still sees the 10.1 and 10.9 levels apart.
+        let alice_order = JsonOrder {
+            amount: "1.5".to_string(),
+            price: "10.1".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(10),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert_eq!(fill_result.generate_filled_orders()[0].price, "10.10");
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.bids[0].price, "10.00");
+        assert_eq!(l2_book.asks[0].amount, "1.00");
+    }
+
+    #[test]
+    fn signed_orders() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut manager = account_init(&trader, "0.0", "20.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().require_signatures = true;
+        let mut order = order_init(1);
+        order.traderAddress = trader;
+        let digest = order.encode_order().unwrap().encode_eip712().unwrap();
+        let signature = wallet.sign_hash(H256::from(digest));
+        assert_eq!(
+            order
+                .encode_order()
+                .unwrap()
+                .recover_signer(&signature.to_string(), &default_domain())
+                .unwrap(),
+            trader
+        );
+        // unsigned and tampered orders are rejected.
+        assert!(matches!(
+            order_book.add_order(&mut manager, order.clone()),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        order.signature = Some(signature.to_string());
+        let mut tampered = order.clone();
+        tampered.amount = "2.0".to_string();
+        assert_ne!(
+            tampered
+                .encode_order()
+                .unwrap()
+                .recover_signer(&signature.to_string(), &default_domain())
+                .unwrap(),
+            trader
+        );
+        assert!(matches!(
+            order_book.add_order(&mut manager, tampered),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        // the signature covers none of the flags, so a signed order cannot carry them.
+        let mut flagged = vec![order.clone(); 4];
+        flagged[0].postOnly = true;
+        flagged[1].reduceOnly = true;
+        flagged[2].expiresAt = Some(u64::MAX);
+        flagged[3].displayAmount = Some("0.5".to_string());
+        for flagged in flagged {
+            assert_eq!(
+                order_book.add_order(&mut manager, flagged).unwrap_err(),
+                OrderBookError::InvalidSignature("flags are not signed".to_string())
+            );
+        }
+        assert!(order_book.add_order(&mut manager, order).is_ok());
+        assert_eq!(order_book.open_order_count(&trader), 1);
+    }
+
+    #[test]
+    fn replayed_nonces() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut manager = account_init(&trader, "0.0", "50.0", &bob_address, "0.0", "0.0");
+        let mut order = order_init(1);
+        order.traderAddress = trader;
+        let encoded = order.encode_order().unwrap();
+        order.signature = Some(
+            wallet
+                .sign_hash(H256::from(encoded.encode_eip712().unwrap()))
+                .to_string(),
+        );
+        assert!(!manager.nonce_used(&trader, &encoded.nonce));
+        assert!(manager.validate_order(order.clone()).is_ok());
+        assert!(manager.nonce_used(&trader, &encoded.nonce));
+        let usd_hold = manager.accounts[&trader].usd_hold();
+        assert!(matches!(
+            manager.validate_order(order.clone()),
+            Err(OrderBookError::NonceUsed)
+        ));
+        // the replay holds no further funds.
+        assert_eq!(manager.accounts[&trader].usd_hold(), usd_hold);
+        // nonces are tracked per trader.
+        assert!(!manager.nonce_used(&bob_address, &encoded.nonce));
+        order.nonce = get_nonce(2);
+        let second_nonce = order.encode_order().unwrap().nonce;
+        assert!(manager.validate_order(order).is_ok());
+        let mut expected = vec![encoded.nonce, second_nonce];
+        expected.sort();
+        assert_eq!(manager.used_nonces(&trader), expected);
+    }
+
+    #[test]
+    fn order_owner() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager =
+            account_init(&alice_address, "10.0", "50.0", &bob_address, "10.0", "50.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order = order_init(1);
+        order.traderAddress = bob_address;
+        order_book.add_order(&mut manager, order.clone()).unwrap();
+        let hash = order.encode_order().unwrap().hash_hex().unwrap();
+        assert_eq!(order_book.order_owner(&hash), Some(bob_address));
+        assert_eq!(order_book.order_owner(&"0x00".to_string()), None);
+        order_book.cancel_order(&mut manager, hash.clone()).unwrap();
+        assert_eq!(order_book.order_owner(&hash), None);
+    }
+
+    #[test]
+    fn l3_book_depth() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "100.0",
+            "0.0",
+            &bob_address,
+            "0.0",
+            "1000.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for i in 0..10 {
+            let mut ask = order_init(i);
+            ask.side = Side::Ask;
+            ask.traderAddress = alice_address;
+            ask.price = format!("{}.0", 20 - i);
+            order_book.add_order(&mut manager, ask).unwrap();
+        }
+        let mut bid = order_init(100);
+        bid.traderAddress = bob_address;
+        order_book.add_order(&mut manager, bid).unwrap();
+        let l3_book = order_book.generate_l3_order_book(4);
+        assert_eq!(l3_book.asks.len(), 4);
+        assert_eq!(l3_book.bids.len(), 1);
+        let prices: Vec<&str> = l3_book.asks.iter().map(|ask| ask.price.as_str()).collect();
+        assert_eq!(prices, vec!["11.00", "12.00", "13.00", "14.00"]);
+        assert_eq!(order_book.generate_l3_order_book(100).asks.len(), 10);
+    }
+
+    #[test]
+    fn maker_taker_fees() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = AccountManager::with_fees(FeeSchedule {
+            maker_bps: 10,
+            taker_bps: 20,
+        });
+        for (user, address, ddx) in [("alice", alice_address, "0.0"), ("bob", bob_address, "2.0")] {
+            manager
+                .add_json_account(
+                    user,
+                    JsonAccount {
+                        ddxBalance: ddx.to_string(),
+                        usdBalance: "20.0".to_string(),
+                        traderAddress: address,
+                        balances: Default::default(),
+                        funds: Default::default(),
+                    },
+                )
+                .unwrap();
+        }
+        let usd_before = manager.accounts[&alice_address].total_usd().unwrap();
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut alice_order = order_init(1);
+        alice_order.traderAddress = alice_address;
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        let mut bob_order = order_init(2);
+        bob_order.side = Side::Ask;
+        bob_order.traderAddress = bob_address;
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        let fill = fill_result.filled_orders[0].clone();
+        assert!(matches!(fill.taker_side, Side::Ask));
+        manager.update_accounts(fill_result).unwrap();
+        let notional = fill.fill_amount * fill.price / Scale::ENGINE.unit();
+        let maker_fee = notional * 10 / 10_000;
+        let taker_fee = notional * 20 / 10_000;
+        // alice bought as the maker, bob sold as the taker.
+        assert_eq!(
+            manager.accounts[&alice_address].total_usd().unwrap(),
+            usd_before - notional - maker_fee
+        );
+        assert_eq!(
+            manager.accounts[&bob_address].total_usd().unwrap(),
+            usd_before + notional - taker_fee
+        );
+        assert_eq!(manager.fee_pool(), maker_fee + taker_fee);
+        assert_eq!(u256_to_decimal(&manager.fee_pool()), "0.03");
+    }
+
+    #[test]
+    fn settlement_batches() {
+        let (alice_address, bob_address) = address_init();
+        let unit = Scale::ENGINE.unit();
+        let fill = |from: Address, to: Address, amount: U256, price: U256, timestamp: u64| Fill {
+            from,
+            to,
+            maker_hash: "0x01".to_string(),
+            taker_hash: "0x02".to_string(),
+            taker_side: Side::Ask,
+            fill_amount: amount,
+            price,
+            bid_price: price,
+            bid_amount: amount,
+            mid: None,
+            maker_seq: 0,
+            taker_seq: 0,
+            timestamp,
+        };
+        let mut batcher = SettlementBatcher::new(1_000);
+        // alice sells 1 DDX at 10 then buys 0.4 DDX at 12 back from bob within one second.
+        assert!(batcher
+            .push(fill(alice_address, bob_address, unit, unit * 10, 1_200))
+            .unwrap()
+            .is_none());
+        assert!(batcher
+            .push(fill(
+                bob_address,
+                alice_address,
+                unit * 4 / 10,
+                unit * 12,
+                1_900
+            ))
+            .unwrap()
+            .is_none());
+        assert!(batcher.tick(1_999).unwrap().is_none());
+        let bundle = batcher.tick(2_000).unwrap().unwrap();
+        assert_eq!(
+            (bundle.start, bundle.end, bundle.fill_count),
+            (1_000, 2_000, 2)
+        );
+        let expected = vec![
+            NetTransfer {
+                asset: Asset::Ddx,
+                from: alice_address,
+                to: bob_address,
+                amount: unit * 6 / 10,
+            },
+            NetTransfer {
+                asset: Asset::Usd,
+                from: bob_address,
+                to: alice_address,
+                amount: unit * 52 / 10,
+            },
+        ];
+        assert_eq!(bundle.transfers, expected);
+        // a fill past the window starts the next bundle.
+        assert!(batcher
+            .push(fill(alice_address, bob_address, unit, unit * 10, 2_500))
+            .unwrap()
+            .is_none());
+        let bundle = batcher
+            .push(fill(alice_address, bob_address, unit, unit * 10, 3_000))
+            .unwrap()
+            .unwrap();
+        assert_eq!((bundle.start, bundle.fill_count), (2_000, 1));
+        assert_eq!(batcher.flush().unwrap().unwrap().start, 3_000);
+        assert!(batcher.flush().unwrap().is_none());
+        // net amounts beyond the largest amount are an error, and the fills stay pending.
+        let huge = U256::MAX / 2 + 1;
+        for timestamp in [4_000, 4_100] {
+            assert!(batcher
+                .push(fill(
+                    alice_address,
+                    bob_address,
+                    huge,
+                    U256::one(),
+                    timestamp
+                ))
+                .unwrap()
+                .is_none());
+        }
+        assert_eq!(
+            batcher.flush().unwrap_err(),
+            BalanceError::Overflow(Asset::Ddx)
+        );
+        assert_eq!(
+            batcher
+                .push(fill(alice_address, bob_address, unit, unit * 10, 5_000))
+                .unwrap_err(),
+            BalanceError::Overflow(Asset::Ddx)
+        );
+        let mut batcher = SettlementBatcher::new(1_000);
+        batcher
+            .push(fill(alice_address, bob_address, huge, unit * 10, 1_000))
+            .unwrap();
+        assert_eq!(
+            batcher.tick(2_000).unwrap_err(),
+            BalanceError::Overflow(Asset::Usd)
+        );
+    }
+
+    #[test]
+    fn signed_cancels() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let other_wallet = "0123456789012345678901234567890123456789012345678901234567890123"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut manager = account_init(&trader, "0.0", "20.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order = order_init(1);
+        order.traderAddress = trader;
+        order_book.add_order(&mut manager, order.clone()).unwrap();
+        let order_hash = order.encode_order().unwrap().hash_hex().unwrap();
+        let sign = |wallet: &LocalWallet, nonce: u64| {
+            let mut cancel = JsonCancel {
+                orderHash: order_hash.clone(),
+                nonce: get_nonce(nonce),
+                signature: String::new(),
+            };
+            let digest = cancel.encode_cancel().unwrap().encode_eip712().unwrap();
+            cancel.signature = wallet.sign_hash(H256::from(digest)).to_string();
+            cancel
+        };
+        // a cancel signed by another key is rejected.
+        assert!(matches!(
+            order_book.cancel_signed_order(&mut manager, &sign(&other_wallet, 1)),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        assert_eq!(order_book.open_order_count(&trader), 1);
+        // so is an unsigned cancel once signatures are required.
+        order_book.config_mut().require_signatures = true;
+        assert!(matches!(
+            order_book.cancel_order(&mut manager, order_hash.clone()),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        let cancelled = order_book
+            .cancel_signed_order(&mut manager, &sign(&wallet, 2))
+            .unwrap();
+        assert_eq!(cancelled.traderAddress, trader);
+        assert_eq!(order_book.open_order_count(&trader), 0);
+        assert_eq!(manager.accounts[&trader].usd_hold(), U256::zero());
+        assert!(matches!(
+            order_book.cancel_signed_order(&mut manager, &sign(&wallet, 3)),
+            Err(OrderBookError::NoSuchOrder)
+        ));
+    }
+
+    #[test]
+    fn decimals_beyond_u128() {
+        let unit = Scale::ENGINE.unit();
+        assert_eq!(
+            u256_to_decimal(&(U256::from(123) * unit / 100)),
+            "1.23".to_string()
+        );
+        assert_eq!(u256_to_decimal(&U256::from(1)), "0.00");
+        assert_eq!(u256_to_decimal(&(unit * 1005 / 1000)), "1.01");
+        assert_eq!(u256_to_decimal(&(unit * 1004 / 1000)), "1.00");
+        let near = U256::from(u128::MAX) * unit;
+        assert_eq!(
+            u256_to_decimal(&near),
+            "340282366920938463463374607431768211455.00"
+        );
+        assert_eq!(
+            u256_to_decimal(&(near + unit * 42 / 100 + unit)),
+            "340282366920938463463374607431768211456.42"
+        );
+        assert_eq!(
+            to_decimal(&U256::from(1234), Scale::new(0)),
+            "1234.00".to_string()
+        );
+        assert!(
+            u256_to_decimal(&U256::MAX).starts_with("115792089237316195423570985008687907853269")
+        );
+    }
+
+    #[test]
+    fn malformed_decimals_are_errors() {
+        let huge = format!("1{}", "0".repeat(60));
+        assert!(matches!(
+            decimal_to_u256(&"abc".to_string()),
+            Err(ConversionError::Unparseable(_))
+        ));
+        assert!(matches!(
+            decimal_to_u256(&"-1.0".to_string()),
+            Err(ConversionError::Negative(_))
+        ));
+        assert!(matches!(
+            decimal_to_u256(&huge),
+            Err(ConversionError::Overflow(_))
+        ));
+        assert!(matches!(
+            decimal_to_u256(&"NaN".to_string()),
+            Err(ConversionError::Unparseable(_))
+        ));
+        // values convert exactly, up to the U256 range and down to the smallest unit.
+        let tenth = decimal_to_u256(&"0.1".to_string()).unwrap();
+        assert_eq!(tenth, U256::exp10(17));
+        assert_eq!(format_units(&tenth, Scale::ENGINE), "0.1");
+        let max = format_units(&U256::MAX, Scale::ENGINE);
+        assert_eq!(decimal_to_u256(&max).unwrap(), U256::MAX);
+        assert!(matches!(
+            decimal_to_u256(&format!("{}1", max)),
+            Err(ConversionError::TooPrecise(_))
+        ));
+        assert!(matches!(
+            decimal_to_u256(&"1e-19".to_string()),
+            Err(ConversionError::TooPrecise(_))
+        ));
+        let beyond = format!("{}.0", U256::MAX / Scale::ENGINE.unit() + 1);
+        assert!(matches!(
+            decimal_to_u256(&beyond),
+            Err(ConversionError::Overflow(_))
+        ));
+        assert_eq!(
+            u256_to_decimal(&decimal_to_u256(&"0.5".to_string()).unwrap()),
+            "0.50"
+        );
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for amount in ["abc", "-1.0", huge.as_str()] {
+            let mut alice_order = order_init(1);
+            alice_order.amount = amount.to_string();
+            assert!(matches!(
+                alice_order.encode_order(),
+                Err(OrderBookError::InvalidDecimal(_))
+            ));
+            assert!(matches!(
+                manager.validate_order(alice_order.clone()),
+                Err(OrderBookError::InvalidDecimal(_))
+            ));
+            assert!(matches!(
+                order_book.add_order(&mut manager, alice_order),
+                Err(OrderBookError::InvalidDecimal(_))
+            ));
+        }
+        assert_eq!(manager.accounts[&alice_address].usd_hold(), U256::zero());
+        let bad_account = JsonAccount {
+            ddxBalance: "abc".to_string(),
+            usdBalance: "0.0".to_string(),
+            traderAddress: alice_address,
+            balances: Default::default(),
+            funds: Default::default(),
+        };
+        assert!(manager.add_json_account("mallory", bad_account).is_err());
+    }
+
+    #[test]
+    fn cached_l2_prices() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "0.0",
+            "1000.0",
+            &bob_address,
+            "100.0",
+            "0.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for i in 0..20u64 {
+            let mut bid = order_init(i);
+            bid.traderAddress = alice_address;
+            bid.price = format!("{}.25", 10 + i % 7);
+            order_book.add_order(&mut manager, bid).unwrap();
+            let mut ask = order_init(100 + i);
+            ask.side = Side::Ask;
+            ask.traderAddress = bob_address;
+            ask.price = format!("{}.5", 20 + i);
+            order_book.add_order(&mut manager, ask).unwrap();
+        }
+        let uncached = serde_json::to_string(&order_book.generate_l2_order_book()).unwrap();
+        // a cache smaller than the book evicts prices between requests.
+        order_book.config_mut().l2_price_cache = Some(8);
+        for _ in 0..3 {
+            let cached = serde_json::to_string(&order_book.generate_l2_order_book()).unwrap();
+            assert_eq!(cached, uncached);
+        }
+        order_book.config_mut().l2_display_tick = Some(U256::exp10(18));
+        let cached = order_book.generate_l2_order_book();
+        order_book.config_mut().l2_price_cache = None;
+        assert_eq!(
+            serde_json::to_string(&cached).unwrap(),
+            serde_json::to_string(&order_book.generate_l2_order_book()).unwrap()
+        );
+
+        let mut cache = DecimalCache::new(2);
+        let (one, two, three) = (U256::exp10(18), U256::exp10(18) * 2, U256::exp10(18) * 3);
+        assert_eq!(cache.format(&one), "1.00");
+        assert_eq!(cache.format(&two), "2.00");
+        assert_eq!(cache.format(&one), "1.00");
+        // two is the least recently used value.
+        assert_eq!(cache.format(&three), "3.00");
+        assert!(cache.entries.contains_key(&one));
+        assert!(!cache.entries.contains_key(&two));
+    }
+
+    #[test]
+    fn archived_makers() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().archive_removed_orders = true;
+        let mut alice_order = order_init(1);
+        alice_order.traderAddress = alice_address;
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let mut bob_order = order_init(2);
+        bob_order.side = Side::Ask;
+        bob_order.traderAddress = bob_address;
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        let fill = &fill_result.generate_filled_orders()[0];
+        // the filled maker left the live book but still resolves.
+        assert!(order_book.order_owner(&fill.maker_hash).is_none());
+        let maker = order_book.get_order(fill.maker_hash.clone()).unwrap();
+        assert_eq!(maker.traderAddress, alice_address);
+        assert_eq!(maker.price, "10.00");
+        assert!(matches!(maker.side, Side::Bid));
+        manager.update_accounts(fill_result).unwrap();
+        // so does a cancelled order.
+        let mut cancelled = order_init(3);
+        cancelled.traderAddress = alice_address;
+        order_book
+            .add_order(&mut manager, cancelled.clone())
+            .unwrap();
+        let cancelled_hash = cancelled.encode_order().unwrap().hash_hex().unwrap();
+        order_book
+            .cancel_order(&mut manager, cancelled_hash.clone())
+            .unwrap();
+        assert_eq!(order_book.get_order(cancelled_hash).unwrap().amount, "1.00");
+
+        // without the archive removed orders are gone.
+        order_book.config_mut().archive_removed_orders = false;
+        let mut removed = order_init(4);
+        removed.traderAddress = alice_address;
+        order_book.add_order(&mut manager, removed.clone()).unwrap();
+        let removed_hash = removed.encode_order().unwrap().hash_hex().unwrap();
+        order_book
+            .cancel_order(&mut manager, removed_hash.clone())
+            .unwrap();
+        assert!(order_book.get_order(removed_hash).is_err());
+    }
+
+    #[test]
+    fn minimum_order_size() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let ask = |amount: &str, price: &str, seed: u64| JsonOrder {
+            amount: amount.to_string(),
+            price: price.to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(seed),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let minimum = ask("1.0", "10.0", 1).encode_order().unwrap();
+        order_book.config_mut().min_amount = minimum.amount;
+        order_book.config_mut().min_notional =
+            minimum.amount * minimum.price / Scale::ENGINE.unit();
+        // just below the minimum amount.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, ask("0.99", "20.0", 2))
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        // enough amount, just below the minimum notional.
+        order_book.config_mut().min_amount = U256::zero();
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, ask("1.0", "9.99", 3))
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        order_book.config_mut().min_amount = minimum.amount;
+        assert!(order_book
+            .add_order(&mut manager, ask("1.0", "10.0", 1))
+            .is_ok());
+        // the rejected orders did not use their nonces.
+        for seed in [2, 3] {
+            let nonce = ask("1.0", "10.0", seed).encode_order().unwrap().nonce;
+            assert!(!manager.nonce_used(&bob_address, &nonce));
+        }
+        // market orders are checked at their worst price.
+        let mut bid = ask("0.5", "10.0", 4);
+        bid.side = Side::Bid;
+        bid.traderAddress = alice_address.clone();
+        assert_eq!(
+            order_book.add_market_order(&mut manager, bid).unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        assert_eq!(order_book.open_order_count(&bob_address), 1);
+    }
+
+    #[test]
+    fn market_orders_into_an_empty_side() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let market_order = |side: Side, trader: &Address, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: "".to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // rejected by default.
+        assert!(matches!(
+            order_book.add_market_order(&mut manager, market_order(Side::Bid, &alice_address, 1)),
+            Err(OrderBookError::InsufficientLiquidity)
+        ));
+        order_book.config_mut().one_sided_market_policy = OneSidedMarketPolicy::Cancel;
+        for (side, trader, seed) in [(Side::Bid, alice_address, 1), (Side::Ask, bob_address, 2)] {
+            let fill_result = order_book
+                .add_market_order(&mut manager, market_order(side, &trader, seed))
+                .unwrap();
+            assert!(matches!(fill_result.status, OrderStatus::Cancelled));
+            assert!(fill_result.filled_orders.is_empty());
+            assert_eq!(u256_to_decimal(&fill_result.remaining), "1.00");
+            assert_eq!(fill_result.cancelled_orders.len(), 1);
+            manager.update_accounts(fill_result).unwrap();
+            assert_eq!(manager.accounts[&trader].ddx_hold(), U256::zero());
+            assert_eq!(manager.accounts[&trader].usd_hold(), U256::zero());
+        }
+        assert!(order_book.bid_book.price_map.is_empty());
+        assert!(order_book.ask_book.price_map.is_empty());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "10.00");
+        let bob_json = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob_json.ddxBalance, "1.00");
+        // bob's whole DDX balance is free again.
+        let bob_ask = JsonOrder {
+            price: "10.0".to_string(),
+            ..market_order(Side::Ask, &bob_address, 3)
+        };
+        assert!(order_book.add_order(&mut manager, bob_ask).is_ok());
+    }
+
+    #[test]
+    fn prices_on_the_tick_grid() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().tick_size = U256::exp10(17) * 5;
+        let order = |side: Side, trader: &Address, price: &str, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        assert!(order_book
+            .add_order(&mut manager, order(Side::Ask, &bob_address, "10.5", 1))
+            .is_ok());
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Ask, &bob_address, "10.75", 2))
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        // snapped away from the opposite side, so a bid never pays more than it asked for.
+        order_book.config_mut().off_tick_policy = OffTickPolicy::Snap;
+        assert!(order_book
+            .add_order(&mut manager, order(Side::Ask, &bob_address, "10.75", 2))
+            .is_ok());
+        assert!(order_book
+            .add_order(&mut manager, order(Side::Bid, &alice_address, "9.99", 3))
+            .is_ok());
+        let l2 = order_book.generate_l2_order_book();
+        let prices = |levels: &[SimpleOrder]| -> Vec<Decimal> {
+            levels.iter().map(|level| level.price.clone()).collect()
+        };
+        assert_eq!(prices(&l2.asks), vec!["10.50", "11.00"]);
+        assert_eq!(prices(&l2.bids), vec!["9.50"]);
+        // a bid below one tick has nowhere to go.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Bid, &alice_address, "0.2", 4))
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        // a signed price is never changed.
+        let trader = wallet.address();
+        manager.new_account("carol", trader);
+        let mut signed = order(Side::Bid, &trader, "9.99", 5);
+        let digest = signed.encode_order().unwrap().encode_eip712().unwrap();
+        signed.signature = Some(wallet.sign_hash(H256::from(digest)).to_string());
+        assert_eq!(
+            order_book.add_order(&mut manager, signed).unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+    }
+
+    #[test]
+    fn epoch_changes_on_restart() {
+        let order_book = OrderBook::new("DDX".to_string());
+        assert_eq!(order_book.clone().epoch(), order_book.epoch());
+        // a restarted engine builds a new book.
+        let restarted = OrderBook::new("DDX".to_string());
+        assert_ne!(restarted.epoch(), order_book.epoch());
+    }
+
+    #[test]
+    fn missing_order_errors() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "0.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let order_id = order_init(1).encode_order().unwrap().hash_hex().unwrap();
+        assert_eq!(
+            order_book.get_order(order_id.clone()).unwrap_err(),
+            OrderBookError::NoSuchOrder
+        );
+        assert_eq!(
+            order_book.cancel_order(&mut manager, order_id).unwrap_err(),
+            OrderBookError::NoSuchOrder
+        );
+    }
+
+    #[test]
+    fn good_till_time_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "20.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let now = now_millis() / 1000;
+        let bob_ask = |price: &str, seed: u64, expires_at: Option<u64>| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(seed),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: expires_at,
+            displayAmount: None,
+        };
+        // already expired on arrival.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, bob_ask("10.0", 1, Some(now - 1)))
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        let gtt_hash = bob_ask("10.0", 2, None)
+            .encode_order()
+            .unwrap()
+            .hash_hex()
+            .unwrap();
+        order_book
+            .add_order(&mut manager, bob_ask("10.0", 2, Some(now + 60)))
+            .unwrap();
+        order_book
+            .add_order(&mut manager, bob_ask("11.0", 3, None))
+            .unwrap();
+        assert_eq!(
+            order_book.get_order(gtt_hash.clone()).unwrap().expiresAt,
+            Some(now + 60)
+        );
+        assert!(order_book
+            .prune_expired(&mut manager, now + 59)
+            .unwrap()
+            .is_empty());
+        let pruned = order_book.prune_expired(&mut manager, now + 60).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].price, "10.00");
+        assert!(order_book.get_order(gtt_hash).is_err());
+        assert_eq!(order_book.open_order_count(&bob_address), 1);
+        // only the order still resting holds DDX.
+        assert_eq!(
+            u256_to_decimal(&manager.accounts[&bob_address].ddx_hold()),
+            "1.00"
+        );
+        assert_eq!(
+            manager.get_json_account(&bob_address).unwrap().ddxBalance,
+            "2.00"
+        );
+    }
+
+    #[test]
+    fn effective_spread() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        assert!(order_book.effective_spread(u64::MAX).is_none());
+        for (side, trader, price, seed) in [
+            (Side::Bid, &alice_address, "9.0", 1),
+            (Side::Ask, &bob_address, "11.0", 2),
+            (Side::Ask, &bob_address, "12.0", 3),
+        ] {
+            order_book
+                .add_order(&mut manager, new_order(side, trader, "1.0", price, seed))
+                .unwrap();
+        }
+        // a buy at mid 10 sweeping 11 and 12 pays 2 and 4.
+        order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Bid, &alice_address, "1.5", "12.0", 4),
+            )
+            .unwrap();
+        // a sell at mid 10.5 hitting 9 pays 3.
+        order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Ask, &bob_address, "0.5", "8.0", 5),
+            )
+            .unwrap();
+        let mids: Vec<Decimal> = order_book
+            .trades
+            .iter()
+            .map(|fill| u256_to_decimal(&fill.mid.unwrap()))
+            .collect();
+        assert_eq!(mids, vec!["10.00", "10.00", "10.50"]);
+        assert_eq!(
+            order_book.effective_spread(u64::MAX),
+            Some("3.00".to_string())
+        );
+        // move the buy a second back, out of the window.
+        let moved: TradeHistory = order_book
+            .trades
+            .iter()
+            .enumerate()
+            .map(|(i, fill)| Fill {
+                timestamp: fill.timestamp - if i < 2 { 1_000 } else { 0 },
+                ..fill.clone()
+            })
+            .collect();
+        order_book.trades = moved;
+        assert_eq!(order_book.effective_spread(500), Some("3.00".to_string()));
+        let one_sided: TradeHistory = order_book
+            .trades
+            .iter()
+            .enumerate()
+            .map(|(i, fill)| Fill {
+                mid: fill.mid.filter(|_| i != 2),
+                ..fill.clone()
+            })
+            .collect();
+        order_book.trades = one_sided;
+        assert!(order_book.effective_spread(500).is_none());
+    }
+
+    #[test]
+    fn price_level_summary() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "0.0",
+            "1000.0",
+            &bob_address,
+            "100.0",
+            "10000.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let config = SeedConfig {
+            trader: bob_address,
+            levels: 3,
+            spread: 1.0,
+            step: 0.5,
+            size: 2.0,
+            size_decay: 0.5,
+        };
+        assert_eq!(order_book.seed_around(&mut manager, 100.0, &config), 6);
+        // alice joins the second best bid twice.
+        for seed in [1, 2] {
+            let alice_bid = JsonOrder {
+                amount: "0.25".to_string(),
+                price: "99.0".to_string(),
+                side: Side::Bid,
+                nonce: get_nonce(seed),
+                traderAddress: alice_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, alice_bid).unwrap();
+        }
+        let level = |price: &str, orders: usize, volume: &str| {
+            (price.to_string(), orders, volume.to_string())
+        };
+        assert_eq!(
+            order_book.price_level_summary(Side::Bid),
+            vec![
+                level("99.50", 1, "2.00"),
+                level("99.00", 3, "1.50"),
+                level("98.50", 1, "0.50"),
+            ]
+        );
+        assert_eq!(
+            order_book.price_level_summary(Side::Ask),
+            vec![
+                level("100.50", 1, "2.00"),
+                level("101.00", 1, "1.00"),
+                level("101.50", 1, "0.50"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reduce_resting_order() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "50.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_bid = |amount: &str, seed: u64| JsonOrder {
+            amount: amount.to_string(),
+            price: "10.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(seed),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let first = alice_bid("2.0", 1)
+            .encode_order()
+            .unwrap()
+            .hash_hex()
+            .unwrap();
+        order_book
+            .add_order(&mut manager, alice_bid("2.0", 1))
+            .unwrap();
+        order_book
+            .add_order(&mut manager, alice_bid("1.0", 2))
+            .unwrap();
+        let usd_hold = |manager: &AccountManager| {
+            u256_to_decimal(&manager.accounts[&alice_address].usd_hold())
+        };
+        assert_eq!(usd_hold(&manager), "30.00");
+        // the amount cannot grow.
+        assert_eq!(
+            order_book
+                .reduce_order(&mut manager, first.clone(), &"2.5".to_string())
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        let reduced = order_book
+            .reduce_order(&mut manager, first.clone(), &"0.5".to_string())
+            .unwrap();
+        assert_eq!(reduced.amount, "0.50");
+        assert_eq!(usd_hold(&manager), "15.00");
+        assert_eq!(
+            manager.get_json_account(&alice_address).unwrap().usdBalance,
+            "50.00"
+        );
+        // still first in the queue.
+        let level = order_book
+            .level_detail(Side::Bid, &"10.0".to_string())
+            .unwrap();
+        let amounts: Vec<Decimal> = level.into_iter().map(|order| order.amount).collect();
+        assert_eq!(amounts, vec!["0.50", "1.00"]);
+        assert_eq!(order_book.get_order(first).unwrap().amount, "0.50");
+        assert_eq!(
+            order_book
+                .reduce_order(&mut manager, "0x00".to_string(), &"0.5".to_string())
+                .unwrap_err(),
+            OrderBookError::NoSuchOrder
+        );
+    }
+
+    #[test]
+    fn replace_resting_order() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "35.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let alice_bid = |amount: &str, price: &str, seed: u64| JsonOrder {
+            amount: amount.to_string(),
+            price: price.to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(seed),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let first = alice_bid("1.0", "10.0", 1)
+            .encode_order()
+            .unwrap()
+            .hash_hex()
+            .unwrap();
+        order_book
+            .add_order(&mut manager, alice_bid("1.0", "10.0", 1))
+            .unwrap();
+        order_book
+            .add_order(&mut manager, alice_bid("1.0", "10.0", 2))
+            .unwrap();
+        let usd_hold = |manager: &AccountManager| {
+            u256_to_decimal(&manager.accounts[&alice_address].usd_hold())
+        };
+        // 40 USD is more than the 15 USD free and the 10 USD released.
+        assert_eq!(
+            order_book
+                .replace_order(&mut manager, first.clone(), alice_bid("4.0", "10.0", 3))
+                .unwrap_err(),
+            OrderBookError::InsufficientFunds
+        );
+        assert_eq!(order_book.get_order(first.clone()).unwrap().amount, "1.00");
+        assert_eq!(usd_hold(&manager), "20.00");
+        let queue = |order_book: &OrderBook| -> Vec<Decimal> {
+            order_book
+                .level_detail(Side::Bid, &"10.0".to_string())
+                .unwrap()
+                .into_iter()
+                .map(|order| order.nonce)
+                .collect()
+        };
+        let nonces = queue(&order_book);
+        assert_eq!(
+            nonces[0],
+            order_book.get_order(first.clone()).unwrap().nonce
+        );
+        // the failed replacement did not use its nonce.
+        let nonce = alice_bid("1.0", "10.0", 3).encode_order().unwrap().nonce;
+        assert!(!manager.nonce_used(&alice_address, &nonce));
+        // an amended amount goes to the back of the queue.
+        order_book
+            .replace_order(&mut manager, first.clone(), alice_bid("2.0", "10.0", 3))
+            .unwrap();
+        assert!(order_book.get_order(first).is_err());
+        let amended = alice_bid("2.0", "10.0", 3).encode_order().unwrap();
+        assert_eq!(
+            queue(&order_book),
+            vec![nonces[1].clone(), amended.to_json().nonce]
+        );
+        assert_eq!(usd_hold(&manager), "30.00");
+    }
+
+    #[test]
+    fn protected_open() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().protected_open_millis = Some(60_000);
+        let order = |side: Side, trader: &Address, price: &str, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book
+            .add_order(&mut manager, order(Side::Ask, &bob_address, "10.0", 1))
+            .unwrap();
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Bid, &alice_address, "10.0", 2))
+                .unwrap_err(),
+            OrderBookError::ProtectedOpen
+        );
+        assert_eq!(
+            order_book
+                .add_market_order(&mut manager, order(Side::Bid, &alice_address, "0", 3))
+                .unwrap_err(),
+            OrderBookError::ProtectedOpen
+        );
+        // a passive bid rests.
+        let fill_result = order_book
+            .add_order(&mut manager, order(Side::Bid, &alice_address, "9.0", 4))
+            .unwrap();
+        assert!(fill_result.filled_orders.is_empty());
+        assert_eq!(order_book.open_order_count(&alice_address), 1);
+        // aggressive orders are matched once the period is over.
+        order_book.opened_at -= 60_000;
+        let fill_result = order_book
+            .add_order(&mut manager, order(Side::Bid, &alice_address, "10.0", 2))
+            .unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        // a standby taking over opens the market again.
+        order_book.set_read_only(true);
+        order_book.set_read_only(false);
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Ask, &bob_address, "9.0", 5))
+                .unwrap_err(),
+            OrderBookError::ProtectedOpen
+        );
+    }
+
+    #[test]
+    fn bootstrap_payload() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "30.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (i, price) in ["10.0", "11.0", "12.0"].iter().enumerate() {
+            let bob_order = JsonOrder {
+                amount: "1.0".to_string(),
+                price: price.to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(i as u64),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            order_book.add_order(&mut manager, bob_order).unwrap();
+        }
+        let alice_order = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "11.0".to_string(),
+            side: Side::Bid,
+            nonce: get_nonce(3),
+            traderAddress: alice_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, alice_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        let payload = order_book.bootstrap(&manager, &bob_address, 1).unwrap();
+        assert_eq!(payload.epoch, order_book.epoch());
+        // three asks and the bid were accepted.
+        assert_eq!(payload.sequence, 4);
+        assert_eq!(payload.sequence, order_book.sequence());
+        // the latest fill only.
+        assert_eq!(payload.trades.len(), 1);
+        assert_eq!(payload.trades[0].price, "11.00");
+        assert!(payload.book.bids.is_empty());
+        assert_eq!(payload.book.asks.len(), 1);
+        // bob's account reflects both fills and still holds the DDX of the resting ask.
+        let account = payload.account.unwrap();
+        assert_eq!(account.ddxBalance, "1.00");
+        assert_eq!(account.usdBalance, "21.00");
+        assert_eq!(payload.book.asks[0].amount, "1.00");
+        let value = serde_json::to_value(
+            order_book
+                .bootstrap(&manager, &Address::zero(), 10)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(value["trades"].as_array().unwrap().len(), 2);
+        assert_eq!(value["sequence"], 4);
+        assert!(value["account"].is_null());
+    }
+
+    #[test]
+    fn orders_by_trader() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "1.0", "30.0", &bob_address, "1.0", "10.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        assert!(order_book.get_orders_by_trader(&alice_address).is_empty());
+        let order = |side: Side, trader: &Address, price: &str, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        for (side, trader, price, seed) in [
+            (Side::Bid, &alice_address, "9.0", 1),
+            (Side::Ask, &bob_address, "13.0", 2),
+            (Side::Ask, &alice_address, "12.0", 3),
+            (Side::Bid, &alice_address, "10.0", 4),
+        ] {
+            order_book
+                .add_order(&mut manager, order(side, trader, price, seed))
+                .unwrap();
+        }
+        // bob takes half of alice's ask.
+        let mut bob_bid = order(Side::Bid, &bob_address, "12.0", 5);
+        bob_bid.amount = "0.5".to_string();
+        order_book.add_order(&mut manager, bob_bid).unwrap();
+        let orders: Vec<(Decimal, Decimal)> = order_book
+            .get_orders_by_trader(&alice_address)
+            .into_iter()
+            .map(|order| (order.price, order.amount))
+            .collect();
+        assert_eq!(
+            orders,
+            vec![
+                ("9.00".to_string(), "1.00".to_string()),
+                ("12.00".to_string(), "0.50".to_string()),
+                ("10.00".to_string(), "1.00".to_string()),
+            ]
+        );
+        assert_eq!(order_book.get_orders_by_trader(&bob_address).len(), 1);
+    }
+
+    #[test]
+    fn cancel_all_orders_of_a_trader() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "2.0", "30.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let order = |side: Side, trader: &Address, price: &str, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        for (side, trader, price, seed) in [
+            (Side::Bid, &alice_address, "9.0", 1),
+            (Side::Bid, &alice_address, "10.0", 2),
+            (Side::Ask, &alice_address, "12.0", 3),
+            (Side::Ask, &bob_address, "13.0", 4),
+        ] {
+            order_book
+                .add_order(&mut manager, order(side, trader, price, seed))
+                .unwrap();
+        }
+        let cancelled = order_book.cancel_all(&mut manager, &alice_address).unwrap();
+        let prices: Vec<Decimal> = cancelled.into_iter().map(|order| order.price).collect();
+        assert_eq!(prices, vec!["9.00", "10.00", "12.00"]);
+        assert!(order_book.get_orders_by_trader(&alice_address).is_empty());
+        assert_eq!(order_book.open_order_count(&bob_address), 1);
+        let alice = &manager.accounts[&alice_address];
+        assert_eq!(u256_to_decimal(&alice.usd_hold()), "0.00");
+        assert_eq!(alice.ddx_hold(), U256::zero());
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "2.00");
+        assert_eq!(alice_json.usdBalance, "30.00");
+        assert!(order_book
+            .cancel_all(&mut manager, &alice_address)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn best_bid_and_offer() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        assert!(order_book.best_bid().is_none());
+        assert!(order_book.best_ask().is_none());
+        for (side, trader, amount, price, seed) in [
+            (Side::Ask, &bob_address, "0.5", "10.0", 1),
+            (Side::Ask, &bob_address, "0.5", "10.0", 2),
+            (Side::Ask, &bob_address, "2.0", "11.0", 3),
+            (Side::Bid, &alice_address, "1.0", "9.0", 4),
+        ] {
+            order_book
+                .add_order(&mut manager, new_order(side, trader, amount, price, seed))
+                .unwrap();
+        }
+        let bbo = |order_book: &OrderBook| {
+            let level = |level: Option<SimpleOrder>| {
+                let level = level.unwrap();
+                (level.price, level.amount)
+            };
+            (level(order_book.best_bid()), level(order_book.best_ask()))
+        };
+        assert_eq!(
+            bbo(&order_book),
+            (
+                ("9.00".to_string(), "1.00".to_string()),
+                ("10.00".to_string(), "1.00".to_string())
+            )
+        );
+        // takes the whole top ask level and rests the rest as the new best bid.
+        let fill_result = order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Bid, &alice_address, "1.5", "10.0", 5),
+            )
+            .unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        assert_eq!(
+            bbo(&order_book),
+            (
+                ("10.00".to_string(), "0.50".to_string()),
+                ("11.00".to_string(), "2.00".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn spread_and_mid_price() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut add = |order_book: &mut OrderBook, order: JsonOrder| {
+            let fill_result = order_book.add_order(&mut manager, order).unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        };
+        assert!(order_book.spread().is_none());
+        assert!(order_book.mid_price().is_none());
+        add(
+            &mut order_book,
+            new_order(Side::Ask, &bob_address, "1.0", "10.0", 1),
+        );
+        assert!(order_book.spread().is_none());
+        add(
+            &mut order_book,
+            new_order(Side::Bid, &alice_address, "1.0", "8.0", 2),
+        );
+        assert_eq!(order_book.spread().unwrap(), "2.00");
+        assert_eq!(order_book.mid_price().unwrap(), "9.00");
+        // a crossing bid takes the ask side out and rests above the old touch.
+        add(
+            &mut order_book,
+            new_order(Side::Bid, &alice_address, "1.5", "11.0", 3),
+        );
+        assert!(order_book.best_ask().is_none());
+        assert!(order_book.spread().is_none());
+        assert!(order_book.mid_price().is_none());
+        add(
+            &mut order_book,
+            new_order(Side::Ask, &bob_address, "1.0", "12.0", 4),
+        );
+        assert_eq!(order_book.spread().unwrap(), "1.00");
+        assert_eq!(order_book.mid_price().unwrap(), "11.50");
+    }
+
+    #[test]
+    fn l2_book_depth() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for i in 0..5 {
+            for (side, trader, price) in [
+                (Side::Bid, &alice_address, 5 - i),
+                (Side::Ask, &bob_address, 10 + i),
+            ] {
+                let order = JsonOrder {
+                    amount: "1.0".to_string(),
+                    price: format!("{}.0", price),
+                    side,
+                    nonce: get_nonce(price),
+                    traderAddress: trader.clone(),
+                    postOnly: false,
+                    reduceOnly: false,
+                    signature: None,
+                    expiresAt: None,
+                    displayAmount: None,
+                };
+                order_book.add_order(&mut manager, order).unwrap();
+            }
+        }
+        let prices = |levels: &[SimpleOrder]| -> Vec<Decimal> {
+            levels.iter().map(|level| level.price.clone()).collect()
+        };
+        let l2_book = order_book.generate_l2_order_book_with_depth(3);
+        assert_eq!(prices(&l2_book.bids), vec!["5.00", "4.00", "3.00"]);
+        assert_eq!(prices(&l2_book.asks), vec!["10.00", "11.00", "12.00"]);
+        let l2_book = order_book.generate_l2_order_book_with_depth(usize::MAX);
+        assert_eq!(l2_book.bids.len(), 5);
+        assert_eq!(l2_book.asks.len(), 5);
+    }
+
+    #[test]
+    fn fills_are_published() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        order_book.set_fill_sender(Some(sender));
+        for (price, seed) in [("11.0", 1), ("10.0", 2)] {
+            let fill_result = order_book
+                .add_order(
+                    &mut manager,
+                    new_order(Side::Ask, &bob_address, "1.0", price, seed),
+                )
+                .unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        }
+        assert!(receiver.try_recv().is_err());
+        let fill_result = order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Bid, &alice_address, "2.5", "11.0", 3),
+            )
+            .unwrap();
+        let published: Vec<Fill> = receiver.try_iter().collect();
+        assert_eq!(published.len(), 2);
+        for (published, returned) in published.iter().zip(&fill_result.filled_orders) {
+            assert_eq!(published.maker_hash, returned.maker_hash);
+            assert_eq!(published.fill_amount, returned.fill_amount);
+        }
+        let prices: Vec<Decimal> = published
+            .iter()
+            .map(|fill| u256_to_decimal(&fill.price))
+            .collect();
+        assert_eq!(prices, vec!["10.00", "11.00"]);
+        manager.update_accounts(fill_result).unwrap();
+        // publishing stops once the subscriber goes away.
+        drop(receiver);
+        let fill_result = order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Ask, &bob_address, "0.5", "11.0", 4),
+            )
+            .unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 1);
+        assert!(order_book.fill_sender.is_none());
+    }
+
+    #[test]
+    fn order_status() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().archive_removed_orders = true;
+        let order = |side: Side, trader: &Address, amount: &str, seed: u64| JsonOrder {
+            amount: amount.to_string(),
+            price: "10.0".to_string(),
+            side,
+            nonce: get_nonce(seed),
+            traderAddress: trader.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let mut hashes = Vec::new();
+        for (amount, seed) in [("2.0", 1), ("1.0", 2), ("1.0", 3)] {
+            let ask = order(Side::Ask, &bob_address, amount, seed);
+            hashes.push(ask.encode_order().unwrap().hash_hex().unwrap());
+            let fill_result = order_book.add_order(&mut manager, ask).unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        }
+        let status = order_book.get_order_status(hashes[0].clone()).unwrap();
+        assert!(matches!(status.status, OrderStatus::Created));
+        assert_eq!(status.remaining, "2.00");
+        let fill_result = order_book
+            .add_order(&mut manager, order(Side::Bid, &alice_address, "0.5", 4))
+            .unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        let status = order_book.get_order_status(hashes[0].clone()).unwrap();
+        assert!(matches!(status.status, OrderStatus::PartiallyFilled));
+        assert_eq!(status.remaining, "1.50");
+        assert_eq!(status.order.amount, "1.50");
+        // takes the rest of the first ask.
+        let fill_result = order_book
+            .add_order(&mut manager, order(Side::Bid, &alice_address, "1.5", 5))
+            .unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        let status = order_book.get_order_status(hashes[0].clone()).unwrap();
+        assert!(matches!(status.status, OrderStatus::Filled));
+        assert_eq!(status.remaining, "0.00");
+        order_book
+            .cancel_order(&mut manager, hashes[2].clone())
+            .unwrap();
+        let status = order_book.get_order_status(hashes[2].clone()).unwrap();
+        assert!(matches!(status.status, OrderStatus::Cancelled));
+        assert_eq!(status.remaining, "1.00");
+        assert!(matches!(
+            order_book
+                .get_order_status(hashes[1].clone())
+                .unwrap()
+                .status,
+            OrderStatus::Created
+        ));
+    }
+
+    #[test]
+    fn fill_result_totals() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for (amount, price, seed) in [("1.0", "10.0", 1), ("3.0", "12.0", 2)] {
+            let fill_result = order_book
+                .add_order(
+                    &mut manager,
+                    new_order(Side::Ask, &bob_address, amount, price, seed),
+                )
+                .unwrap();
+            assert_eq!(fill_result.total_filled(), "0.00");
+            assert_eq!(fill_result.average_price().unwrap(), "0.00");
+            manager.update_accounts(fill_result).unwrap();
+        }
+        // sweeps the first level and half of the second.
+        let fill_result = order_book
+            .add_order(
+                &mut manager,
+                new_order(Side::Bid, &alice_address, "2.5", "12.0", 3),
+            )
+            .unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        assert_eq!(fill_result.total_filled(), "2.50");
+        assert_eq!(fill_result.total_notional().unwrap(), "28.00");
+        // (1 * 10 + 1.5 * 12) / 2.5
+        assert_eq!(fill_result.average_price().unwrap(), "11.20");
+        // totals beyond the largest amount are an error rather than a panic.
+        let mut fill_result = fill_result;
+        fill_result.filled_orders[0].fill_amount = U256::MAX / 2;
+        let overflow = Err(BalanceError::Overflow(Asset::Usd));
+        assert_eq!(fill_result.total_notional(), overflow);
+        assert_eq!(fill_result.average_price(), overflow);
+    }
+
+    #[test]
+    fn deposit_and_withdraw() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let amount = |amount: &str| decimal_to_u256(&amount.to_string()).unwrap();
+        manager
+            .deposit(&alice_address, Asset::Ddx, amount("2.5"))
+            .unwrap();
+        manager
+            .deposit(&alice_address, Asset::Usd, amount("5.0"))
+            .unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.ddxBalance, "2.50");
+        assert_eq!(alice_json.usdBalance, "15.00");
+        let mut alice_order = order_init(1);
+        alice_order.price = "12.0".to_string();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        // 12 of alice's 15 USD back her bid.
+        assert_eq!(
+            manager
+                .withdraw(&alice_address, Asset::Usd, amount("4.0"))
+                .unwrap_err(),
+            OrderBookError::InsufficientFunds
+        );
+        manager
+            .withdraw(&alice_address, Asset::Usd, amount("2.0"))
+            .unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "13.00");
+        assert_eq!(
+            u256_to_decimal(&manager.accounts[&alice_address].usd_hold()),
+            "12.00"
+        );
+        let carol_address = Address::random();
+        assert_eq!(
+            manager
+                .deposit(&carol_address, Asset::Usd, amount("1.0"))
+                .unwrap_err(),
+            OrderBookError::NoSuchAccount
+        );
+        assert_eq!(
+            manager
+                .withdraw(&carol_address, Asset::Usd, amount("1.0"))
+                .unwrap_err(),
+            OrderBookError::NoSuchAccount
+        );
+        assert_eq!(
+            manager
+                .deposit(&alice_address, Asset::Ddx, U256::MAX)
+                .unwrap_err(),
+            OrderBookError::Balance(BalanceError::Overflow(Asset::Ddx))
+        );
+    }
+
+    #[test]
+    fn available_and_held_funds() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut alice_order = order_init(1);
+        alice_order.price = "8.0".to_string();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        let alice_json = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice_json.usdBalance, "10.00");
+        assert_eq!(alice_json.funds.usdHold, "8.00");
+        assert_eq!(alice_json.funds.usdAvailable, "2.00");
+        assert_eq!(alice_json.funds.ddxHold, "0.00");
+        assert_eq!(alice_json.funds.ddxAvailable, "0.00");
+        let json = serde_json::to_value(&alice_json).unwrap();
+        assert_eq!(json["usdHold"], "8.00");
+        assert_eq!(json["usdAvailable"], "2.00");
+    }
+
+    #[test]
+    fn configurable_eip712_domain() {
+        let order = order_init(1);
+        let encoded = order.encode_order().unwrap();
+        let domain = default_domain();
+        // the runtime domain matches the derived one by default.
+        assert_eq!(
+            eip712_digest(&encoded, &domain).unwrap(),
+            encoded.encode_eip712().unwrap()
+        );
+        assert_eq!(
+            encoded.hash_hex_in(&domain).unwrap(),
+            encoded.hash_hex().unwrap()
+        );
+        let cancel = JsonCancel {
+            orderHash: encoded.hash_hex().unwrap(),
+            nonce: "0x01".to_string(),
+            signature: String::new(),
+        }
+        .encode_cancel()
+        .unwrap();
+        assert_eq!(
+            eip712_digest(&cancel, &domain).unwrap(),
+            cancel.encode_eip712().unwrap()
+        );
+        // another chain id or verifying contract makes another hash.
+        let mainnet = EIP712Domain {
+            chain_id: Some(1.into()),
+            ..default_domain()
+        };
+        let contract = EIP712Domain {
+            verifying_contract: Some(Address::from_low_u64_be(1)),
+            ..default_domain()
+        };
+        let mainnet_hash = encoded.hash_hex_in(&mainnet).unwrap();
+        assert_ne!(mainnet_hash, encoded.hash_hex().unwrap());
+        assert_ne!(encoded.hash_hex_in(&contract).unwrap(), mainnet_hash);
+        // the book hashes and verifies orders in its configured domain.
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().domain = mainnet.clone();
+        assert_eq!(order_book.order_hash(&encoded).unwrap(), mainnet_hash);
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let mut signed = order.clone();
+        signed.traderAddress = wallet.address();
+        let digest = eip712_digest(&signed.encode_order().unwrap(), &mainnet).unwrap();
+        let signature = wallet.sign_hash(H256::from(digest)).to_string();
+        let signer = signed.encode_order().unwrap();
+        assert_eq!(
+            signer.recover_signer(&signature, &mainnet).unwrap(),
+            wallet.address()
+        );
+        assert_ne!(
+            signer.recover_signer(&signature, &domain).unwrap(),
+            wallet.address()
+        );
+    }
+
+    #[test]
+    fn side_spellings() {
+        for spelling in ["\"Bid\"", "\"buy\"", "\"BUY\"", "\"bid\"", "\"0\"", "0"] {
+            let side: Side = serde_json::from_str(spelling).unwrap();
+            assert!(matches!(side, Side::Bid), "{}", spelling);
+        }
+        for spelling in ["\"Ask\"", "\"sell\"", "\"Sell\"", "\"ASK\"", "\"1\"", "1"] {
+            let side: Side = serde_json::from_str(spelling).unwrap();
+            assert!(matches!(side, Side::Ask), "{}", spelling);
+        }
+        for spelling in ["\"hold\"", "2", "-1", "\"\""] {
+            assert!(
+                serde_json::from_str::<Side>(spelling).is_err(),
+                "{}",
+                spelling
+            );
+        }
+        assert!(matches!("Sell".parse::<Side>(), Ok(Side::Ask)));
+        assert!("hold".parse::<Side>().is_err());
+        // orders still serialize the variant name.
+        let mut order = order_init(1);
+        order.side = "buy".parse().unwrap();
+        assert!(serde_json::to_string(&order)
+            .unwrap()
+            .contains("\"side\":\"Bid\""));
+    }
+
+    #[test]
+    fn price_level_slots_are_bounded() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "1000.0",
+            "1000.0",
+            &bob_address,
+            "1000.0",
+            "1000.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let order = |trader: &Address, side: Side, price: String, nonce: u64| JsonOrder {
+            amount: "0.1".to_string(),
+            nonce: get_nonce(nonce),
+            price,
+            side,
+            traderAddress: *trader,
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        for i in 0..1000 {
+            let price = format!("{}.{:02}", 1 + i / 100, i % 100);
+            // a level emptied by a cancel...
+            let bid = order(&alice_address, Side::Bid, price.clone(), i);
+            let order_id = order_book.order_hash(&bid.encode_order().unwrap()).unwrap();
+            order_book.add_order(&mut manager, bid).unwrap();
+            order_book.cancel_order(&mut manager, order_id).unwrap();
+            // ...and one emptied by a match.
+            let ask = order(&alice_address, Side::Ask, price.clone(), 1000 + i);
+            order_book.add_order(&mut manager, ask).unwrap();
+            let bid = order(&bob_address, Side::Bid, price, i);
+            let fill_result = order_book.add_order(&mut manager, bid).unwrap();
+            assert_eq!(fill_result.filled_orders.len(), 1);
+            manager.update_accounts(fill_result).unwrap();
+        }
+        assert!(order_book.bid_book.price_map.is_empty());
+        assert!(order_book.ask_book.price_map.is_empty());
+        assert_eq!(order_book.bid_book.price_levels.len(), 1);
+        assert_eq!(order_book.ask_book.price_levels.len(), 1);
+        assert!(order_book.check_integrity(&manager).is_empty());
+    }
+
+    #[test]
+    fn iceberg_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "20.0", "0.0", &bob_address, "0.0", "200.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let iceberg = JsonOrder {
+            amount: "10.0".to_string(),
+            nonce: get_nonce(1),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            traderAddress: alice_address,
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: Some("1.0".to_string()),
+        };
+        let iceberg_id = order_book
+            .order_hash(&iceberg.encode_order().unwrap())
+            .unwrap();
+        order_book.add_order(&mut manager, iceberg).unwrap();
+        let shown = |order_book: &OrderBook| {
+            let l2 = order_book.generate_l2_order_book();
+            assert_eq!(l2.asks.len(), 1);
+            assert_eq!(order_book.best_ask().unwrap().amount, l2.asks[0].amount);
+            l2.asks[0].amount.clone()
+        };
+        assert_eq!(shown(&order_book), "1.00");
+        assert_eq!(order_book.generate_l3_order_book(10).asks[0].amount, "1.00");
+        // the owner still sees the whole order.
+        let order = order_book.get_order(iceberg_id.clone()).unwrap();
+        assert_eq!(order.amount, "10.00");
+        assert_eq!(order.displayAmount.as_deref(), Some("1.00"));
+        let mut nonce = 100;
+        let mut buy = |order_book: &mut OrderBook, amount: &str| {
+            nonce += 1;
+            let bid = JsonOrder {
+                amount: amount.to_string(),
+                nonce: get_nonce(nonce),
+                price: "10.0".to_string(),
+                side: Side::Bid,
+                traderAddress: bob_address,
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            let fill_result = order_book.add_order(&mut manager, bid).unwrap();
+            assert_eq!(fill_result.filled_orders.len(), 1);
+            manager.update_accounts(fill_result).unwrap();
+        };
+        // fills come off the shown slice...
+        buy(&mut order_book, "0.4");
+        assert_eq!(shown(&order_book), "0.60");
+        // ...which is shown again once filled.
+        buy(&mut order_book, "0.6");
+        assert_eq!(shown(&order_book), "1.00");
+        // matching takes from the whole amount, beyond the shown slice.
+        buy(&mut order_book, "8.5");
+        assert_eq!(shown(&order_book), "0.50");
+        buy(&mut order_book, "0.5");
+        assert!(order_book.generate_l2_order_book().asks.is_empty());
+        assert!(order_book.ask_book.icebergs.is_empty());
+        // a zero display amount is rejected.
+        let mut empty = order_init(2);
+        empty.displayAmount = Some("0".to_string());
+        assert_eq!(
+            order_book.add_order(&mut manager, empty).unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+    }
+
+    #[test]
+    fn reduce_only_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "5.0", "100.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let order = |side: Side, amount: &str, seed: u64| JsonOrder {
+            amount: amount.to_string(),
+            nonce: get_nonce(seed),
+            price: "10.0".to_string(),
+            side,
+            traderAddress: alice_address,
+            postOnly: false,
+            reduceOnly: true,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        // a bid only ever adds to the position.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Bid, "1.0", 1))
+                .unwrap_err(),
+            OrderBookError::ReduceOnly
+        );
+        // an ask beyond the held DDX would go short.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Ask, "6.0", 2))
+                .unwrap_err(),
+            OrderBookError::ReduceOnly
+        );
+        // one within the position rests as usual.
+        order_book
+            .add_order(&mut manager, order(Side::Ask, "3.0", 3))
+            .unwrap();
+        assert_eq!(order_book.generate_l2_order_book().asks.len(), 1);
+        // and counts against what is left to reduce.
+        assert_eq!(
+            order_book
+                .add_order(&mut manager, order(Side::Ask, "3.0", 4))
+                .unwrap_err(),
+            OrderBookError::ReduceOnly
+        );
+        order_book
+            .add_order(&mut manager, order(Side::Ask, "1.5", 5))
+            .unwrap();
+        assert!(order_book.check_integrity(&manager).is_empty());
+    }
+
+    #[test]
+    fn order_sequence_numbers() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "10.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order_ids = Vec::new();
+        let mut last_seq = order_book.sequence();
+        for seed in 1..=4 {
+            let mut order = order_init(seed);
+            order.price = format!("{}.0", seed);
+            order_ids.push(
+                order_book
+                    .order_hash(&order.encode_order().unwrap())
+                    .unwrap(),
+            );
+            order_book.add_order(&mut manager, order).unwrap();
+            assert!(order_book.sequence() > last_seq);
+            last_seq = order_book.sequence();
+        }
+        let statuses: Vec<JsonOrderStatus> = order_ids
+            .iter()
+            .map(|order_id| order_book.get_order_status(order_id.clone()).unwrap())
+            .collect();
+        for pair in statuses.windows(2) {
+            assert!(pair[0].seq < pair[1].seq);
+            assert!(pair[0].createdAt <= pair[1].createdAt);
+        }
+        // fills echo the sequence numbers of both orders.
+        let bob_order = JsonOrder {
+            amount: "1.5".to_string(),
+            nonce: get_nonce(5),
+            price: "3.0".to_string(),
+            side: Side::Ask,
+            traderAddress: bob_address,
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        let fills: Vec<JsonFill> = fill_result
+            .filled_orders
+            .iter()
+            .map(Fill::to_json)
+            .collect();
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].maker_seq, statuses[3].seq);
+        assert_eq!(fills[1].maker_seq, statuses[2].seq);
+        assert!(fills
+            .iter()
+            .all(|fill| fill.taker_seq == order_book.sequence()));
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "20.0",
+            "200.0",
+            &bob_address,
+            "20.0",
+            "200.0",
+        );
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order_ids = Vec::new();
+        for (seed, side, price, trader) in [
+            (1, Side::Bid, "9.0", alice_address),
+            (2, Side::Bid, "9.5", bob_address),
+            (3, Side::Bid, "9.5", alice_address),
+            (4, Side::Ask, "10.5", bob_address),
+            (5, Side::Ask, "11.0", alice_address),
+        ] {
+            let order = JsonOrder {
+                amount: "2.0".to_string(),
+                nonce: get_nonce(seed),
+                price: price.to_string(),
+                side,
+                traderAddress: trader,
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: Some(u64::MAX),
+                displayAmount: (seed == 5).then(|| "0.5".to_string()),
+            };
+            order_ids.push(
+                order_book
+                    .order_hash(&order.encode_order().unwrap())
+                    .unwrap(),
+            );
+            let fill_result = order_book.add_order(&mut manager, order).unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        }
+        // partially fill the best bid.
+        let mut ask = order_init(6);
+        ask.traderAddress = bob_address;
+        ask.side = Side::Ask;
+        ask.price = "9.5".to_string();
+        let fill_result = order_book.add_order(&mut manager, ask).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+
+        let mut restored = OrderBook::new("DDX".to_string());
+        restored.restore(&order_book.snapshot()).unwrap();
+        let mut restored_manager = AccountManager::new();
+        restored_manager.restore(&manager.snapshot()).unwrap();
+        assert_eq!(
+            serde_json::to_value(restored.generate_l2_order_book()).unwrap(),
+            serde_json::to_value(order_book.generate_l2_order_book()).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(restored.generate_l3_order_book(10)).unwrap(),
+            serde_json::to_value(order_book.generate_l3_order_book(10)).unwrap()
+        );
+        for order_id in order_ids.iter() {
+            assert_eq!(
+                restored.get_order(order_id.clone()).unwrap().to_string(),
+                order_book.get_order(order_id.clone()).unwrap().to_string()
+            );
+            assert_eq!(
+                serde_json::to_value(restored.get_order_status(order_id.clone()).unwrap()).unwrap(),
+                serde_json::to_value(order_book.get_order_status(order_id.clone()).unwrap())
+                    .unwrap()
+            );
+        }
+        assert_eq!(restored.sequence(), order_book.sequence());
+        for trader in [alice_address, bob_address] {
+            assert_eq!(
+                serde_json::to_value(restored_manager.get_json_account(&trader).unwrap()).unwrap(),
+                serde_json::to_value(manager.get_json_account(&trader).unwrap()).unwrap()
+            );
+        }
+        assert!(restored.check_integrity(&restored_manager).is_empty());
+        // the restored index finds resting orders, and used nonces stay used.
+        restored
+            .cancel_order(&mut restored_manager, order_ids[0].clone())
+            .unwrap();
+        let mut replay = order_init(1);
+        replay.price = "9.0".to_string();
+        assert_eq!(
+            restored
+                .add_order(&mut restored_manager, replay)
+                .unwrap_err(),
+            OrderBookError::NonceUsed
+        );
+        // a snapshot only restores into a book of the same symbol.
+        assert!(matches!(
+            OrderBook::new("ETH".to_string()).restore(&order_book.snapshot()),
+            Err(OrderBookError::InvalidSnapshot(_))
+        ));
+        assert!(matches!(
+            restored.restore(b"{}"),
+            Err(OrderBookError::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn replay_action_log() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "20.0",
+            "200.0",
+            &bob_address,
+            "20.0",
+            "200.0",
+        );
+        let start = manager.snapshot();
+        let config = MarketConfig {
+            record_actions: true,
+            ..MarketConfig::default()
+        };
+        let mut order_book = OrderBook::with_config("DDX".to_string(), config.clone());
+        let order = |seed: u64, side: Side, amount: &str, price: &str, trader: Address| JsonOrder {
+            amount: amount.to_string(),
+            nonce: get_nonce(seed),
+            price: price.to_string(),
+            side,
+            traderAddress: trader,
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        let bid = order(1, Side::Bid, "2.0", "9.5", alice_address);
+        let bid_hash = order_book.order_hash(&bid.encode_order().unwrap()).unwrap();
+        let ask = order(2, Side::Ask, "3.0", "10.5", bob_address);
+        let ask_hash = order_book.order_hash(&ask.encode_order().unwrap()).unwrap();
+        let actions = [
+            Action::Add(bid),
+            Action::Add(ask),
+            Action::Add(order(3, Side::Bid, "1.0", "9.0", alice_address)),
+            // fills part of the ask.
+            Action::Add(order(4, Side::Bid, "1.5", "10.5", alice_address)),
+            Action::Replace {
+                old_hash: bid_hash,
+                order: order(5, Side::Bid, "1.0", "9.8", alice_address),
+            },
+            Action::Cancel(ask_hash),
+            Action::Add(order(6, Side::Ask, "0.5", "9.0", bob_address)),
+        ];
+        for action in actions {
+            if let ActionOutcome::Order(fill_result) =
+                order_book.apply(&mut manager, action).unwrap()
+            {
+                manager.update_accounts(fill_result).unwrap();
+            }
+        }
+        // rejected actions are not logged.
+        assert!(order_book
+            .apply(&mut manager, Action::Cancel("0x01".to_string()))
+            .is_err());
+        let log = order_book.take_actions();
+        assert_eq!(log.len(), 7);
+        assert!(order_book.take_actions().is_empty());
+
+        let mut replayed_manager = AccountManager::new();
+        replayed_manager.restore(&start).unwrap();
+        let mut replayed = OrderBook::with_config("DDX".to_string(), config);
+        // the log goes through serialization as it would to storage.
+        let log: Vec<Action> = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        replayed.replay(&mut replayed_manager, &log).unwrap();
+        assert_eq!(
+            serde_json::to_value(replayed.generate_l2_order_book()).unwrap(),
+            serde_json::to_value(order_book.generate_l2_order_book()).unwrap()
+        );
+        for trader in [alice_address, bob_address] {
+            assert_eq!(
+                serde_json::to_value(replayed_manager.get_json_account(&trader).unwrap()).unwrap(),
+                serde_json::to_value(manager.get_json_account(&trader).unwrap()).unwrap()
+            );
+        }
+        assert_eq!(replayed.take_actions().len(), log.len());
+        assert!(replayed.check_integrity(&replayed_manager).is_empty());
+    }
+
+    #[test]
+    fn trade_history_is_chronological() {
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.config_mut().trade_retention = TradeRetention::MaxEntries(3);
+        let mut maker_hashes = Vec::new();
+        for seed in [1, 3, 5, 7] {
+            trade_once(&mut order_book, seed);
+            maker_hashes.push(order_book.trades.back().unwrap().maker_hash.clone());
+        }
+        // the history is bounded and keeps the fills in the order they executed.
+        assert_eq!(order_book.trade_history().len(), 3);
+        let trades = order_book.latest_trades(usize::MAX);
+        assert_eq!(trades.len(), 3);
+        for (trade, maker_hash) in trades.iter().zip(&maker_hashes[1..]) {
+            assert_eq!(&trade.maker_hash, maker_hash);
+        }
+        for pair in trades.windows(2) {
+            assert!(pair[0].timestamp <= pair[1].timestamp);
+            assert!(pair[0].taker_seq < pair[1].taker_seq);
+        }
+        // a limit keeps the latest fills, still oldest first.
+        let latest = order_book.latest_trades(2);
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[0].maker_hash, maker_hashes[2]);
+        assert_eq!(latest[1].maker_hash, maker_hashes[3]);
+        assert!(order_book.latest_trades(0).is_empty());
+        let first = trades[0].timestamp;
+        assert_eq!(
+            order_book.trade_history().query(first, u64::MAX, 1)[0].maker_hash,
+            maker_hashes[3]
+        );
+        // the log is bounded unless a market opts out.
+        assert!(matches!(
+            MarketConfig::default().trade_retention,
+            TradeRetention::MaxEntries(10_000)
+        ));
+    }
+
+    #[test]
+    fn candles_from_trades() {
+        let mut order_book = OrderBook::new("DDX".to_string());
+        for seed in [1, 3, 5] {
+            trade_once(&mut order_book, seed);
+        }
+        // spread the fills over two minutes with different prices and amounts.
+        let fill = order_book.trades.iter().next().unwrap().clone();
+        let unit = U256::exp10(18);
+        order_book.trades = [(60_000, 10, 1), (119_999, 12, 2), (120_000, 11, 3)]
+            .iter()
+            .map(|(timestamp, price, amount)| Fill {
+                timestamp: *timestamp,
+                price: unit * *price,
+                fill_amount: unit * *amount,
+                ..fill.clone()
+            })
+            .collect();
+        let candles = order_book.candles(60_000, 0, u64::MAX);
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].start, 60_000);
+        assert_eq!(candles[0].open, "10.00");
+        assert_eq!(candles[0].high, "12.00");
+        assert_eq!(candles[0].low, "10.00");
+        assert_eq!(candles[0].close, "12.00");
+        assert_eq!(candles[0].volume, "3.00");
+        assert_eq!(candles[1].start, 120_000);
+        assert_eq!(candles[1].open, "11.00");
+        assert_eq!(candles[1].close, "11.00");
+        assert_eq!(candles[1].volume, "3.00");
+        // a longer interval folds them into one candle.
+        let candles = order_book.candles(parse_interval("5m").unwrap(), 0, u64::MAX);
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].start, 0);
+        assert_eq!(candles[0].close, "11.00");
+        assert_eq!(candles[0].volume, "6.00");
+        assert_eq!(parse_interval("1m"), Ok(60_000));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("1w").is_err());
+    }
+
+    #[test]
+    fn oversized_fill_is_a_balance_error() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let fill_result = order_book.add_order(&mut manager, order_init(1)).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        // alice holds 10 USD for her bid and bob holds no DDX, so neither can settle 2 DDX.
+        let unit = U256::exp10(18);
+        let fill = Fill {
+            from: bob_address.clone(),
+            to: alice_address.clone(),
+            maker_hash: get_nonce(1),
+            taker_hash: get_nonce(2),
+            taker_side: Side::Ask,
+            fill_amount: unit * 2,
+            price: unit * 10,
+            bid_price: unit * 10,
+            bid_amount: unit * 2,
+            mid: None,
+            maker_seq: 0,
+            taker_seq: 0,
+            timestamp: 0,
+        };
+        let mut fill_result = FillResult::new(U256::zero(), Side::Ask);
+        fill_result.filled_orders.push(fill);
+        assert_eq!(
+            manager.update_accounts(fill_result),
+            Err(BalanceError::Underflow(Asset::Ddx))
+        );
+        // both accounts are left as they were.
+        let alice = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice.ddxBalance, "0.00");
+        assert_eq!(alice.funds.usdHold, "10.00");
+        let bob = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob.ddxBalance, "1.00");
+        assert_eq!(bob.funds.ddxHold, "0.00");
+        assert_eq!(bob.usdBalance, "0.00");
+    }
+
+    #[test]
+    fn notional_rounding_covers_settlement() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "1.0", "0.0");
+        let unit = U256::exp10(18);
+        // 0.3 DDX at 10/3 USD is worth 1 USD less a fraction of a unit, which truncates.
+        let price = U256::from(3_333_333_333_333_333_333u64);
+        let tenth = unit / 10;
+        assert_eq!(
+            notional(&(tenth * 3), &price, Rounding::Down),
+            Some(unit - 1)
+        );
+        assert_eq!(notional(&(tenth * 3), &price, Rounding::Up), Some(unit));
+        let bid = Order {
+            amount: tenth * 3,
+            nonce: U256::one(),
+            price,
+            side: 0,
+            traderAddress: alice_address.clone(),
+        };
+        manager.hold_funds(&bid).unwrap();
+        // a third of the bid fills, paying its notional rounded down out of the hold.
+        let fill = Fill {
+            from: bob_address.clone(),
+            to: alice_address.clone(),
+            maker_hash: get_nonce(1),
+            taker_hash: get_nonce(2),
+            taker_side: Side::Ask,
+            fill_amount: tenth,
+            price,
+            bid_price: price,
+            bid_amount: tenth * 3,
+            mid: None,
+            maker_seq: 0,
+            taker_seq: 0,
+            timestamp: 0,
+        };
+        let ask = Order {
+            amount: tenth,
+            nonce: U256::from(2),
+            price,
+            side: 1,
+            traderAddress: bob_address.clone(),
+        };
+        manager.hold_funds(&ask).unwrap();
+        let mut fill_result = FillResult::new(U256::zero(), Side::Ask);
+        fill_result.filled_orders.push(fill);
+        manager.update_accounts(fill_result).unwrap();
+        let paid = notional(&tenth, &price, Rounding::Down).unwrap();
+        // cancelling the rest releases exactly the hold left, with no unit stuck in it.
+        let remaining = Order {
+            amount: tenth * 2,
+            ..bid
+        };
+        let alice = manager.release_pending_fund(&remaining).unwrap();
+        assert!(alice.usd_hold().is_zero());
+        assert_eq!(alice.total_usd().unwrap(), unit * 10 - paid);
+        assert_eq!(alice.total_ddx().unwrap(), tenth);
+    }
+
+    #[test]
+    fn simulated_fills_are_not_published() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "5.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_order = JsonOrder {
+            amount: "1.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(1),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        order_book.set_fill_sender(Some(sender));
+        for check_balances in [true, false] {
+            let fill_result = order_book
+                .simulate_order(&manager, order_init(2), check_balances)
+                .unwrap();
+            assert_eq!(fill_result.filled_orders.len(), 1);
+        }
+        assert!(receiver.try_recv().is_err());
+        // the subscriber is still attached and sees the real trade.
+        order_book.add_order(&mut manager, order_init(2)).unwrap();
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn fill_or_kill_ask_under_fill_cap() {
+        // bids only fit within two fills when taken from the highest price down.
+        for (amounts, fillable) in [
+            (["0.5", "1.0", "1.0"], true),
+            (["1.0", "1.0", "0.5"], false),
+        ] {
+            let (alice_address, bob_address) = address_init();
+            let mut manager =
+                account_init(&alice_address, "0.0", "100.0", &bob_address, "5.0", "0.0");
+            let mut order_book = OrderBook::new("DDX".to_string());
+            order_book.config_mut().max_fills_per_submission = Some(2);
+            for (i, (amount, price)) in amounts.iter().zip(["10.0", "11.0", "12.0"]).enumerate() {
+                let alice_order = JsonOrder {
+                    amount: amount.to_string(),
+                    price: price.to_string(),
+                    side: Side::Bid,
+                    nonce: get_nonce(i as u64),
+                    traderAddress: alice_address.clone(),
+                    postOnly: false,
+                    reduceOnly: false,
+                    signature: None,
+                    expiresAt: None,
+                    displayAmount: None,
+                };
+                order_book.add_order(&mut manager, alice_order).unwrap();
+            }
+            let fok = JsonOrder {
+                amount: "2.0".to_string(),
+                price: "10.0".to_string(),
+                side: Side::Ask,
+                nonce: get_nonce(10),
+                traderAddress: bob_address.clone(),
+                postOnly: false,
+                reduceOnly: false,
+                signature: None,
+                expiresAt: None,
+                displayAmount: None,
+            };
+            let result = order_book.add_order_fill_or_kill(&mut manager, fok);
+            if fillable {
+                let fill_result = result.unwrap();
+                assert!(matches!(fill_result.status, OrderStatus::Filled));
+                let prices: Vec<Decimal> = fill_result
+                    .filled_orders
+                    .iter()
+                    .map(|fill| u256_to_decimal(&fill.price))
+                    .collect();
+                assert_eq!(prices, vec!["12.00", "11.00"]);
+            } else {
+                assert_eq!(result.unwrap_err(), OrderBookError::InsufficientLiquidity);
+                assert_eq!(order_book.order_loc.len(), 3);
+            }
+        }
+    }
+
+    #[test]
+    fn all_or_none_matches_like_limit_orders() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "40.0", &bob_address, "3.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        // a zero amount is rejected, also at a price without a level.
+        let mut alice_order = order_init(1);
+        alice_order.amount = "0".to_string();
+        assert_eq!(
+            order_book
+                .add_order_all_or_none(&mut manager, alice_order)
+                .unwrap_err(),
+            OrderBookError::InvalidOrder
+        );
+        let bob_order = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "10.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: Some("0.5".to_string()),
+        };
+        order_book.add_order(&mut manager, bob_order).unwrap();
+        let mut alice_order = order_init(3);
+        alice_order.amount = "0.3".to_string();
+        let fill_result = order_book
+            .add_order_all_or_none(&mut manager, alice_order)
+            .unwrap();
+        assert!(matches!(fill_result.status, OrderStatus::Filled));
+        assert_eq!(fill_result.filled_orders[0].taker_seq, 2);
+        assert_eq!(fill_result.filled_orders[0].maker_seq, 1);
+        manager.update_accounts(fill_result).unwrap();
+        // the shown slice of the iceberg shrinks by the fill.
+        let l2_book = order_book.generate_l2_order_book();
+        assert_eq!(l2_book.asks[0].amount, "0.20");
+    }
+
+    #[test]
+    fn replace_book_with_required_signatures() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut manager = account_init(&trader, "0.0", "20.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut order = order_init(1);
+        order.traderAddress = trader;
+        let old_hash = order.encode_order().unwrap().hash_hex().unwrap();
+        order_book.add_order(&mut manager, order).unwrap();
+        order_book.config_mut().require_signatures = true;
+        let mut snapshot_order = order_init(2);
+        snapshot_order.traderAddress = trader;
+        let digest = snapshot_order
+            .encode_order()
+            .unwrap()
+            .encode_eip712()
+            .unwrap();
+        snapshot_order.signature = Some(wallet.sign_hash(H256::from(digest)).to_string());
+        // the trader cannot cancel unsigned, but the operator still reloads the book.
+        assert!(order_book
+            .cancel_order(&mut manager, old_hash.clone())
+            .is_err());
+        order_book
+            .replace_book(&mut manager, vec![snapshot_order])
+            .unwrap();
+        assert!(order_book.get_order(old_hash).is_err());
+        assert_eq!(order_book.open_order_count(&trader), 1);
+        let trader_json = manager.get_json_account(&trader).unwrap();
+        assert_eq!(trader_json.funds.usdHold, "10.00");
+    }
+
+    #[test]
+    fn signed_bulk_cancels() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let other_wallet = "0123456789012345678901234567890123456789012345678901234567890123"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut manager = account_init(&trader, "0.0", "50.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut add = |order_book: &mut OrderBook, seed: u64| {
+            let mut order = order_init(seed);
+            order.traderAddress = trader;
+            order_book.add_order(&mut manager, order).unwrap();
+        };
+        add(&mut order_book, 1);
+        add(&mut order_book, 2);
+        order_book.config_mut().require_signatures = true;
+        let sign = |wallet: &LocalWallet, nonce: u64| {
+            let mut cancel = JsonCancelAll {
+                traderAddress: trader,
+                nonce: get_nonce(nonce),
+                signature: String::new(),
+            };
+            let digest = cancel.encode_cancel_all().unwrap().encode_eip712().unwrap();
+            cancel.signature = wallet.sign_hash(H256::from(digest)).to_string();
+            cancel
+        };
+        // a cancel signed by another key is rejected.
+        assert!(matches!(
+            order_book.cancel_all_signed(&mut manager, &sign(&other_wallet, 10)),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        assert_eq!(order_book.open_order_count(&trader), 2);
+        // the trader's cancel goes through although signatures are required.
+        let cancel = sign(&wallet, 11);
+        let cancelled = order_book.cancel_all_signed(&mut manager, &cancel).unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(order_book.open_order_count(&trader), 0);
+        assert_eq!(manager.accounts[&trader].usd_hold(), U256::zero());
+        // a replay cannot cancel orders added later.
+        order_book.config_mut().require_signatures = false;
+        let mut order = order_init(3);
+        order.traderAddress = trader;
+        order_book.add_order(&mut manager, order).unwrap();
+        assert!(matches!(
+            order_book.cancel_all_signed(&mut manager, &cancel),
+            Err(OrderBookError::NonceUsed)
+        ));
+        assert_eq!(order_book.open_order_count(&trader), 1);
+    }
+
+    #[test]
+    fn fill_settles_both_legs_or_neither() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let fill_result = order_book.add_order(&mut manager, order_init(1)).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        let bob_ask = JsonOrder {
+            amount: "2.0".to_string(),
+            price: "12.0".to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(2),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, bob_ask).unwrap();
+        // bob's leg of 2 DDX fits his hold, alice's leg of 20 USD does not fit hers.
+        let unit = U256::exp10(18);
+        let fill = Fill {
+            from: bob_address.clone(),
+            to: alice_address.clone(),
+            maker_hash: get_nonce(1),
+            taker_hash: get_nonce(2),
+            taker_side: Side::Ask,
+            fill_amount: manager.accounts[&bob_address].ddx_hold(),
+            price: unit * 10,
+            bid_price: unit * 10,
+            bid_amount: manager.accounts[&bob_address].ddx_hold(),
+            mid: None,
+            maker_seq: 0,
+            taker_seq: 0,
+            timestamp: 0,
+        };
+        let mut fill_result = FillResult::new(U256::zero(), Side::Ask);
+        fill_result.filled_orders.push(fill);
+        assert_eq!(
+            manager.update_accounts(fill_result),
+            Err(BalanceError::Underflow(Asset::Usd))
+        );
+        let alice = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice.ddxBalance, "0.00");
+        assert_eq!(alice.funds.usdHold, "10.00");
+        let bob = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob.ddxBalance, "2.00");
+        assert_eq!(bob.funds.ddxHold, "2.00");
+        assert_eq!(bob.usdBalance, "0.00");
+    }
+
+    #[test]
+    fn commit_rechecks_the_book() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "2.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let ask = |price: &str, seed: u64| JsonOrder {
+            amount: "1.0".to_string(),
+            price: price.to_string(),
+            side: Side::Ask,
+            nonce: get_nonce(seed),
+            traderAddress: bob_address.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        };
+        order_book.add_order(&mut manager, ask("11.0", 1)).unwrap();
+        let mut alice_order = order_init(2);
+        alice_order.postOnly = true;
+        let token = order_book.reserve(&mut manager, alice_order).unwrap();
+        // an ask arriving before the commit makes the post-only bid cross.
+        order_book.add_order(&mut manager, ask("10.0", 3)).unwrap();
+        assert!(order_book.commit(&mut manager, token).is_none());
+        assert!(order_book.trades.is_empty());
+        assert_eq!(order_book.open_order_count(&alice_address), 0);
+        assert_eq!(manager.accounts[&alice_address].usd_hold(), U256::zero());
+        assert!(order_book.abort(&mut manager, token).is_none());
+        // neither is a reservation committed once the book is read-only.
+        let token = order_book.reserve(&mut manager, order_init(4)).unwrap();
+        order_book.set_read_only(true);
+        assert!(order_book.commit(&mut manager, token).is_none());
+        assert!(order_book.trades.is_empty());
+        assert_eq!(manager.accounts[&alice_address].usd_hold(), U256::zero());
+    }
+
+    #[test]
+    fn bid_fees_are_held_with_the_order() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = AccountManager::with_fees(FeeSchedule {
+            maker_bps: 10,
+            taker_bps: 20,
+        });
+        for (user, address, ddx, usd) in [
+            ("alice", alice_address, "0.0", "20.0"),
+            ("bob", bob_address, "1.0", "0.0"),
+        ] {
+            manager
+                .add_json_account(
+                    user,
+                    JsonAccount {
+                        ddxBalance: ddx.to_string(),
+                        usdBalance: usd.to_string(),
+                        traderAddress: address,
+                        balances: Default::default(),
+                        funds: Default::default(),
+                    },
+                )
+                .unwrap();
+        }
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let fill_result = order_book.add_order(&mut manager, order_init(1)).unwrap();
+        // 10 USD of notional and 0.02 USD of fee at the taker rate.
+        assert_eq!(u256_to_decimal(&fill_result.reserved.unwrap().1), "10.02");
+        manager.update_accounts(fill_result).unwrap();
+        // alice keeps no free USD to pay the fee from.
+        let free = manager
+            .get_json_account(&alice_address)
+            .unwrap()
+            .funds
+            .usdAvailable;
+        manager
+            .withdraw(&alice_address, Asset::Usd, decimal_to_u256(&free).unwrap())
+            .unwrap();
+        let mut bob_order = order_init(2);
+        bob_order.side = Side::Ask;
+        bob_order.traderAddress = bob_address;
+        let fill_result = order_book.add_order(&mut manager, bob_order).unwrap();
+        manager.update_accounts(fill_result).unwrap();
+        // alice paid the maker fee out of her hold and bob the taker fee out of the proceeds.
+        assert_eq!(u256_to_decimal(&manager.fee_pool()), "0.03");
+        let alice = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice.ddxBalance, "1.00");
+        assert_eq!(alice.usdBalance, "0.01");
+        let bob = manager.get_json_account(&bob_address).unwrap();
+        assert_eq!(bob.usdBalance, "9.98");
+    }
+
+    #[test]
+    fn filled_orders_leave_no_hold() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = AccountManager::with_fees(FeeSchedule {
+            maker_bps: 10,
+            taker_bps: 20,
+        });
+        for (user, address, ddx, usd) in [
+            ("alice", alice_address.clone(), "0.0", "40.0"),
+            ("bob", bob_address.clone(), "4.0", "0.0"),
+        ] {
+            manager
+                .add_json_account(
+                    user,
+                    JsonAccount {
+                        ddxBalance: ddx.to_string(),
+                        usdBalance: usd.to_string(),
+                        traderAddress: address,
+                        balances: Default::default(),
+                        funds: Default::default(),
+                    },
+                )
+                .unwrap();
+        }
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let bob_ask = |seed: u64, amount: &str, price: &str| {
+            let mut order = order_init(seed);
+            order.side = Side::Ask;
+            order.traderAddress = bob_address.clone();
+            order.amount = amount.to_string();
+            order.price = price.to_string();
+            order
+        };
+        for (seed, price) in [(1, "8.0"), (2, "9.0")] {
+            let fill_result = order_book
+                .add_order(&mut manager, bob_ask(seed, "1.0", price))
+                .unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        }
+        // alice's bid takes both asks below her price and rests with the last 1 DDX.
+        let mut alice_bid = order_init(3);
+        alice_bid.amount = "3.0".to_string();
+        let fill_result = order_book.add_order(&mut manager, alice_bid).unwrap();
+        assert_eq!(fill_result.filled_orders.len(), 2);
+        manager.update_accounts(fill_result).unwrap();
+        // the rest of her bid fills as a maker in two halves.
+        for seed in [4, 5] {
+            let fill_result = order_book
+                .add_order(&mut manager, bob_ask(seed, "0.5", "10.0"))
+                .unwrap();
+            manager.update_accounts(fill_result).unwrap();
+        }
+        assert!(order_book.bid_book.best_price().is_none());
+        let alice = &manager.accounts[&alice_address];
+        assert!(alice.usd_hold().is_zero());
+        // 27 USD of notional, 0.034 USD of taker fee and 0.01 USD of maker fee.
+        let alice = manager.get_json_account(&alice_address).unwrap();
+        assert_eq!(alice.ddxBalance, "3.00");
+        assert_eq!(alice.usdBalance, "12.96");
+    }
+
+    #[test]
+    fn refreshed_iceberg_joins_the_back() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "20.0", "0.0", &bob_address, "0.0", "200.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let mut iceberg = order_init(1);
+        iceberg.side = Side::Ask;
+        iceberg.amount = "10.0".to_string();
+        iceberg.displayAmount = Some("1.0".to_string());
+        let mut plain = order_init(2);
+        plain.side = Side::Ask;
+        let plain_id = order_book
+            .order_hash(&plain.encode_order().unwrap())
+            .unwrap();
+        let iceberg_id = order_book
+            .order_hash(&iceberg.encode_order().unwrap())
+            .unwrap();
+        order_book.add_order(&mut manager, iceberg).unwrap();
+        order_book.add_order(&mut manager, plain).unwrap();
+        let mut buy = |order_book: &mut OrderBook, seed: u64| {
+            let mut bid = order_init(seed);
+            bid.traderAddress = bob_address.clone();
+            let fill_result = order_book.add_order(&mut manager, bid).unwrap();
+            let maker_hash = fill_result.filled_orders[0].maker_hash.clone();
+            manager.update_accounts(fill_result).unwrap();
+            maker_hash
+        };
+        // the iceberg was first at the level, so its shown slice fills first...
+        assert_eq!(buy(&mut order_book, 3), iceberg_id);
+        // ...and its next slice waits behind the order resting after it.
+        assert_eq!(buy(&mut order_book, 4), plain_id);
+        assert_eq!(buy(&mut order_book, 5), iceberg_id);
+    }
+
+    #[test]
+    fn resting_prices_are_on_the_tick_grid() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "100.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        let tick_size = U256::exp10(16);
+        order_book.config_mut().tick_size = tick_size;
+        // neither price has an exact binary form.
+        for (seed, price, ticks) in [(1, "12.34", 1234), (2, "0.3", 30)] {
+            let mut alice_order = order_init(seed);
+            alice_order.price = price.to_string();
+            let order_id = order_book
+                .order_hash(&alice_order.encode_order().unwrap())
+                .unwrap();
+            order_book.add_order(&mut manager, alice_order).unwrap();
+            let resting = order_book.get_resting(&order_id).unwrap();
+            assert!((resting.price % tick_size).is_zero());
+            assert_eq!(resting.price, tick_size * ticks);
+        }
+    }
+
+    #[test]
+    fn reduced_bid_keeps_the_hold_of_the_rest() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "10.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        // 0.1 DDX at 10/3 USD is worth a fraction of a unit more than its rounded down notional.
+        let mut alice_order = order_init(1);
+        alice_order.amount = "0.3".to_string();
+        alice_order.price = "3.333333333333333333".to_string();
+        let encoded = alice_order.encode_order().unwrap();
+        let order_id = order_book.order_hash(&encoded).unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        order_book
+            .reduce_order(&mut manager, order_id.clone(), &"0.2".to_string())
+            .unwrap();
+        let reduced = Order {
+            amount: U256::exp10(17) * 2,
+            ..encoded
+        };
+        let (_, hold) = required_hold(&reduced, &manager.fee_schedule()).unwrap();
+        assert_eq!(manager.accounts[&alice_address].usd_hold(), hold);
+        order_book
+            .cancel_order(&mut manager, order_id.clone())
+            .unwrap();
+        assert!(manager.accounts[&alice_address].usd_hold().is_zero());
+        // a market requiring signatures does not take an unsigned reduce.
+        let alice_order = order_init(2);
+        let order_id = order_book
+            .order_hash(&alice_order.encode_order().unwrap())
+            .unwrap();
+        order_book.add_order(&mut manager, alice_order).unwrap();
+        order_book.config_mut().require_signatures = true;
+        assert_eq!(
+            order_book
+                .reduce_order(&mut manager, order_id.clone(), &"0.5".to_string())
+                .unwrap_err(),
+            OrderBookError::InvalidSignature("missing".to_string())
+        );
+        assert_eq!(order_book.get_order(order_id).unwrap().amount, "1.00");
+    }
+
+    #[test]
+    fn orders_signed_for_one_market_are_rejected_by_another() {
+        let wallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse::<LocalWallet>()
+            .unwrap();
+        let (_, bob_address) = address_init();
+        let trader = wallet.address();
+        let mut ddx_book = OrderBook::new("DDX".to_string());
+        ddx_book.config_mut().domain = market_domain("DDX");
+        let mut eth_book = OrderBook::new("ETH".to_string());
+        eth_book.config_mut().domain = market_domain("ETH");
+        assert_ne!(
+            market_domain("DDX").separator(),
+            default_domain().separator()
+        );
+        assert_ne!(
+            market_domain("DDX").separator(),
+            market_domain("ETH").separator()
+        );
+        let mut order = order_init(1);
+        order.traderAddress = trader;
+        let digest = eip712_digest(&order.encode_order().unwrap(), &market_domain("DDX")).unwrap();
+        order.signature = Some(wallet.sign_hash(H256::from(digest)).to_string());
+        let mut eth_manager = account_init(&trader, "0.0", "20.0", &bob_address, "0.0", "0.0");
+        assert!(matches!(
+            eth_book.add_order(&mut eth_manager, order.clone()),
+            Err(OrderBookError::InvalidSignature(_))
+        ));
+        assert_eq!(eth_book.open_order_count(&trader), 0);
+        let mut ddx_manager = account_init(&trader, "0.0", "20.0", &bob_address, "0.0", "0.0");
+        ddx_book.add_order(&mut ddx_manager, order).unwrap();
+        assert_eq!(ddx_book.open_order_count(&trader), 1);
+    }
+
+    #[test]
+    fn replay_every_logged_mutator() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(
+            &alice_address,
+            "20.0",
+            "200.0",
+            &bob_address,
+            "20.0",
+            "200.0",
+        );
+        let start = manager.snapshot();
+        let config = MarketConfig {
+            record_actions: true,
+            ..MarketConfig::default()
+        };
+        let mut order_book = OrderBook::with_config("DDX".to_string(), config.clone());
+        let now = now_millis() / 1000;
+        let mut expiring = new_order(Side::Bid, &alice_address, "0.2", "8.5", 9);
+        expiring.expiresAt = Some(now + 60);
+        let bid = new_order(Side::Bid, &alice_address, "1.0", "9.0", 2);
+        let bid_hash = order_book.order_hash(&bid.encode_order().unwrap()).unwrap();
+        let mut apply = |order_book: &mut OrderBook, action: Action| {
+            let outcome = order_book.apply(&mut manager, action).unwrap();
+            match outcome {
+                ActionOutcome::Order(fill_result) => {
+                    manager.update_accounts(fill_result).unwrap();
+                    None
+                }
+                ActionOutcome::Reserved(token) => Some(token),
+                _ => None,
+            }
+        };
+        for action in [
+            Action::Add(new_order(Side::Ask, &bob_address, "2.0", "11.0", 1)),
+            Action::Add(bid),
+            Action::AddPegged {
+                order: new_order(Side::Bid, &alice_address, "0.5", "9.0", 3),
+                peg: Peg {
+                    reference: Side::Bid,
+                    offset_ticks: 0,
+                },
+            },
+            Action::Reduce {
+                order_hash: bid_hash,
+                amount: "0.5".to_string(),
+            },
+            Action::AddFillOrKill(new_order(Side::Bid, &alice_address, "0.3", "11.0", 4)),
+            Action::AddAllOrNone(new_order(Side::Bid, &alice_address, "0.2", "11.0", 5)),
+            Action::AddMarket(new_order(Side::Bid, &alice_address, "0.1", "0", 6)),
+            Action::Add(expiring),
+        ] {
+            apply(&mut order_book, action);
+        }
+        let token = apply(
+            &mut order_book,
+            Action::Reserve(new_order(Side::Bid, &alice_address, "0.4", "11.0", 7)),
+        )
+        .unwrap();
+        apply(&mut order_book, Action::Commit(token));
+        let token = apply(
+            &mut order_book,
+            Action::Reserve(new_order(Side::Bid, &alice_address, "0.2", "8.0", 8)),
+        )
+        .unwrap();
+        apply(&mut order_book, Action::Abort(token));
+        for action in [
+            Action::PruneExpired { now: now + 60 },
+            Action::CancelOldest {
+                trader: alice_address,
+                count: 1,
+            },
+            Action::CancelAll(bob_address),
+        ] {
+            apply(&mut order_book, action);
+        }
+        // a commit of a token already used is rejected and not logged.
+        assert_eq!(
+            order_book
+                .apply(&mut manager, Action::Commit(token))
+                .unwrap_err(),
+            OrderBookError::NoSuchOrder
+        );
+        let log = order_book.take_actions();
+        assert_eq!(log.len(), 15);
+        assert_eq!(order_book.open_order_count(&alice_address), 1);
+        assert_eq!(order_book.open_order_count(&bob_address), 0);
+
+        let mut replayed_manager = AccountManager::new();
+        replayed_manager.restore(&start).unwrap();
+        let mut replayed = OrderBook::with_config("DDX".to_string(), config);
+        let log: Vec<Action> = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        replayed.replay(&mut replayed_manager, &log).unwrap();
+        assert_eq!(
+            serde_json::to_value(replayed.get_orders_by_trader(&alice_address)).unwrap(),
+            serde_json::to_value(order_book.get_orders_by_trader(&alice_address)).unwrap()
+        );
+        for trader in [alice_address, bob_address] {
+            assert_eq!(
+                serde_json::to_value(replayed_manager.get_json_account(&trader).unwrap()).unwrap(),
+                serde_json::to_value(manager.get_json_account(&trader).unwrap()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn unrepresentable_hold_is_a_violation() {
+        let (alice_address, bob_address) = address_init();
+        let mut manager = account_init(&alice_address, "0.0", "50.0", &bob_address, "0.0", "0.0");
+        let mut order_book = OrderBook::new("DDX".to_string());
+        order_book.add_order(&mut manager, order_init(1)).unwrap();
+        order_book
+            .reserve(
+                &mut manager,
+                new_order(Side::Bid, &alice_address, "1.0", "9.0", 2),
+            )
+            .unwrap();
+        assert!(order_book.check_integrity(&manager).is_empty());
+        // a resting bid whose notional overflows, counted before the reserved one.
+        for level in order_book.bid_book.price_levels.iter_mut() {
+            for order in level.values_mut() {
+                order.amount = U256::MAX / 2;
+            }
+        }
+        let violations = order_book.check_integrity(&manager);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            &violations[0],
+            IntegrityViolation::HoldMismatch { trader, .. } if *trader == alice_address
+        ));
+    }
 }
@@ -1,26 +1,29 @@
 //! Methods and structures for limit orders.
-use crate::json::{JsonOrder, Side};
-use crate::primitive::{Address, decimal_to_u256, Hash, u256_to_decimal};
+use crate::error::OrderBookError;
+use crate::json::{JsonCancel, JsonCancelAll, JsonOrder, Side};
+use crate::primitive::{Address, decimal_to_u256, Hash, Scale, u256_to_decimal};
 use ethers::abi::AbiEncode;
-use ethers::types::{transaction::eip712::Eip712, U256};
+use ethers::types::transaction::eip712::{Eip712, EIP712Domain};
+use ethers::types::{H256, Signature, SignatureError, U256};
+use ethers::utils::keccak256;
 use ethers_contract::EthAbiType;
 use ethers_derive_eip712::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 impl JsonOrder {
-    pub fn encode_order(&self) -> Order {
-        // TODO: here may lose some precision
-        let amount = decimal_to_u256(&self.amount);
-        let price = decimal_to_u256(&self.price);
-        let no_prefix = self.nonce.strip_prefix("0x").unwrap();
-        let nonce = U256::from(hex::decode(no_prefix).unwrap().as_slice());
+    pub fn encode_order(&self) -> Result<Order, OrderBookError> {
+        let amount = decimal_to_u256(&self.amount)?;
+        let price = decimal_to_u256(&self.price)?;
+        let nonce = parse_nonce(&self.nonce)?;
         let side: u8 = self.side.clone() as u8;
-        Order {
+        Ok(Order {
             amount,
             nonce,
             price,
             side,
             traderAddress: self.traderAddress.clone(),
-        }
+        })
     }
 
     pub fn get_trader(&self) -> String {
@@ -28,8 +31,73 @@ impl JsonOrder {
     }
 }
 
+impl JsonCancel {
+    pub fn encode_cancel(&self) -> Result<CancelOrder, OrderBookError> {
+        let order_hash = H256::from_str(&self.orderHash).map_err(|_| {
+            OrderBookError::Eip712Encoding(format!("invalid order hash {:?}", self.orderHash))
+        })?;
+        Ok(CancelOrder {
+            orderHash: order_hash,
+            nonce: parse_nonce(&self.nonce)?,
+        })
+    }
+}
+
+impl JsonCancelAll {
+    pub fn encode_cancel_all(&self) -> Result<CancelAll, OrderBookError> {
+        Ok(CancelAll {
+            traderAddress: self.traderAddress,
+            nonce: parse_nonce(&self.nonce)?,
+        })
+    }
+}
+
+/// EIP-712 domain of the `eip712` attributes of `Order` and `CancelOrder`, used unless a market
+/// configures its own.
+pub fn default_domain() -> EIP712Domain {
+    EIP712Domain {
+        name: Some("DDX take-home".to_string()),
+        version: Some("0.1.0".to_string()),
+        chain_id: None,
+        verifying_contract: None,
+        salt: None,
+    }
+}
+
+/// The default domain salted with the keccak256 hash of `symbol`, so that orders and cancels
+/// signed for one market are not valid in a market of another symbol.
+pub fn market_domain(symbol: &str) -> EIP712Domain {
+    EIP712Domain {
+        salt: Some(keccak256(symbol.as_bytes())),
+        ..default_domain()
+    }
+}
+
+/// EIP-712 digest of `payload` in `domain`, which is built at runtime rather than taken from
+/// the payload's `eip712` attribute. Equal to `encode_eip712` in the default domain.
+pub fn eip712_digest<T: Eip712>(
+    payload: &T,
+    domain: &EIP712Domain,
+) -> Result<[u8; 32], OrderBookError> {
+    let struct_hash = payload
+        .struct_hash()
+        .map_err(|e| OrderBookError::Eip712Encoding(e.to_string()))?;
+    Ok(keccak256(
+        [&[0x19, 0x01], &domain.separator()[..], &struct_hash[..]].concat(),
+    ))
+}
+
+fn parse_nonce(nonce: &str) -> Result<U256, OrderBookError> {
+    let bytes = nonce
+        .strip_prefix("0x")
+        .and_then(|no_prefix| hex::decode(no_prefix).ok())
+        .filter(|bytes| bytes.len() <= 32)
+        .ok_or_else(|| OrderBookError::Eip712Encoding(format!("invalid nonce {:?}", nonce)))?;
+    Ok(U256::from(bytes.as_slice()))
+}
+
 /// Order structure for computing and EIP712 hashing.
-#[derive(Debug, Copy, Clone, Eip712, EthAbiType)]
+#[derive(Debug, Copy, Clone, Eip712, EthAbiType, Serialize, Deserialize)]
 #[eip712(name = "DDX take-home", version = "0.1.0")]
 #[allow(non_snake_case)]
 pub struct Order {
@@ -44,19 +112,52 @@ impl Order {
     pub fn to_json(&self) -> JsonOrder {
         JsonOrder {
             amount: u256_to_decimal(&self.amount),
-            nonce: format!("0x{}", &self.nonce.encode_hex()),
+            nonce: format!("0x{}", hex::encode(self.nonce.encode())),
             price: u256_to_decimal(&self.price),
             side: self.get_side(),
             traderAddress: self.traderAddress.clone(),
+            postOnly: false,
+            reduceOnly: false,
+            signature: None,
+            expiresAt: None,
+            displayAmount: None,
+        }
+    }
+
+    /// The order with amount and price rescaled from 18 decimals to `decimals`, rounded to the
+    /// nearest unit, as it is represented on chain.
+    pub fn with_decimals(&self, decimals: u32) -> Order {
+        let scale = Scale::new(decimals);
+        Order {
+            amount: Scale::ENGINE.rescale(self.amount, scale),
+            price: Scale::ENGINE.rescale(self.price, scale),
+            ..*self
         }
     }
 
-    pub fn hash_hex(&self) -> Hash {
-        let hash_bytes = self.encode_eip712().unwrap();
+    /// Address whose key made `signature`, a hex encoded 65 byte signature of the order's
+    /// EIP-712 digest in `domain`.
+    pub fn recover_signer(
+        &self,
+        signature: &str,
+        domain: &EIP712Domain,
+    ) -> Result<Address, OrderBookError> {
+        recover_signer(self, signature, domain)
+    }
+
+    /// Hex encoded EIP-712 digest of the order in `domain`.
+    pub fn hash_hex_in(&self, domain: &EIP712Domain) -> Result<Hash, OrderBookError> {
+        Ok(format!("0x{}", hex::encode(eip712_digest(self, domain)?)))
+    }
+
+    pub fn hash_hex(&self) -> Result<Hash, OrderBookError> {
+        let hash_bytes = self
+            .encode_eip712()
+            .map_err(|e| OrderBookError::Eip712Encoding(e.to_string()))?;
         let mut prefix = "0x".to_string();
         let hash_str = hex::encode(&hash_bytes);
         prefix.push_str(&hash_str);
-        prefix
+        Ok(prefix)
     }
 
     pub fn get_side(&self) -> Side {
@@ -66,3 +167,58 @@ impl Order {
         }
     }
 }
+
+/// Cancel request of a resting order by its trader for computing and EIP712 hashing. The nonce
+/// only makes each signed cancel unique.
+#[derive(Debug, Copy, Clone, Eip712, EthAbiType)]
+#[eip712(name = "DDX take-home", version = "0.1.0")]
+#[allow(non_snake_case)]
+pub struct CancelOrder {
+    pub orderHash: H256,
+    pub nonce: U256,
+}
+
+impl CancelOrder {
+    /// Address whose key made `signature`, a hex encoded 65 byte signature of the cancel's
+    /// EIP-712 digest in `domain`.
+    pub fn recover_signer(
+        &self,
+        signature: &str,
+        domain: &EIP712Domain,
+    ) -> Result<Address, OrderBookError> {
+        recover_signer(self, signature, domain)
+    }
+}
+
+/// Cancel request of every resting order of a trader for computing and EIP712 hashing. Each
+/// nonce is accepted once by a book, so a replayed cancel cannot remove later orders.
+#[derive(Debug, Copy, Clone, Eip712, EthAbiType)]
+#[eip712(name = "DDX take-home", version = "0.1.0")]
+#[allow(non_snake_case)]
+pub struct CancelAll {
+    pub traderAddress: Address,
+    pub nonce: U256,
+}
+
+impl CancelAll {
+    /// Address whose key made `signature`, a hex encoded 65 byte signature of the cancel's
+    /// EIP-712 digest in `domain`.
+    pub fn recover_signer(
+        &self,
+        signature: &str,
+        domain: &EIP712Domain,
+    ) -> Result<Address, OrderBookError> {
+        recover_signer(self, signature, domain)
+    }
+}
+
+fn recover_signer<T: Eip712>(
+    payload: &T,
+    signature: &str,
+    domain: &EIP712Domain,
+) -> Result<Address, OrderBookError> {
+    let invalid = |e: SignatureError| OrderBookError::InvalidSignature(e.to_string());
+    let signature = Signature::from_str(signature).map_err(invalid)?;
+    let digest = eip712_digest(payload, domain)?;
+    signature.recover(H256::from(digest)).map_err(invalid)
+}
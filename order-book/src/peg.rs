@@ -0,0 +1,120 @@
+//! Pegged orders, whose price follows the touch of one side of the book.
+use crate::account::AccountManager;
+use crate::error::OrderBookError;
+use crate::json::{JsonOrder, Side};
+use crate::order::Order;
+use crate::primitive::{Hash, now_millis};
+use crate::OrderBook;
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+/// Price reference of a pegged order: the best price of `reference`, shifted by
+/// `offset_ticks` tick sizes. Pegged orders themselves are not part of the reference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peg {
+    /// Side of the book whose best price is tracked.
+    pub reference: Side,
+    /// Signed distance from the tracked price in ticks.
+    pub offset_ticks: i64,
+}
+
+impl OrderBook {
+    /// Rest a passive order priced by `peg` instead of its own price. The order keeps its hash
+    /// when it is re-priced, which happens whenever an order submitted or cancelled through
+    /// the book moves the reference.
+    pub fn add_pegged_order(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+        peg: Peg,
+    ) -> Result<Hash, OrderBookError> {
+        if self.read_only {
+            return Err(OrderBookError::ReadOnly);
+        }
+        let order = self.normalize_order(order)?;
+        let price = self.peg_price(&peg).ok_or(OrderBookError::InvalidOrder)?;
+        if self.crosses(&order.side, &price) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        let mut encoded_order = order.encode_order()?;
+        encoded_order.price = price;
+        if self.below_minimum(&encoded_order) {
+            return Err(OrderBookError::InvalidOrder);
+        }
+        let order_id = self.order_hash(&encoded_order)?;
+        if self.order_loc.contains_key(&order_id) || self.is_reserved(&order_id) {
+            return Err(OrderBookError::DuplicateOrder);
+        }
+        manager.accept_order(&order, &encoded_order)?;
+        self.create_new_limit_order(order.side, order_id.clone(), encoded_order);
+        self.assign_sequence(&order_id, now_millis());
+        if let Some(expires_at) = order.expiresAt {
+            self.expiries.insert(order_id.clone(), expires_at);
+        }
+        self.pegged.insert(order_id.clone(), peg);
+        Ok(order_id)
+    }
+
+    /// Price a pegged order should rest at, if the reference side has other orders.
+    fn peg_price(&self, peg: &Peg) -> Option<U256> {
+        let book = match peg.reference {
+            Side::Bid => &self.bid_book,
+            Side::Ask => &self.ask_book,
+        };
+        let reference = |(price, loc): (&U256, &usize)| {
+            book.price_levels[*loc]
+                .keys()
+                .any(|order_id| !self.pegged.contains_key(order_id))
+                .then_some(*price)
+        };
+        let touch = match peg.reference {
+            Side::Bid => book.price_map.iter().rev().find_map(reference)?,
+            Side::Ask => book.price_map.iter().find_map(reference)?,
+        };
+        let shift = self
+            .config
+            .tick_size
+            .checked_mul(U256::from(peg.offset_ticks.unsigned_abs()))?;
+        let price = if peg.offset_ticks >= 0 {
+            touch.checked_add(shift)?
+        } else {
+            touch.checked_sub(shift)?
+        };
+        (!price.is_zero()).then_some(price)
+    }
+
+    /// Move pegged orders to the price given by the current reference, at the back of the
+    /// new level's queue. An order is left in place if its new price would cross the book,
+    /// and cancelled if its trader cannot fund it at the new price.
+    pub(crate) fn reprice_pegged(&mut self, manager: &mut AccountManager) {
+        let pegged: Vec<(Hash, Peg)> = self
+            .pegged
+            .iter()
+            .map(|(order_id, peg)| (order_id.clone(), peg.clone()))
+            .collect();
+        for (order_id, peg) in pegged {
+            let current = match self.get_resting(&order_id) {
+                Some(order) => *order,
+                None => {
+                    // filled or cancelled.
+                    self.pegged.shift_remove(&order_id);
+                    continue;
+                }
+            };
+            let side = current.get_side();
+            let price = match self.peg_price(&peg) {
+                Some(price) if price != current.price && !self.crosses(&side, &price) => price,
+                _ => continue,
+            };
+            self.remove_order(&order_id);
+            manager.release_pending_fund(&current);
+            let repriced = Order { price, ..current };
+            if manager.hold_funds(&repriced).is_some() {
+                self.create_new_limit_order(side, order_id, repriced);
+            } else {
+                self.pegged.shift_remove(&order_id);
+                self.archive_order(order_id, current);
+            }
+        }
+    }
+}
@@ -1,27 +1,249 @@
 //! Primitive types and conversion methods.
+use crate::error::{ConversionError, OrderBookError};
 use ethers::types::{H160, U256};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// decimals of formatted amounts and prices.
+const DISPLAY_DECIMALS: u32 = 2;
 
 // local type alias
 pub type Address = H160;
 pub type Hash = String;
 pub type Decimal = String;
-// constants
-const MIN_PRICE: f64 = 1e-18;
+
+/// Decimal scale of an asset, i.e. the number of decimals of its smallest unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    decimals: u32,
+}
+
+impl Scale {
+    /// Scale of every amount and price inside the engine.
+    pub const ENGINE: Scale = Scale { decimals: 18 };
+
+    pub const fn new(decimals: u32) -> Self {
+        Scale { decimals }
+    }
+
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+
+    /// Number of smallest units in one whole unit.
+    pub fn unit(&self) -> U256 {
+        U256::exp10(self.decimals as usize)
+    }
+
+    /// Express a value of this scale in `to`, rounded to the nearest unit if precision is lost.
+    pub fn rescale(&self, value: U256, to: Scale) -> U256 {
+        if to.decimals >= self.decimals {
+            value.saturating_mul(U256::exp10((to.decimals - self.decimals) as usize))
+        } else {
+            let unit = U256::exp10((self.decimals - to.decimals) as usize);
+            value.saturating_add(unit / 2) / unit
+        }
+    }
+}
+
+/// Direction to round an amount that falls between two units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Toward zero, for amounts paid out: settling each fill rounded down takes no more than
+    /// the rounded up hold of the order leaves for the rest of it.
+    Down,
+    /// Away from zero, for amounts held or released as a hold.
+    Up,
+}
+
+/// USD value of `amount` DDX at `price`, both at the engine scale, rounded to a whole unit as
+/// given. Returns `None` if the product overflows.
+pub fn notional(amount: &U256, price: &U256, rounding: Rounding) -> Option<U256> {
+    let (notional, rem) = amount.checked_mul(*price)?.div_mod(Scale::ENGINE.unit());
+    match rounding {
+        Rounding::Up if !rem.is_zero() => Some(notional + 1),
+        _ => Some(notional),
+    }
+}
+
+/// Format a value of `scale` with two decimals, rounded to the nearest cent.
+pub fn to_decimal(value: &U256, scale: Scale) -> Decimal {
+    let display = Scale::new(DISPLAY_DECIMALS);
+    let (integer, fraction) = scale.rescale(*value, display).div_mod(display.unit());
+    format!(
+        "{}.{:0width$}",
+        integer,
+        fraction.as_u32(),
+        width = DISPLAY_DECIMALS as usize
+    )
+}
+
+/// Exact value of a decimal, optionally signed and with an exponent such as `1e-18`, in the
+/// smallest unit of `scale`. A value finer than that unit is rejected rather than truncated.
+pub fn from_decimal(from: &str, scale: Scale) -> Result<U256, ConversionError> {
+    let unparseable = || ConversionError::Unparseable(from.to_string());
+    let (negative, unsigned) = match from.as_bytes().first() {
+        Some(b'-') => (true, &from[1..]),
+        Some(b'+') => (false, &from[1..]),
+        _ => (false, from),
+    };
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (
+            mantissa,
+            exponent.parse::<i64>().map_err(|_| unparseable())?,
+        ),
+        None => (unsigned, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(unparseable());
+    }
+    // the value is `digits` times ten to the power of `shift`, in the smallest unit.
+    let shift = exponent
+        .saturating_sub(fraction.len() as i64)
+        .saturating_add(scale.decimals() as i64);
+    let significant = digits.trim_start_matches('0');
+    let trimmed = significant.trim_end_matches('0');
+    if trimmed.is_empty() {
+        return Ok(U256::zero());
+    }
+    if negative {
+        return Err(ConversionError::Negative(from.to_string()));
+    }
+    // trailing zeros move into the power of ten.
+    let shift = shift.saturating_add((significant.len() - trimmed.len()) as i64);
+    if shift < 0 {
+        return Err(ConversionError::TooPrecise(from.to_string()));
+    }
+    let overflow = || ConversionError::Overflow(from.to_string());
+    let value = U256::from_dec_str(trimmed).map_err(|_| overflow())?;
+    if shift > 77 {
+        return Err(overflow());
+    }
+    value
+        .checked_mul(U256::exp10(shift as usize))
+        .ok_or_else(overflow)
+}
 
 pub fn u256_to_decimal(from: &U256) -> Decimal {
-    let float = from.low_u128() as f64;
-    format!("{:.2}", float * MIN_PRICE)
+    to_decimal(from, Scale::ENGINE)
+}
+
+pub fn decimal_to_u256(from: &Decimal) -> Result<U256, ConversionError> {
+    from_decimal(from, Scale::ENGINE)
+}
+
+/// Format a value of `scale` with as many decimals as it has, unlike `to_decimal`.
+pub fn format_units(value: &U256, scale: Scale) -> Decimal {
+    let (integer, fraction) = value.div_mod(scale.unit());
+    if fraction.is_zero() {
+        return integer.to_string();
+    }
+    let fraction = format!(
+        "{:0>width$}",
+        fraction.to_string(),
+        width = scale.decimals() as usize
+    );
+    format!("{}.{}", integer, fraction.trim_end_matches('0'))
 }
 
-pub fn decimal_to_u256(from: &Decimal) -> U256 {
-    U256::from((from.parse::<f64>().unwrap() / MIN_PRICE) as u128)
+/// Least recently used cache of formatted values, for hot paths that format the same prices
+/// over and over.
+#[derive(Debug, Clone)]
+pub struct DecimalCache {
+    capacity: usize,
+    // formatted value and the last lookup it was used in.
+    pub(crate) entries: HashMap<U256, (Decimal, u64)>,
+    lookups: u64,
 }
 
-#[derive(Debug)]
+impl DecimalCache {
+    pub fn new(capacity: usize) -> Self {
+        DecimalCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            lookups: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Format a value like `u256_to_decimal`, evicting the least recently used value if the
+    /// cache is full.
+    pub fn format(&mut self, value: &U256) -> Decimal {
+        self.lookups += 1;
+        if let Some((decimal, last_used)) = self.entries.get_mut(value) {
+            *last_used = self.lookups;
+            return decimal.clone();
+        }
+        let decimal = u256_to_decimal(value);
+        if self.capacity == 0 {
+            return decimal;
+        }
+        if self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(value, _)| *value);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(*value, (decimal.clone(), self.lookups));
+        decimal
+    }
+}
+
+/// Bring a client decimal to the canonical form parsed by `decimal_to_u256`: surrounding
+/// whitespace is trimmed and `separator` becomes `.`. Grouping characters such as thousands
+/// separators are rejected rather than guessed.
+pub fn normalize_decimal(from: &str, separator: char) -> Result<Decimal, OrderBookError> {
+    let invalid = |reason: &str| OrderBookError::InvalidDecimal(format!("{:?}: {}", from, reason));
+    let trimmed = from.trim();
+    if trimmed.is_empty() {
+        return Err(invalid("empty"));
+    }
+    let mut parts = trimmed.split(separator);
+    let integer = parts.next().unwrap();
+    let fraction = parts.next();
+    if parts.next().is_some() {
+        return Err(invalid("more than one decimal separator"));
+    }
+    for part in [Some(integer), fraction].into_iter().flatten() {
+        if let Some(c) = part.chars().find(|c| !c.is_ascii_digit()) {
+            return Err(match c {
+                '.' | ',' | ' ' | '\'' | '_' => invalid("thousands separators are not accepted"),
+                _ => invalid("not a decimal number"),
+            });
+        }
+    }
+    if integer.is_empty() || fraction.map_or(false, str::is_empty) {
+        return Err(invalid("missing digits around the decimal separator"));
+    }
+    Ok(match fraction {
+        Some(fraction) => format!("{}.{}", integer, fraction),
+        None => integer.to_string(),
+    })
+}
+
+/// Milliseconds elapsed since the unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is before unix epoch!")
+        .as_millis() as u64
+}
+
+#[derive(Debug, Serialize)]
 pub enum OrderStatus {
     Created,
     Filled,
     PartiallyFilled,
+    /// Cancelled without any fill.
+    Cancelled,
 }
-
-
@@ -0,0 +1,84 @@
+//! Two-phase order submission: funds are reserved first and the order is matched on commit,
+//! so that a submission can be coordinated with other engines.
+use crate::account::{AccountManager, required_hold};
+use crate::fill::FillResult;
+use crate::json::JsonOrder;
+use crate::primitive::Hash;
+use crate::OrderBook;
+use serde::{Deserialize, Serialize};
+
+/// Handle of an order whose funds are held but which has not been matched yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReservationToken(u64);
+
+impl OrderBook {
+    /// Validate an order and hold its funds without touching the book. Nothing is reserved
+    /// in read-only mode or if the same order is already resting or reserved.
+    pub fn reserve(
+        &mut self,
+        manager: &mut AccountManager,
+        order: JsonOrder,
+    ) -> Option<ReservationToken> {
+        let order = self.normalize_order(order).ok()?;
+        let encoded_order = order.encode_order().ok()?;
+        if self.read_only || !self.check_order(&encoded_order) {
+            return None;
+        }
+        if (order.postOnly || self.protected_open())
+            && self.crosses(&order.side, &encoded_order.price)
+        {
+            return None;
+        }
+        let order_id = self.order_hash(&encoded_order).ok()?;
+        if self.order_loc.contains_key(&order_id) || self.is_reserved(&order_id) {
+            return None;
+        }
+        let encoded_order = manager.validate_order(order.clone()).ok()?;
+        self.reservation_count += 1;
+        let token = ReservationToken(self.reservation_count);
+        self.reservations
+            .insert(token, (order_id, order, encoded_order));
+        Some(token)
+    }
+
+    /// Whether an order with the given hash is waiting to be committed.
+    pub(crate) fn is_reserved(&self, order_id: &Hash) -> bool {
+        self.reservations
+            .values()
+            .any(|(reserved_id, _, _)| reserved_id == order_id)
+    }
+
+    /// Match a reserved order against the book. The book may have moved since the order was
+    /// reserved, so the order is aborted and its funds released instead if the book turned
+    /// read-only, or if it is post-only or the market is in its protected open and it now
+    /// crosses the book.
+    pub fn commit(
+        &mut self,
+        manager: &mut AccountManager,
+        token: ReservationToken,
+    ) -> Option<FillResult> {
+        let (_, order, encoded_order) = self.reservations.get(&token)?;
+        let crossing = (order.postOnly || self.protected_open())
+            && self.crosses(&order.side, &encoded_order.price);
+        if self.read_only || crossing {
+            self.abort(manager, token);
+            return None;
+        }
+        let (order_id, order, encoded_order) = self.reservations.remove(&token)?;
+        let reserved = required_hold(&encoded_order, &manager.fee_schedule());
+        let mut fill_result = self.match_order(order, encoded_order, order_id, true);
+        fill_result.reserved = reserved;
+        Some(fill_result)
+    }
+
+    /// Drop a reserved order and release its held funds.
+    pub fn abort(
+        &mut self,
+        manager: &mut AccountManager,
+        token: ReservationToken,
+    ) -> Option<JsonOrder> {
+        let (_, order, encoded_order) = self.reservations.remove(&token)?;
+        manager.release_pending_fund(&encoded_order);
+        Some(order)
+    }
+}
@@ -0,0 +1,119 @@
+//! Proof of reserves: a Merkle tree committing to the total balances of every account.
+//!
+//! Leaves are the keccak256 hash of the ABI encoded `(address, ddx, usd)` total balances,
+//! ordered by address, and each parent hashes its two children in sorted order so that a
+//! proof needs no left or right flags. An odd node is carried up to the next level as is.
+use crate::account::AccountManager;
+use crate::error::BalanceError;
+use crate::primitive::{Address, Hash};
+use ethers::abi::{encode, Token};
+use ethers::types::U256;
+use ethers::utils::keccak256;
+
+/// Inclusion proof of an account's total balances in the balances Merkle root.
+#[derive(Debug, Clone)]
+pub struct BalanceProof {
+    pub address: Address,
+    pub ddx_balance: U256,
+    pub usd_balance: U256,
+    /// Sibling hashes from the leaf up to the root.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl BalanceProof {
+    /// Check the proof against a published root.
+    pub fn verify(&self, root: &Hash) -> bool {
+        let node = self.siblings.iter().fold(
+            leaf_hash(&self.address, &self.ddx_balance, &self.usd_balance),
+            |node, sibling| parent_hash(&node, sibling),
+        );
+        to_hash(&node) == *root
+    }
+}
+
+fn leaf_hash(address: &Address, ddx_balance: &U256, usd_balance: &U256) -> [u8; 32] {
+    keccak256(encode(&[
+        Token::Address(*address),
+        Token::Uint(*ddx_balance),
+        Token::Uint(*usd_balance),
+    ]))
+}
+
+fn parent_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    keccak256([first.as_slice(), second.as_slice()].concat())
+}
+
+fn to_hash(node: &[u8; 32]) -> Hash {
+    format!("0x{}", hex::encode(node))
+}
+
+/// Hash pairs of nodes into the next level of the tree.
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [a, b] => parent_hash(a, b),
+            [a] => *a,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+impl AccountManager {
+    /// Leaves of the balances tree in address order.
+    fn balance_leaves(&self) -> Result<Vec<(Address, U256, U256)>, BalanceError> {
+        let mut leaves = self
+            .accounts
+            .iter()
+            .map(|(address, account)| Ok((*address, account.total_ddx()?, account.total_usd()?)))
+            .collect::<Result<Vec<_>, BalanceError>>()?;
+        leaves.sort_by_key(|(address, _, _)| *address);
+        Ok(leaves)
+    }
+
+    /// Merkle root committing to the total balances of all accounts, zero if there are none.
+    pub fn balances_merkle_root(&self) -> Result<Hash, BalanceError> {
+        let mut level: Vec<[u8; 32]> = self
+            .balance_leaves()?
+            .iter()
+            .map(|(address, ddx, usd)| leaf_hash(address, ddx, usd))
+            .collect();
+        if level.is_empty() {
+            return Ok(to_hash(&[0u8; 32]));
+        }
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        Ok(to_hash(&level[0]))
+    }
+
+    /// Inclusion proof of an account's balances in `balances_merkle_root`, `None` if there is
+    /// no such account.
+    pub fn balance_proof(&self, address: &Address) -> Result<Option<BalanceProof>, BalanceError> {
+        let leaves = self.balance_leaves()?;
+        let mut index = match leaves.iter().position(|(a, _, _)| a == address) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let (_, ddx_balance, usd_balance) = leaves[index];
+        let mut level: Vec<[u8; 32]> = leaves
+            .iter()
+            .map(|(address, ddx, usd)| leaf_hash(address, ddx, usd))
+            .collect();
+        let mut siblings = Vec::new();
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(index ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+        Ok(Some(BalanceProof {
+            address: *address,
+            ddx_balance,
+            usd_balance,
+            siblings,
+        }))
+    }
+}
@@ -0,0 +1,67 @@
+//! Synthetic liquidity around a reference price for demos and tests.
+use crate::account::AccountManager;
+use crate::json::{JsonOrder, Side};
+use crate::primitive::Address;
+use crate::OrderBook;
+
+#[derive(Debug, Clone)]
+pub struct SeedConfig {
+    /// Market maker account providing the liquidity.
+    pub trader: Address,
+    /// Number of price levels on each side of the book.
+    pub levels: usize,
+    /// Distance between the seeded best bid and best ask.
+    pub spread: f64,
+    /// Distance between two adjacent seeded price levels.
+    pub step: f64,
+    /// Order amount at the best level of each side.
+    pub size: f64,
+    /// Ratio applied to the amount of every next level away from the reference price.
+    pub size_decay: f64,
+}
+
+impl OrderBook {
+    /// Populate both sides of the book with levels placed symmetrically around `ref_price`.
+    /// Orders are submitted on behalf of the configured trader, so its account must hold
+    /// enough funds. Returns the number of accepted orders.
+    pub fn seed_around(
+        &mut self,
+        manager: &mut AccountManager,
+        ref_price: f64,
+        config: &SeedConfig,
+    ) -> usize {
+        let mut accepted = 0;
+        let mut size = config.size;
+        for level in 0..config.levels {
+            let offset = config.spread / 2.0 + config.step * level as f64;
+            for (side, price) in [
+                (Side::Bid, ref_price - offset),
+                (Side::Ask, ref_price + offset),
+            ] {
+                if price <= 0.0 {
+                    continue;
+                }
+                let order = JsonOrder {
+                    amount: size.to_string(),
+                    nonce: format!("0x{}", hex::encode(rand::random::<[u8; 32]>())),
+                    price: price.to_string(),
+                    side,
+                    traderAddress: config.trader,
+                    postOnly: false,
+                    reduceOnly: false,
+                    signature: None,
+                    expiresAt: None,
+                    displayAmount: None,
+                };
+                if let Ok(fill_result) = self.add_order(manager, order) {
+                    manager
+                        .update_accounts(fill_result)
+                        .expect("funds held for accepted orders cover their fills");
+                    accepted += 1;
+                }
+            }
+            size *= config.size_decay;
+        }
+        accepted
+    }
+}
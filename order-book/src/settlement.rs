@@ -0,0 +1,138 @@
+//! Batching of fills into periodic settlement bundles for on-chain submission.
+//!
+//! Fills are grouped into windows of a fixed interval aligned to the unix epoch, and the fills
+//! of a window are netted per pair of traders, so that a bundle carries at most one transfer of
+//! each asset between two traders.
+use crate::error::BalanceError;
+use crate::fill::Fill;
+use crate::json::Asset;
+use crate::primitive::{Address, notional, Rounding};
+use ethers::types::U256;
+use std::collections::BTreeMap;
+
+/// Net transfer of one asset between two traders over a settlement window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetTransfer {
+    pub asset: Asset,
+    pub from: Address,
+    pub to: Address,
+    pub amount: U256,
+}
+
+/// Net transfers of the fills executed from `start` (inclusive) to `end` (exclusive), in unix
+/// milliseconds.
+#[derive(Debug, Clone)]
+pub struct SettlementBundle {
+    pub start: u64,
+    pub end: u64,
+    pub fill_count: usize,
+    pub transfers: Vec<NetTransfer>,
+}
+
+// Gross amounts a pair of traders owe each other, from the lower address to the higher one and
+// back.
+#[derive(Default)]
+struct PairFlows {
+    ddx: (U256, U256),
+    usd: (U256, U256),
+}
+
+#[derive(Debug)]
+pub struct SettlementBatcher {
+    interval_millis: u64,
+    // start of the window of the pending fills.
+    window_start: u64,
+    fills: Vec<Fill>,
+}
+
+impl SettlementBatcher {
+    pub fn new(interval_millis: u64) -> Self {
+        assert!(interval_millis > 0, "Settlement interval must be positive!");
+        SettlementBatcher {
+            interval_millis,
+            window_start: 0,
+            fills: Vec::new(),
+        }
+    }
+
+    /// Add an executed fill, returning the bundle of the previous window if the fill is past
+    /// its end. The fill is not added if that bundle cannot be netted.
+    pub fn push(&mut self, fill: Fill) -> Result<Option<SettlementBundle>, BalanceError> {
+        let bundle = self.tick(fill.timestamp)?;
+        if self.fills.is_empty() {
+            self.window_start = fill.timestamp - fill.timestamp % self.interval_millis;
+        }
+        self.fills.push(fill);
+        Ok(bundle)
+    }
+
+    /// Bundle the pending fills if their window has ended by `now`.
+    pub fn tick(&mut self, now: u64) -> Result<Option<SettlementBundle>, BalanceError> {
+        if now < self.window_start.saturating_add(self.interval_millis) {
+            return Ok(None);
+        }
+        self.flush()
+    }
+
+    /// Bundle the pending fills regardless of their window, e.g. on shutdown. The fills stay
+    /// pending if a net amount overflows.
+    pub fn flush(&mut self) -> Result<Option<SettlementBundle>, BalanceError> {
+        if self.fills.is_empty() {
+            return Ok(None);
+        }
+        let transfers = net_transfers(&self.fills)?;
+        let fills = std::mem::take(&mut self.fills);
+        Ok(Some(SettlementBundle {
+            start: self.window_start,
+            end: self.window_start.saturating_add(self.interval_millis),
+            fill_count: fills.len(),
+            transfers,
+        }))
+    }
+}
+
+/// Net the DDX sold and the USD paid by each pair of traders, ordered by pair.
+fn net_transfers(fills: &[Fill]) -> Result<Vec<NetTransfer>, BalanceError> {
+    let add = |total: &mut U256, amount: U256, asset: Asset| {
+        *total = total
+            .checked_add(amount)
+            .ok_or(BalanceError::Overflow(asset))?;
+        Ok(())
+    };
+    let mut pairs: BTreeMap<(Address, Address), PairFlows> = BTreeMap::new();
+    for fill in fills {
+        // rounded as `Account::update` settles it.
+        let notional = notional(&fill.fill_amount, &fill.price, Rounding::Down)
+            .ok_or(BalanceError::Overflow(Asset::Usd))?;
+        // the seller delivers DDX to the buyer, who pays USD back.
+        let (seller, buyer) = (fill.from, fill.to);
+        if seller < buyer {
+            let flows = pairs.entry((seller, buyer)).or_default();
+            add(&mut flows.ddx.0, fill.fill_amount, Asset::Ddx)?;
+            add(&mut flows.usd.1, notional, Asset::Usd)?;
+        } else {
+            let flows = pairs.entry((buyer, seller)).or_default();
+            add(&mut flows.ddx.1, fill.fill_amount, Asset::Ddx)?;
+            add(&mut flows.usd.0, notional, Asset::Usd)?;
+        }
+    }
+    let mut transfers = Vec::new();
+    for ((low, high), flows) in pairs {
+        for (asset, (forward, backward)) in [(Asset::Ddx, flows.ddx), (Asset::Usd, flows.usd)] {
+            let transfer = if forward > backward {
+                (low, high, forward - backward)
+            } else {
+                (high, low, backward - forward)
+            };
+            if !transfer.2.is_zero() {
+                transfers.push(NetTransfer {
+                    asset,
+                    from: transfer.0,
+                    to: transfer.1,
+                    amount: transfer.2,
+                });
+            }
+        }
+    }
+    Ok(transfers)
+}
@@ -0,0 +1,53 @@
+//! Snapshots of the order book and the accounts, restored after a crash.
+use crate::account::AccountManager;
+use crate::error::OrderBookError;
+use crate::OrderBook;
+
+fn invalid(e: serde_json::Error) -> OrderBookError {
+    OrderBookError::InvalidSnapshot(e.to_string())
+}
+
+impl OrderBook {
+    /// Serialized state of the book: resting orders in queue order with their index, pegs,
+    /// expiries, icebergs, pending reservations, sequence numbers, trades and the archive.
+    pub fn snapshot(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Replace the state of the book with a snapshot of a book of the same symbol. The
+    /// configuration, epoch and read-only mode of this instance are kept.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), OrderBookError> {
+        let restored: OrderBook = serde_json::from_slice(snapshot).map_err(invalid)?;
+        if restored._symbol != self._symbol {
+            return Err(OrderBookError::InvalidSnapshot(format!(
+                "book of {}",
+                restored._symbol
+            )));
+        }
+        *self = OrderBook {
+            config: self.config.clone(),
+            read_only: self.read_only,
+            epoch: self.epoch,
+            opened_at: self.opened_at,
+            fill_sender: self.fill_sender.take(),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+impl AccountManager {
+    /// Serialized balances, holds and used nonces of every account, and the fee pool.
+    pub fn snapshot(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Replace the accounts with a snapshot, keeping the settings of this manager.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), OrderBookError> {
+        let restored: AccountManager = serde_json::from_slice(snapshot).map_err(invalid)?;
+        self.accounts = restored.accounts;
+        self.used_nonces = restored.used_nonces;
+        self.fee_pool = restored.fee_pool;
+        Ok(())
+    }
+}
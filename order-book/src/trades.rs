@@ -0,0 +1,81 @@
+//! History of the fills executed by the book, bounded by the market's trade retention.
+use crate::config::TradeRetention;
+use crate::fill::Fill;
+use crate::json::JsonFill;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Retained fills in execution order, which is also time order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TradeHistory {
+    fills: VecDeque<Fill>,
+}
+
+impl TradeHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a fill and drop the oldest ones beyond `retention`.
+    pub fn push(&mut self, fill: Fill, retention: TradeRetention) {
+        self.fills.push_back(fill);
+        let expired = match retention {
+            TradeRetention::Unbounded => 0,
+            TradeRetention::MaxEntries(max) => self.fills.len().saturating_sub(max),
+            TradeRetention::MaxAgeMillis(age) => match self.fills.back() {
+                Some(latest) => {
+                    let cutoff = latest.timestamp.saturating_sub(age);
+                    self.fills.partition_point(|fill| fill.timestamp < cutoff)
+                }
+                None => 0,
+            },
+        };
+        self.fills.drain(..expired);
+    }
+
+    /// The latest `limit` fills executed from `start` (inclusive) to `end` (exclusive), in
+    /// unix milliseconds, oldest first.
+    pub fn query(&self, start: u64, end: u64, limit: usize) -> Vec<JsonFill> {
+        let fills = self.between(start, end);
+        let skip = fills.len().saturating_sub(limit);
+        fills.skip(skip).map(Fill::to_json).collect()
+    }
+
+    /// Number of retained fills.
+    pub fn len(&self) -> usize {
+        self.fills.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fills.is_empty()
+    }
+
+    /// The latest fill, if any.
+    pub fn back(&self) -> Option<&Fill> {
+        self.fills.back()
+    }
+
+    /// Retained fills, oldest first.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &Fill> {
+        self.fills.iter()
+    }
+
+    /// Fills executed from `start` (inclusive) to `end` (exclusive), in unix milliseconds.
+    pub fn between(&self, start: u64, end: u64) -> impl ExactSizeIterator<Item = &Fill> {
+        let first = self.fills.partition_point(|fill| fill.timestamp < start);
+        let last = self
+            .fills
+            .partition_point(|fill| fill.timestamp < end)
+            .max(first);
+        self.fills.range(first..last)
+    }
+}
+
+impl FromIterator<Fill> for TradeHistory {
+    fn from_iter<I: IntoIterator<Item = Fill>>(fills: I) -> Self {
+        TradeHistory {
+            fills: fills.into_iter().collect(),
+        }
+    }
+}
@@ -1,24 +1,218 @@
 use actix_web::body::BoxBody;
-use actix_web::http::header::ContentType;
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorUnauthorized, ErrorUnprocessableEntity,
+};
+use actix_web::http::header::{ContentType, AUTHORIZATION};
 use actix_web::http::StatusCode;
-use actix_web::{delete, get, post, web, App, HttpResponse, HttpServer, Responder, ResponseError};
+use actix_web::{
+    delete, get, post, rt, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder,
+    ResponseError,
+};
+use actix_ws::Message;
+use tokio::sync::broadcast::{self, error::RecvError};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
-use std::sync::Mutex;
+use std::sync::Mutex as SyncMutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
 // local module.
 use order_book::account::AccountManager;
-use order_book::json::{JsonAccount, JsonOrder};
-use order_book::primitive::{Address, Hash};
+use order_book::action::{Action, ActionOutcome};
+use order_book::candles::parse_interval;
+use order_book::config::AssetSymbols;
+use order_book::error::{BalanceError, OrderBookError};
+use order_book::integrity::IntegrityViolation;
+use order_book::json::{
+    Asset, Denomination, JsonAccount, JsonCancel, JsonCancelAll, JsonFill, JsonOrder, JsonTransfer,
+    L2OrderBook, Side, SimpleOrder,
+};
+use order_book::order::market_domain;
+use order_book::primitive::{
+    decimal_to_u256, normalize_decimal, u256_to_decimal, Address, Decimal, Hash,
+};
 use order_book::OrderBook;
 
 struct AppState {
+    // Order books by symbol, each settling against its own accounts.
+    markets: BTreeMap<String, Market>,
+    // Symbol of the market used by requests that do not name one.
+    default_symbol: String,
+    // Number of accounts across the markets.
+    user_count: Mutex<u64>,
+    // Max time a handler waits for a lock before giving up.
+    lock_timeout: Duration,
+    // Bearer token of the `/admin` routes, which are disabled without one.
+    admin_token: Option<String>,
+}
+
+struct Market {
     // This shall be your database in the production env.
-    // In this simple exercise, all data is stored in memory.
+    // In this simple exercise, all data is stored in memory. Accounts hold the market's symbol
+    // as their base asset, so a balance in one market cannot back orders of another.
     manager: Mutex<AccountManager>,
     order_book: Mutex<OrderBook>,
-    user_count: Mutex<u64>,
+    // L2 updates pushed to `/ws/book` subscribers.
+    book_feed: broadcast::Sender<String>,
+    // L2 book as of the latest update pushed to the feed, only locked with the book held.
+    feed_book: SyncMutex<L2OrderBook>,
+}
+
+const LOCK_TIMEOUT: Duration = Duration::from_millis(500);
+// Header carrying the book epoch on snapshots and trades, which changes when the engine restarts.
+const EPOCH_HEADER: &str = "X-Book-Epoch";
+// Orders a side of the L3 book returns unless the request sets its depth.
+const L3_DEFAULT_DEPTH: usize = 50;
+// Max fills `/trades` returns, also its default limit.
+const MAX_TRADES: usize = 1000;
+// Latest fills in a bootstrap payload.
+const BOOTSTRAP_TRADES: usize = 100;
+// Updates a slow `/ws/book` subscriber can fall behind by before it is disconnected.
+const BOOK_FEED_CAPACITY: usize = 1024;
+// Symbols traded unless the `SYMBOLS` environment variable lists others, comma separated.
+const DEFAULT_SYMBOLS: &str = "DDX";
+// Environment variable holding the bearer token of the `/admin` routes.
+const ADMIN_TOKEN_VAR: &str = "ADMIN_TOKEN";
+
+/// Accounts and books of every market, as taken by `/admin/snapshot`. Loaded at startup from
+/// the file named by the `SNAPSHOT` environment variable, if any.
+#[derive(Debug, Serialize, Deserialize)]
+struct ServiceSnapshot {
+    markets: BTreeMap<String, MarketSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MarketSnapshot {
+    accounts: serde_json::Value,
+    book: serde_json::Value,
+}
+
+impl AppState {
+    /// Markets for each of `symbols`, the first of which is the default.
+    fn with_symbols(lock_timeout: Duration, symbols: &[&str]) -> Self {
+        AppState {
+            markets: symbols
+                .iter()
+                .map(|symbol| (symbol.to_string(), Market::new(symbol)))
+                .collect(),
+            default_symbol: symbols[0].to_string(),
+            user_count: Mutex::new(0),
+            lock_timeout,
+            admin_token: None,
+        }
+    }
+
+    /// Check that `req` carries the admin bearer token.
+    fn authorize_admin(&self, req: &HttpRequest) -> Result<(), Error> {
+        let token = self
+            .admin_token
+            .as_deref()
+            .ok_or_else(|| ErrorForbidden("Admin routes are disabled"))?;
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match bearer {
+            Some(bearer) if bearer == token => Ok(()),
+            _ => Err(ErrorUnauthorized("Missing or invalid admin token")),
+        }
+    }
+
+    /// Market of `symbol`, or of the default symbol if `None`.
+    fn market(&self, symbol: Option<&str>) -> Result<&Market, Error> {
+        let symbol = symbol.unwrap_or(&self.default_symbol);
+        self.markets
+            .get(symbol)
+            .ok_or_else(|| ErrorNotFound(format!("Unknown symbol {}", symbol)))
+    }
+
+    fn default_market(&self) -> &Market {
+        &self.markets[&self.default_symbol]
+    }
+
+    /// Snapshot of the accounts and the book of every market, taken with all of them locked.
+    async fn snapshot(&self) -> Result<ServiceSnapshot, Error> {
+        let mut markets = BTreeMap::new();
+        let mut guards = Vec::new();
+        for (symbol, market) in self.markets.iter() {
+            let manager = acquire(&market.manager, self.lock_timeout).await?;
+            let order_book = acquire(&market.order_book, self.lock_timeout).await?;
+            let snapshot = MarketSnapshot {
+                accounts: serde_json::from_slice(&manager.snapshot())?,
+                book: serde_json::from_slice(&order_book.snapshot())?,
+            };
+            markets.insert(symbol.clone(), snapshot);
+            // keep every market locked until all are taken.
+            guards.push((manager, order_book));
+        }
+        Ok(ServiceSnapshot { markets })
+    }
+
+    /// Replace the accounts and the books with a snapshot, all of whose markets are traded.
+    /// Nothing changes if any part of it cannot be restored.
+    async fn restore(&self, snapshot: &ServiceSnapshot) -> Result<(), OrderBookError> {
+        let to_bytes = |value: &serde_json::Value| serde_json::to_vec(value).unwrap();
+        let mut restored = Vec::new();
+        for (symbol, market_snapshot) in snapshot.markets.iter() {
+            let market = self.markets.get(symbol).ok_or_else(|| {
+                OrderBookError::InvalidSnapshot(format!("unknown symbol {}", symbol))
+            })?;
+            let manager = market.manager.lock().await;
+            let mut restored_manager = manager.clone();
+            restored_manager.restore(&to_bytes(&market_snapshot.accounts))?;
+            let order_book = market.order_book.lock().await;
+            let mut restored_book = order_book.clone();
+            restored_book.restore(&to_bytes(&market_snapshot.book))?;
+            restored.push((manager, restored_manager, order_book, restored_book));
+        }
+        let mut count = 0;
+        for (mut manager, restored_manager, mut order_book, restored_book) in restored {
+            count += restored_manager.account_count() as u64;
+            *manager = restored_manager;
+            *order_book = restored_book;
+        }
+        *self.user_count.lock().await = count;
+        Ok(())
+    }
+}
+
+impl Market {
+    fn new(symbol: &str) -> Self {
+        let mut manager = AccountManager::new();
+        manager.set_asset_symbols(AssetSymbols {
+            base: symbol.to_string(),
+            quote: AssetSymbols::default().quote,
+        });
+        let mut order_book = OrderBook::new(symbol.to_string());
+        order_book.config_mut().domain = market_domain(symbol);
+        Market {
+            manager: Mutex::new(manager),
+            order_book: Mutex::new(order_book),
+            book_feed: broadcast::channel(BOOK_FEED_CAPACITY).0,
+            feed_book: SyncMutex::new(L2OrderBook::new()),
+        }
+    }
+
+    /// Push the L2 levels changed since the last update to the book feed, if anyone listens.
+    /// The caller holds the book lock, so updates go out in the order of the changes.
+    fn publish_book(&self, order_book: &OrderBook) {
+        if self.book_feed.receiver_count() == 0 {
+            return;
+        }
+        let l2_book = order_book.generate_l2_order_book();
+        let mut feed_book = self.feed_book.lock().unwrap();
+        let changes = l2_book.changes_since(&feed_book);
+        *feed_book = l2_book;
+        if !changes.is_empty() {
+            let update = serde_json::json!({ "type": "update", "book": changes });
+            // subscribers may have left since the count was taken.
+            let _ = self.book_feed.send(update.to_string());
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -73,24 +267,109 @@ impl Display for ErrNoOrder {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct ErrBusy {
+    err: String,
+}
+
+// Implement ResponseError for ErrBusy
+impl ResponseError for ErrBusy {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        let body = serde_json::to_string(&self).unwrap();
+        let res = HttpResponse::new(self.status_code());
+        res.set_body(BoxBody::new(body))
+    }
+}
+
+// Implement Display for ErrBusy
+impl Display for ErrBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrReadOnly {
+    err: String,
+}
+
+// Implement ResponseError for ErrReadOnly
+impl ResponseError for ErrReadOnly {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        let body = serde_json::to_string(&self).unwrap();
+        let res = HttpResponse::new(self.status_code());
+        res.set_body(BoxBody::new(body))
+    }
+}
+
+// Implement Display for ErrReadOnly
+impl Display for ErrReadOnly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl ErrReadOnly {
+    fn new() -> Self {
+        ErrReadOnly {
+            err: OrderBookError::ReadOnly.to_string(),
+        }
+    }
+}
+
+/// Acquire a lock with a bounded wait, so that a contended request is answered with 503
+/// instead of stalling a worker forever. The wait yields the worker to its other requests.
+async fn acquire<T>(mutex: &Mutex<T>, timeout: Duration) -> Result<MutexGuard<'_, T>, ErrBusy> {
+    rt::time::timeout(timeout, mutex.lock())
+        .await
+        .map_err(|_| ErrBusy {
+            err: String::from("Service is busy, please retry later"),
+        })
+}
+
 /// Create a new account. The username is generated internally.
 #[post("/accounts")]
-async fn new_account(req: web::Json<JsonAccount>, data: web::Data<AppState>) -> HttpResponse {
-    let mut manager = data.manager.lock().unwrap();
-    let mut count = data.user_count.lock().unwrap();
+async fn new_account(
+    req: web::Json<JsonAccount>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    let mut count = acquire(&data.user_count, data.lock_timeout).await?;
     let account = JsonAccount {
         ddxBalance: req.ddxBalance.clone(),
         usdBalance: req.usdBalance.clone(),
         traderAddress: req.traderAddress.clone(),
+        balances: Default::default(),
+        funds: Default::default(),
     };
 
-    manager.add_json_account(format!("User {}", count).as_str(), account);
+    manager
+        .add_json_account(format!("User {}", count).as_str(), account)
+        .map_err(|e| ErrorBadRequest(OrderBookError::from(e).to_string()))?;
     *count += 1;
 
-    HttpResponse::Created()
+    Ok(HttpResponse::Created()
         .content_type(ContentType::plaintext())
         .insert_header(("X-Hdr", "sample"))
-        .body("New account created!")
+        .body("New account created!"))
+}
+
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+struct AccountResponse {
+    #[serde(flatten)]
+    account: JsonAccount,
+    openOrders: usize,
 }
 
 /// Get an account info with the corresponding trader address.
@@ -98,21 +377,79 @@ async fn new_account(req: web::Json<JsonAccount>, data: web::Data<AppState>) ->
 #[allow(non_snake_case)]
 async fn get_account(
     traderAddress: web::Path<String>,
+    query: web::Query<SymbolQuery>,
     data: web::Data<AppState>,
-) -> Result<impl Responder, ErrNoAccount> {
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
     let trader: Address = traderAddress
         .parse::<Address>()
         .expect("Failed to parse trader's address!");
-    let manager = data.manager.lock().unwrap();
-
-    if let Some(account) = manager.get_json_account(&trader) {
-        Ok(web::Json(account))
-    } else {
-        let response = ErrNoAccount {
-            address: traderAddress.clone(),
-            err: String::from("Account not found"),
-        };
-        Err(response)
+    let manager = acquire(&market.manager, data.lock_timeout).await?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+
+    match manager.get_json_account(&trader) {
+        Ok(account) => Ok(web::Json(AccountResponse {
+            account,
+            openOrders: order_book.open_order_count(&trader),
+        })),
+        Err(OrderBookError::NoSuchAccount) => {
+            let response = ErrNoAccount {
+                address: traderAddress.clone(),
+                err: String::from("Account not found"),
+            };
+            Err(response.into())
+        }
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
+    }
+}
+
+/// Get the resting orders of a trader.
+#[get("/accounts/{traderAddress}/orders")]
+async fn get_account_orders(
+    address: web::Path<Address>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(order_book.get_orders_by_trader(&address)))
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelAllQuery {
+    nonce: Hash,
+    signature: String,
+    symbol: Option<String>,
+}
+
+/// Cancel every resting order of a trader, who must have signed the cancel.
+#[delete("/accounts/{traderAddress}/orders")]
+async fn cancel_account_orders(
+    address: web::Path<Address>,
+    query: web::Query<CancelAllQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let cancel = JsonCancelAll {
+        traderAddress: *address,
+        nonce: query.nonce.clone(),
+        signature: query.signature.clone(),
+    };
+    let mut order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    match order_book.apply(&mut manager, Action::CancelAllSigned(cancel)) {
+        Ok(ActionOutcome::CancelledOrders(orders)) => {
+            market.publish_book(&order_book);
+            Ok(web::Json(orders))
+        }
+        Ok(_) => unreachable!("a bulk cancel cancels a list of orders"),
+        Err(OrderBookError::ReadOnly) => Err(ErrReadOnly::new().into()),
+        Err(
+            e @ (OrderBookError::InvalidSignature(_)
+            | OrderBookError::Eip712Encoding(_)
+            | OrderBookError::NonceUsed),
+        ) => Err(ErrorBadRequest(e.to_string())),
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
     }
 }
 
@@ -121,53 +458,251 @@ async fn get_account(
 #[allow(non_snake_case)]
 async fn delete_account(
     traderAddress: web::Path<String>,
+    query: web::Query<SymbolQuery>,
     data: web::Data<AppState>,
-) -> Result<impl Responder, ErrNoAccount> {
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
     let trader: Address = traderAddress
         .parse::<Address>()
         .expect("Failed to parse trader's address!");
-    let mut manager = data.manager.lock().unwrap();
-    let mut count = data.user_count.lock().unwrap();
-
-    if let Some(account) = manager.delete_account(&trader) {
-        *count -= 1;
-        Ok(web::Json(account))
-    } else {
-        let response = ErrNoAccount {
-            address: traderAddress.clone(),
-            err: String::from("Account not found"),
-        };
-        Err(response)
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    let mut count = acquire(&data.user_count, data.lock_timeout).await?;
+
+    match manager.delete_account(&trader) {
+        Ok(account) => {
+            *count -= 1;
+            Ok(web::Json(account))
+        }
+        Err(OrderBookError::NoSuchAccount) => {
+            let response = ErrNoAccount {
+                address: traderAddress.clone(),
+                err: String::from("Account not found"),
+            };
+            Err(response.into())
+        }
+        Err(e @ OrderBookError::Balance(_)) => Err(ErrorUnprocessableEntity(e.to_string())),
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
     }
 }
 
+#[derive(Debug, Serialize)]
+struct TransferResponse {
+    from: JsonAccount,
+    to: JsonAccount,
+}
+
+/// Move available funds from one account to another.
+#[post("/transfers")]
+async fn new_transfer(
+    req: web::Json<JsonTransfer>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let amount = normalize_decimal(&req.amount, '.')
+        .and_then(|amount| Ok(decimal_to_u256(&amount)?))
+        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    match manager.transfer(&req.from, &req.to, req.asset, amount) {
+        Ok(()) => {
+            let json = |address| {
+                manager
+                    .get_json_account(address)
+                    .map_err(|e| ErrorInternalServerError(e.to_string()))
+            };
+            Ok(web::Json(TransferResponse {
+                from: json(&req.from)?,
+                to: json(&req.to)?,
+            }))
+        }
+        Err(e @ OrderBookError::Balance(_)) => Err(ErrorUnprocessableEntity(e.to_string())),
+        Err(_e) => {
+            let response = ErrNoAccount {
+                address: format!("{:?}", req.from),
+                err: String::from("Account not found or account balance is not enough!"),
+            };
+            Err(response.into())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BalanceChange {
+    asset: Asset,
+    amount: Decimal,
+}
+
+/// Credit funds to an account.
+#[post("/accounts/{traderAddress}/deposit")]
+async fn deposit(
+    address: web::Path<Address>,
+    req: web::Json<BalanceChange>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let amount = normalize_decimal(&req.amount, '.')
+        .and_then(|amount| Ok(decimal_to_u256(&amount)?))
+        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    match manager.deposit(&address, req.asset, amount) {
+        Ok(()) => manager
+            .get_json_account(&address)
+            .map(web::Json)
+            .map_err(|e| ErrorInternalServerError(e.to_string())),
+        Err(OrderBookError::NoSuchAccount) => {
+            let response = ErrNoAccount {
+                address: format!("{:?}", address),
+                err: String::from("Account not found"),
+            };
+            Err(response.into())
+        }
+        Err(e @ OrderBookError::Balance(_)) => Err(ErrorUnprocessableEntity(e.to_string())),
+        Err(e) => Err(ErrorBadRequest(e.to_string())),
+    }
+}
+
+/// Debit available funds from an account.
+#[post("/accounts/{traderAddress}/withdraw")]
+async fn withdraw(
+    address: web::Path<Address>,
+    req: web::Json<BalanceChange>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let amount = normalize_decimal(&req.amount, '.')
+        .and_then(|amount| Ok(decimal_to_u256(&amount)?))
+        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    match manager.withdraw(&address, req.asset, amount) {
+        Ok(()) => manager
+            .get_json_account(&address)
+            .map(web::Json)
+            .map_err(|e| ErrorInternalServerError(e.to_string())),
+        Err(OrderBookError::NoSuchAccount) => {
+            let response = ErrNoAccount {
+                address: format!("{:?}", address),
+                err: String::from("Account not found"),
+            };
+            Err(response.into())
+        }
+        Err(e) => Err(ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountsQuery {
+    asset: Asset,
+    min: Decimal,
+    symbol: Option<String>,
+}
+
+/// Get the accounts holding more than `min` of an asset, largest first.
+#[get("/admin/accounts")]
+async fn get_accounts_above(
+    req: HttpRequest,
+    query: web::Query<AccountsQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    data.authorize_admin(&req)?;
+    let market = data.market(query.symbol.as_deref())?;
+    let min = normalize_decimal(&query.min, '.')
+        .and_then(|min| Ok(decimal_to_u256(&min)?))
+        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    let manager = acquire(&market.manager, data.lock_timeout).await?;
+    manager
+        .accounts_above(query.asset, min)
+        .map(web::Json)
+        .map_err(|e| ErrorInternalServerError(e.to_string()))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewOrderResponse {
+    fills: Vec<JsonFill>,
+    total_filled: Decimal,
+    total_notional: Decimal,
+    average_price: Decimal,
+    // funds held for the order when it was accepted.
+    reserved_asset: Option<Asset>,
+    reserved_amount: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolQuery {
+    symbol: Option<String>,
+}
+
 /// Add an order to the order book (possibly matching other orders).
 #[post("/orders")]
 async fn new_order(
     req: web::Json<JsonOrder>,
+    query: web::Query<SymbolQuery>,
     data: web::Data<AppState>,
-) -> Result<impl Responder, ErrNoAccount> {
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
     let order = JsonOrder {
         amount: req.amount.clone(),
         nonce: req.nonce.clone(),
         price: req.price.clone(),
         side: req.side.clone(),
         traderAddress: req.traderAddress.clone(),
+        postOnly: req.postOnly,
+        reduceOnly: req.reduceOnly,
+        signature: req.signature.clone(),
+        expiresAt: req.expiresAt,
+        displayAmount: req.displayAmount.clone(),
     };
-    let mut manager = data.manager.lock().unwrap();
-    let mut order_book = data.order_book.lock().unwrap();
-    if let Some(fill_result) = order_book.add_order(&mut manager, order.clone()) {
-        // generate json response.
-        let json_res = fill_result.generate_filled_orders();
-        // update accounts based the filled results.
-        manager.update_accounts(fill_result);
-        Ok(web::Json(json_res))
-    } else {
-        let response = ErrNoAccount {
-            address: order.get_trader(),
-            err: String::from("Account not found or account balance is not enough!"),
-        };
-        Err(response)
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    let mut order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    match order_book.apply(&mut manager, Action::Add(order.clone())) {
+        Ok(ActionOutcome::Order(fill_result)) => {
+            // generate json response.
+            let fills = fill_result.generate_filled_orders();
+            let total_filled = fill_result.total_filled();
+            let total_notional = fill_result.total_notional();
+            let average_price = fill_result.average_price();
+            let reserved = fill_result.reserved;
+            // update accounts based the filled results.
+            manager
+                .update_accounts(fill_result)
+                .map_err(|e| ErrorInternalServerError(OrderBookError::from(e).to_string()))?;
+            market.publish_book(&order_book);
+            let overflow =
+                |e: BalanceError| ErrorInternalServerError(OrderBookError::from(e).to_string());
+            Ok(web::Json(NewOrderResponse {
+                fills,
+                total_filled,
+                total_notional: total_notional.map_err(overflow)?,
+                average_price: average_price.map_err(overflow)?,
+                reserved_asset: reserved.map(|(asset, _)| asset),
+                reserved_amount: reserved.map(|(_, amount)| u256_to_decimal(&amount)),
+            }))
+        }
+        Ok(_) => unreachable!("submitting an order only fills"),
+        Err(OrderBookError::ReadOnly) => Err(ErrReadOnly::new().into()),
+        Err(
+            e @ (OrderBookError::InvalidDecimal(_)
+            | OrderBookError::Eip712Encoding(_)
+            | OrderBookError::Rejected(_)
+            | OrderBookError::WouldCross
+            | OrderBookError::NonceUsed
+            | OrderBookError::InvalidSignature(_)
+            | OrderBookError::InvalidOrder
+            | OrderBookError::InsufficientLiquidity
+            | OrderBookError::ProtectedOpen
+            | OrderBookError::ReduceOnly),
+        ) => Err(ErrorBadRequest(e.to_string())),
+        Err(e @ OrderBookError::DuplicateOrder) => Err(ErrorConflict(e.to_string())),
+        Err(OrderBookError::InsufficientFunds) => {
+            let response = ErrNoAccount {
+                address: order.get_trader(),
+                err: String::from("Account not found or account balance is not enough!"),
+            };
+            Err(response.into())
+        }
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
     }
 }
 
@@ -175,71 +710,1065 @@ async fn new_order(
 #[get("/orders/{hash}")]
 async fn get_order(
     hash: web::Path<Hash>,
+    query: web::Query<SymbolQuery>,
     data: web::Data<AppState>,
-) -> Result<impl Responder, ErrNoOrder> {
+) -> Result<impl Responder, Error> {
     let order_hash = hash.clone();
-    let order_book = data.order_book.lock().unwrap();
-    match order_book.get_order(order_hash.clone()) {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    match order_book.get_order_status(order_hash.clone()) {
         Ok(order) => Ok(web::Json(order)),
-        Err(_e) => {
+        Err(OrderBookError::NoSuchOrder) => {
             let response = ErrNoOrder {
                 hash: order_hash,
                 err: String::from("Order not found!"),
             };
-            Err(response)
+            Err(response.into())
         }
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
     }
 }
 
-/// Cancel an order info with its EIP-712 hash.
+#[derive(Debug, Deserialize)]
+struct CancelQuery {
+    nonce: Option<Hash>,
+    signature: Option<String>,
+    symbol: Option<String>,
+}
+
+/// Cancel an order info with its EIP-712 hash. The owner must have signed the cancel, as for a
+/// bulk cancel.
 #[delete("/orders/{hash}")]
 async fn cancel_order(
     hash: web::Path<Hash>,
+    query: web::Query<CancelQuery>,
     data: web::Data<AppState>,
-) -> Result<impl Responder, ErrNoOrder> {
+) -> Result<impl Responder, Error> {
     let order_hash = hash.clone();
-    let mut order_book = data.order_book.lock().unwrap();
-    let mut manager = data.manager.lock().unwrap();
-    match order_book.cancel_order(&mut manager, order_hash.clone()) {
-        Ok(order) => Ok(web::Json(order)),
-        Err(_e) => {
+    let market = data.market(query.symbol.as_deref())?;
+    let action = match (&query.nonce, &query.signature) {
+        (Some(nonce), Some(signature)) => Action::CancelSigned(JsonCancel {
+            orderHash: order_hash.clone(),
+            nonce: nonce.clone(),
+            signature: signature.clone(),
+        }),
+        _ => {
+            let e = OrderBookError::InvalidSignature(String::from("missing"));
+            return Err(ErrorBadRequest(e.to_string()));
+        }
+    };
+    let mut order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let mut manager = acquire(&market.manager, data.lock_timeout).await?;
+    match order_book.apply(&mut manager, action) {
+        Ok(ActionOutcome::Cancelled(order)) => {
+            market.publish_book(&order_book);
+            Ok(web::Json(order))
+        }
+        Ok(_) => unreachable!("a cancel cancels a single order"),
+        Err(OrderBookError::ReadOnly) => Err(ErrReadOnly::new().into()),
+        Err(e @ (OrderBookError::InvalidSignature(_) | OrderBookError::Eip712Encoding(_))) => {
+            Err(ErrorBadRequest(e.to_string()))
+        }
+        Err(OrderBookError::NoSuchOrder) => {
             let response = ErrNoOrder {
                 hash: order_hash,
                 err: String::from("Order not found"),
             };
-            Err(response)
+            Err(response.into())
         }
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
     }
 }
 
-/// Get L2 order book.
+#[derive(Debug, Deserialize)]
+struct L2Query {
+    depth: Option<usize>,
+}
+
+/// Get L2 order book of the default market.
 #[get("/book")]
-async fn get_book(data: web::Data<AppState>) -> impl Responder {
-    let order_book = data.order_book.lock().unwrap();
-    let l2_book = order_book.generate_l2_order_book();
-    web::Json(l2_book)
+async fn get_book(
+    query: web::Query<L2Query>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    l2_book(data.default_market(), query.depth, data.lock_timeout).await
+}
+
+/// Get L2 order book of a market.
+#[get("/book/{symbol}")]
+async fn get_symbol_book(
+    symbol: web::Path<String>,
+    query: web::Query<L2Query>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    l2_book(data.market(Some(&symbol))?, query.depth, data.lock_timeout).await
+}
+
+async fn l2_book(
+    market: &Market,
+    depth: Option<usize>,
+    lock_timeout: Duration,
+) -> Result<impl Responder, Error> {
+    let order_book = acquire(&market.order_book, lock_timeout).await?;
+    let l2_book = match depth {
+        Some(depth) => order_book.generate_l2_order_book_with_depth(depth),
+        None => order_book.generate_l2_order_book(),
+    };
+    Ok(web::Json(l2_book)
+        .customize()
+        .insert_header((EPOCH_HEADER, order_book.epoch().to_string())))
+}
+
+/// Stream the L2 book over a WebSocket: a snapshot on connect, then the changed levels after
+/// every order, cancel or fill.
+#[get("/ws/book")]
+async fn book_feed(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let (snapshot, mut updates) = {
+        let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+        let l2_book = order_book.generate_l2_order_book();
+        let snapshot = serde_json::json!({
+            "type": "snapshot",
+            "epoch": order_book.epoch(),
+            "book": l2_book,
+        });
+        // updates are published with the book held, so none is missed or repeated.
+        *market.feed_book.lock().unwrap() = l2_book;
+        (snapshot.to_string(), market.book_feed.subscribe())
+    };
+    let mut control = session.clone();
+    rt::spawn(async move {
+        while let Some(Ok(message)) = messages.recv().await {
+            match message {
+                // fails only once the session is closed, which ends the stream too.
+                Message::Ping(bytes) => {
+                    let _ = control.pong(&bytes).await;
+                }
+                Message::Close(reason) => {
+                    let _ = control.close(reason).await;
+                    return;
+                }
+                _ => {}
+            }
+        }
+    });
+    rt::spawn(async move {
+        if session.text(snapshot).await.is_err() {
+            return;
+        }
+        loop {
+            match updates.recv().await {
+                Ok(update) => {
+                    if session.text(update).await.is_err() {
+                        return;
+                    }
+                }
+                // missed updates, the client has to resync from a new snapshot.
+                Err(RecvError::Lagged(_)) => {
+                    let _ = session.close(None).await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+    Ok(response)
+}
+
+#[derive(Debug, Serialize)]
+struct Bbo {
+    bid: Option<SimpleOrder>,
+    ask: Option<SimpleOrder>,
+}
+
+/// Get the best bid and offer.
+#[get("/bbo")]
+async fn get_bbo(
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(Bbo {
+        bid: order_book.best_bid(),
+        ask: order_book.best_ask(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    #[serde(flatten)]
+    bbo: Bbo,
+    spread: Option<Decimal>,
+    mid: Option<Decimal>,
+}
+
+/// Get the best bid and offer with the spread and mid price between them.
+#[get("/stats")]
+async fn get_stats(
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(Stats {
+        bbo: Bbo {
+            bid: order_book.best_bid(),
+            ask: order_book.best_ask(),
+        },
+        spread: order_book.spread(),
+        mid: order_book.mid_price(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct L3Query {
+    depth: Option<usize>,
+    symbol: Option<String>,
+}
+
+/// Get a snapshot of the resting orders, best priced first.
+#[get("/book/l3")]
+async fn get_l3_book(
+    query: web::Query<L3Query>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let l3_book = order_book.generate_l3_order_book(query.depth.unwrap_or(L3_DEFAULT_DEPTH));
+    Ok(web::Json(l3_book)
+        .customize()
+        .insert_header((EPOCH_HEADER, order_book.epoch().to_string())))
+}
+
+#[derive(Debug, Deserialize)]
+struct CostToMoveQuery {
+    side: Side,
+    ticks: u64,
+    #[serde(default)]
+    denom: Denomination,
+    symbol: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CostToMove {
+    side: Side,
+    ticks: u64,
+    denom: Denomination,
+    notional: Option<Decimal>,
+}
+
+/// Get the notional required to move the market by N ticks.
+#[get("/book/cost-to-move")]
+async fn get_cost_to_move(
+    query: web::Query<CostToMoveQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let notional = order_book.cost_to_move(query.side.clone(), query.ticks, query.denom);
+    Ok(web::Json(CostToMove {
+        side: query.side.clone(),
+        ticks: query.ticks,
+        denom: query.denom,
+        notional,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct LevelQuery {
+    side: Side,
+    price: Decimal,
+    symbol: Option<String>,
+}
+
+/// Get the resting orders at one price level in queue order.
+#[get("/book/level")]
+async fn get_level(
+    query: web::Query<LevelQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let orders = order_book.level_detail(query.side.clone(), &query.price);
+    Ok(web::Json(orders.unwrap_or_default()))
+}
+
+/// Get the resting order an incoming order at the given side and price would match first.
+#[get("/book/next-match")]
+async fn get_next_match(
+    query: web::Query<LevelQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(
+        order_book.next_match(query.side.clone(), &query.price),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthBucketsQuery {
+    side: Side,
+    bucket: Decimal,
+    count: usize,
+    symbol: Option<String>,
+}
+
+/// Get the resting volume of one side grouped into fixed price buckets.
+#[get("/book/depth-buckets")]
+async fn get_depth_buckets(
+    query: web::Query<DepthBucketsQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let buckets = order_book.depth_buckets(query.side.clone(), &query.bucket, query.count);
+    Ok(web::Json(buckets))
+}
+
+#[derive(Debug, Deserialize)]
+struct LevelsQuery {
+    side: Side,
+    symbol: Option<String>,
+}
+
+/// Get every price level of one side with its order count and volume.
+#[get("/book/levels")]
+async fn get_levels(
+    query: web::Query<LevelsQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(
+        order_book.price_level_summary(query.side.clone()),
+    ))
+}
+
+#[derive(Debug, Serialize)]
+struct IntegrityReport {
+    violations: Vec<IntegrityViolation>,
+}
+
+/// Run consistency checks of the book and the account holds.
+#[get("/admin/integrity")]
+async fn get_integrity(
+    req: HttpRequest,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    data.authorize_admin(&req)?;
+    let market = data.market(query.symbol.as_deref())?;
+    let manager = acquire(&market.manager, data.lock_timeout).await?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let violations = order_book.check_integrity(&manager);
+    Ok(web::Json(IntegrityReport { violations }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OrderOwner {
+    trader_address: Address,
+}
+
+/// Get the accounts and the books of every market, to be loaded on a restart.
+#[get("/admin/snapshot")]
+async fn get_snapshot(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    data.authorize_admin(&req)?;
+    Ok(web::Json(data.snapshot().await?))
+}
+
+/// Get the trader of a resting order by its EIP-712 hash.
+#[get("/admin/orders/{hash}/owner")]
+async fn get_order_owner(
+    req: HttpRequest,
+    hash: web::Path<Hash>,
+    query: web::Query<SymbolQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    data.authorize_admin(&req)?;
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    match order_book.order_owner(&hash) {
+        Some(trader_address) => Ok(web::Json(OrderOwner { trader_address })),
+        None => Err(ErrNoOrder {
+            hash: hash.clone(),
+            err: String::from("Order not found!"),
+        }
+        .into()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TradesQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    limit: Option<usize>,
+    symbol: Option<String>,
+}
+
+/// Get the latest fills executed in a time range, in unix milliseconds, oldest first.
+#[get("/trades")]
+async fn get_trades(
+    query: web::Query<TradesQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let trades = order_book.trade_history().query(
+        query.from.unwrap_or(0),
+        query.to.unwrap_or(u64::MAX),
+        query.limit.unwrap_or(MAX_TRADES).min(MAX_TRADES),
+    );
+    Ok(web::Json(trades)
+        .customize()
+        .insert_header((EPOCH_HEADER, order_book.epoch().to_string())))
+}
+
+#[derive(Debug, Deserialize)]
+struct CandlesQuery {
+    interval: String,
+    from: Option<u64>,
+    to: Option<u64>,
+    symbol: Option<String>,
+}
+
+/// Get OHLCV candles of `interval`, e.g. "1m" or "5m", over the fills in a time range.
+#[get("/candles")]
+async fn get_candles(
+    query: web::Query<CandlesQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let interval = parse_interval(&query.interval).map_err(ErrorBadRequest)?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    let candles = order_book.candles(
+        interval,
+        query.from.unwrap_or(0),
+        query.to.unwrap_or(u64::MAX),
+    );
+    Ok(web::Json(candles))
+}
+
+#[derive(Debug, Deserialize)]
+struct EffectiveSpreadQuery {
+    window: u64,
+    symbol: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EffectiveSpread {
+    window: u64,
+    spread: Option<Decimal>,
+}
+
+/// Get the mean effective spread paid by takers over the latest `window` milliseconds of fills.
+#[get("/stats/effective-spread")]
+async fn get_effective_spread(
+    query: web::Query<EffectiveSpreadQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    Ok(web::Json(EffectiveSpread {
+        window: query.window,
+        spread: order_book.effective_spread(query.window),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct BootstrapQuery {
+    traderAddress: Address,
+    symbol: Option<String>,
+}
+
+/// Get the book, the latest trades and the trader's account in one consistent snapshot.
+#[get("/bootstrap")]
+async fn get_bootstrap(
+    query: web::Query<BootstrapQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, Error> {
+    let market = data.market(query.symbol.as_deref())?;
+    // both locks are held, so no order can land between the book and the account snapshots.
+    let manager = acquire(&market.manager, data.lock_timeout).await?;
+    let order_book = acquire(&market.order_book, data.lock_timeout).await?;
+    order_book
+        .bootstrap(&manager, &query.traderAddress, BOOTSTRAP_TRADES)
+        .map(web::Json)
+        .map_err(|e| ErrorInternalServerError(e.to_string()))
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(new_account)
+        .service(get_account)
+        .service(get_account_orders)
+        .service(cancel_account_orders)
+        .service(delete_account)
+        .service(new_transfer)
+        .service(deposit)
+        .service(withdraw)
+        .service(get_accounts_above)
+        .service(new_order)
+        .service(get_order)
+        .service(cancel_order)
+        .service(get_book)
+        .service(book_feed)
+        .service(get_bbo)
+        .service(get_stats)
+        .service(get_l3_book)
+        .service(get_cost_to_move)
+        .service(get_level)
+        .service(get_next_match)
+        .service(get_depth_buckets)
+        .service(get_levels)
+        .service(get_integrity)
+        .service(get_order_owner)
+        .service(get_snapshot)
+        .service(get_trades)
+        .service(get_candles)
+        .service(get_effective_spread)
+        .service(get_bootstrap)
+        // after the other `/book/...` routes, which take precedence over symbols.
+        .service(get_symbol_book);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let app_state = web::Data::new(AppState {
-        manager: Mutex::new(AccountManager::new()),
-        order_book: Mutex::new(OrderBook::new("DDX".to_string())),
-        user_count: Mutex::new(0),
-    });
+    let symbols = std::env::var("SYMBOLS").unwrap_or_else(|_| DEFAULT_SYMBOLS.to_string());
+    let symbols: Vec<&str> = symbols.split(',').map(str::trim).collect();
+    let mut app_state = AppState::with_symbols(LOCK_TIMEOUT, &symbols);
+    app_state.admin_token = std::env::var(ADMIN_TOKEN_VAR).ok();
+    let app_state = web::Data::new(app_state);
+    if let Ok(path) = std::env::var("SNAPSHOT") {
+        let snapshot: ServiceSnapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+        app_state
+            .restore(&snapshot)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+
+    HttpServer::new(move || App::new().app_data(app_state.clone()).configure(routes))
+        .bind(("127.0.0.1", 4321))?
+        .run()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+    use actix_web::test;
+
+    #[actix_web::test]
+    async fn busy_lock_returns_503() {
+        let app_state = web::Data::new(AppState::with_symbols(Duration::from_millis(20), &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        // hold the book lock as a long running request would do.
+        let _guard = app_state.default_market().order_book.lock().await;
+        let req = test::TestRequest::get().uri("/book").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn lock_wait_yields_to_other_tasks() {
+        let app_state = web::Data::new(AppState::with_symbols(Duration::from_secs(1), &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        // another task of the same worker holds the book lock for a while.
+        let holder = app_state.clone();
+        rt::spawn(async move {
+            let _guard = holder.default_market().order_book.lock().await;
+            rt::time::sleep(Duration::from_millis(20)).await;
+        });
+        rt::task::yield_now().await;
+        assert!(app_state.default_market().order_book.try_lock().is_err());
+        // the request waits for it without blocking the worker, so the holder gets to finish.
+        let req = test::TestRequest::get().uri("/book").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn malformed_amount_returns_400() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let order = serde_json::json!({
+            "amount": format!("1{}", "0".repeat(60)),
+            "nonce": "0x01",
+            "price": "10.0",
+            "side": "Bid",
+            "traderAddress": "0xb794f5ea0ba39494ce839613fffba74279579268",
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(order)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .service(new_account)
-            .service(get_account)
-            .service(delete_account)
-            .service(new_order)
-            .service(get_order)
-            .service(cancel_order)
-            .service(get_book)
-    })
-    .bind(("127.0.0.1", 4321))?
-    .run()
-    .await
+    #[actix_web::test]
+    async fn snapshots_carry_the_book_epoch() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let epoch = app_state
+            .default_market()
+            .order_book
+            .lock()
+            .await
+            .epoch()
+            .to_string();
+        for uri in ["/book", "/book/l3", "/trades"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(EPOCH_HEADER).unwrap().to_str().unwrap(),
+                epoch
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn missing_order_returns_404() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let uri = format!("/orders/0x{}", "00".repeat(32));
+        let signed = format!("{}?nonce=0x01&signature=0x{}", uri, "00".repeat(65));
+        for req in [
+            test::TestRequest::get().uri(&uri).to_request(),
+            test::TestRequest::delete().uri(&signed).to_request(),
+        ] {
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[actix_web::test]
+    async fn unsigned_bulk_cancel_returns_400() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let trader = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let account = serde_json::json!({
+            "ddxBalance": "0.0",
+            "usdBalance": "100.0",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/accounts")
+            .set_json(account)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let order = serde_json::json!({
+            "amount": "1.0",
+            "nonce": "0x01",
+            "price": "10.0",
+            "side": "Bid",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let uri = format!("/accounts/{}/orders", trader);
+        for uri in [
+            uri.clone(),
+            format!("{}?nonce=0x02&signature=0x{}", uri, "00".repeat(65)),
+        ] {
+            let req = test::TestRequest::delete().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let order_book = app_state.default_market().order_book.lock().await;
+        assert_eq!(order_book.open_order_count(&trader.parse().unwrap()), 1);
+    }
+
+    #[actix_web::test]
+    async fn book_feed_streams_snapshot_and_updates() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let trader = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let account = serde_json::json!({
+            "ddxBalance": "0.0",
+            "usdBalance": "100.0",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/accounts")
+            .set_json(account)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::get()
+            .uri("/ws/book")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+        let mut frames = FrameReader {
+            body: resp.into_body(),
+            buf: Vec::new(),
+        };
+        let snapshot = frames.next_json().await;
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["book"]["bids"], serde_json::json!([]));
+        assert_eq!(snapshot["book"]["asks"], serde_json::json!([]));
+        let order = serde_json::json!({
+            "amount": "2.0",
+            "nonce": "0x01",
+            "price": "10.0",
+            "side": "Bid",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let update = frames.next_json().await;
+        assert_eq!(update["type"], "update");
+        assert_eq!(update["book"]["bids"][0]["price"], "10.00");
+        assert_eq!(update["book"]["bids"][0]["amount"], "2.00");
+        assert_eq!(update["book"]["asks"], serde_json::json!([]));
+    }
+
+    #[actix_web::test]
+    async fn markets_are_independent() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX", "ETH"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let alice = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let bob = "0x3a880652f47bfaa771908c07dd8673a787daed3a";
+        for (symbol, trader, base, usd) in
+            [("DDX", alice, "0.0", "100.0"), ("ETH", bob, "10.0", "0.0")]
+        {
+            let account = serde_json::json!({
+                "ddxBalance": base,
+                "usdBalance": usd,
+                "traderAddress": trader,
+            });
+            let req = test::TestRequest::post()
+                .uri(&format!("/accounts?symbol={}", symbol))
+                .set_json(account)
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        // the ask would cross the bid if both were in the same book.
+        for (symbol, side, trader, price, nonce) in [
+            ("DDX", "Bid", alice, "10.0", "0x01"),
+            ("ETH", "Ask", bob, "9.0", "0x02"),
+        ] {
+            let order = serde_json::json!({
+                "amount": "1.0",
+                "nonce": nonce,
+                "price": price,
+                "side": side,
+                "traderAddress": trader,
+            });
+            let req = test::TestRequest::post()
+                .uri(&format!("/orders?symbol={}", symbol))
+                .set_json(order)
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["fills"], serde_json::json!([]));
+        }
+        let book = |symbol: &str| test::TestRequest::get().uri(&format!("/book/{}", symbol));
+        let ddx_book: serde_json::Value =
+            test::call_and_read_body_json(&app, book("DDX").to_request()).await;
+        assert_eq!(ddx_book["bids"][0]["price"], "10.00");
+        assert_eq!(ddx_book["asks"], serde_json::json!([]));
+        let eth_book: serde_json::Value =
+            test::call_and_read_body_json(&app, book("ETH").to_request()).await;
+        assert_eq!(eth_book["asks"][0]["price"], "9.00");
+        assert_eq!(eth_book["bids"], serde_json::json!([]));
+        // the default market is the first one.
+        let req = test::TestRequest::get().uri("/book").to_request();
+        let default_book: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(default_book, ddx_book);
+        let resp = test::call_service(&app, book("BTC").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        // bob's base balance is in ETH, so it cannot back a DDX ask.
+        let req = test::TestRequest::get()
+            .uri(&format!("/accounts/{}?symbol=ETH", bob))
+            .to_request();
+        let account: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(account["balances"]["ETH"], "10.00");
+        assert_eq!(account["openOrders"], 1);
+        let req = test::TestRequest::post()
+            .uri("/orders?symbol=DDX")
+            .set_json(serde_json::json!({
+                "amount": "1.0",
+                "nonce": "0x04",
+                "price": "10.0",
+                "side": "Ask",
+                "traderAddress": bob,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let req = test::TestRequest::post()
+            .uri("/orders?symbol=BTC")
+            .set_json(serde_json::json!({
+                "amount": "1.0",
+                "nonce": "0x03",
+                "price": "10.0",
+                "side": "Bid",
+                "traderAddress": alice,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn snapshot_is_restored() {
+        let mut app_state = AppState::with_symbols(LOCK_TIMEOUT, &["DDX", "ETH"]);
+        app_state.admin_token = Some("secret".to_string());
+        let app_state = web::Data::new(app_state);
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let alice = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let account = serde_json::json!({
+            "ddxBalance": "0.0",
+            "usdBalance": "100.0",
+            "traderAddress": alice,
+        });
+        let req = test::TestRequest::post()
+            .uri("/accounts?symbol=ETH")
+            .set_json(account)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let order = serde_json::json!({
+            "amount": "1.0",
+            "nonce": "0x01",
+            "price": "10.0",
+            "side": "Bid",
+            "traderAddress": alice,
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders?symbol=ETH")
+            .set_json(order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::get()
+            .uri("/admin/snapshot")
+            .insert_header((AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let snapshot: ServiceSnapshot = test::call_and_read_body_json(&app, req).await;
+
+        // a restarted service picks up where the snapshot was taken.
+        let restarted = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX", "ETH"]));
+        restarted.restore(&snapshot).await.unwrap();
+        let restarted_app =
+            test::init_service(App::new().app_data(restarted.clone()).configure(routes)).await;
+        for uri in [
+            "/book/ETH".to_string(),
+            format!("/accounts/{}?symbol=ETH", alice),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let expected: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let actual: serde_json::Value =
+                test::call_and_read_body_json(&restarted_app, req).await;
+            assert_eq!(actual, expected);
+        }
+        assert_eq!(*restarted.user_count.lock().await, 1);
+        // markets not traded by the service are rejected.
+        let other = AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]);
+        assert!(matches!(
+            other.restore(&snapshot).await,
+            Err(OrderBookError::InvalidSnapshot(_))
+        ));
+    }
+
+    // Reads the unmasked text frames a server sends from a WebSocket response body.
+    struct FrameReader {
+        body: BoxBody,
+        buf: Vec<u8>,
+    }
+
+    impl FrameReader {
+        async fn next_json(&mut self) -> serde_json::Value {
+            loop {
+                if let Some((start, len)) = self.frame_len() {
+                    if self.buf.len() >= start + len {
+                        // a final text frame.
+                        assert_eq!(self.buf[0], 0x81);
+                        let frame: Vec<u8> = self.buf.drain(..start + len).collect();
+                        return serde_json::from_slice(&frame[start..]).unwrap();
+                    }
+                }
+                let body = &mut self.body;
+                let chunk = std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                self.buf.extend_from_slice(&chunk);
+            }
+        }
+
+        // Header and payload length of the first buffered frame, once its header is complete.
+        fn frame_len(&self) -> Option<(usize, usize)> {
+            match *self.buf.get(1)? {
+                126 => {
+                    let len = self.buf.get(2..4)?;
+                    Some((4, u16::from_be_bytes([len[0], len[1]]) as usize))
+                }
+                127 => {
+                    let len = self.buf.get(2..10)?;
+                    Some((10, u64::from_be_bytes(len.try_into().unwrap()) as usize))
+                }
+                len => Some((2, len as usize)),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn balance_change_errors() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let trader = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let account = serde_json::json!({
+            "ddxBalance": "0.0",
+            "usdBalance": "100.0",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/accounts")
+            .set_json(account)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let change = |trader: &str, action: &str, amount: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/accounts/{}/{}", trader, action))
+                .set_json(serde_json::json!({ "asset": "USD", "amount": amount }))
+                .to_request()
+        };
+        let stranger = "0x0000000000000000000000000000000000000001";
+        for action in ["deposit", "withdraw"] {
+            let resp = test::call_service(&app, change(stranger, action, "1.0")).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+        let resp = test::call_service(&app, change(trader, "withdraw", "200.0")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn unsigned_cancel_returns_400() {
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let trader = "0xb794f5ea0ba39494ce839613fffba74279579268";
+        let account = serde_json::json!({
+            "ddxBalance": "0.0",
+            "usdBalance": "100.0",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/accounts")
+            .set_json(account)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let order = serde_json::json!({
+            "amount": "1.0",
+            "nonce": "0x01",
+            "price": "10.0",
+            "side": "Bid",
+            "traderAddress": trader,
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let hash = {
+            let order_book = app_state.default_market().order_book.lock().await;
+            order_book.get_orders_by_trader(&trader.parse().unwrap())[0]
+                .encode_order()
+                .unwrap()
+                .hash_hex_in(&order_book.config().domain)
+                .unwrap()
+        };
+        let uri = format!("/orders/{}", hash);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::delete().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let order_book = app_state.default_market().order_book.lock().await;
+        assert_eq!(order_book.open_order_count(&trader.parse().unwrap()), 1);
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_require_the_token() {
+        let uris = [
+            "/admin/accounts?asset=USD&min=0",
+            "/admin/integrity",
+            "/admin/snapshot",
+        ];
+        // without a configured token, the admin routes are disabled.
+        let app_state = web::Data::new(AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]));
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        for uri in uris {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        let mut app_state = AppState::with_symbols(LOCK_TIMEOUT, &["DDX"]);
+        app_state.admin_token = Some("secret".to_string());
+        let app_state = web::Data::new(app_state);
+        let app =
+            test::init_service(App::new().app_data(app_state.clone()).configure(routes)).await;
+        let owner = format!("/admin/orders/0x{}/owner", "00".repeat(32));
+        for uri in uris.into_iter().chain([owner.as_str()]) {
+            for header in [None, Some("Bearer wrong"), Some("secret")] {
+                let mut req = test::TestRequest::get().uri(uri);
+                if let Some(header) = header {
+                    req = req.insert_header((AUTHORIZATION, header));
+                }
+                let resp = test::call_service(&app, req.to_request()).await;
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            }
+        }
+        for uri in uris {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success(), "{}", uri);
+        }
+        let req = test::TestRequest::get()
+            .uri(&owner)
+            .insert_header((AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 }